@@ -1,7 +1,211 @@
-use crate::{error::NotedError, file_utils::FileData};
+use crate::{
+    clients::rate_limit::RequestPacer, error::NotedError, file_utils::FileData,
+    image_utils::ImageEncoding,
+};
 use async_trait::async_trait;
+use serde::Serialize;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Options shared by every client that shape the request sent to the model.
+#[derive(Debug, Default, Clone)]
+pub struct ClientOptions {
+    pub prompt: Option<String>,
+    pub prompt_append: Option<String>,
+    /// Description of the whole document, sent ahead of the transcription
+    /// prompt as its own text part.
+    pub document_context: Option<String>,
+    /// Sampling temperature, sent to every provider.
+    pub temperature: Option<f32>,
+    /// Sampling seed, sent to providers whose API accepts one.
+    pub seed: Option<u64>,
+    /// Ask the model to continue when its answer is cut off at the output
+    /// limit.
+    pub auto_continue: bool,
+    /// Compression of images re-encoded before they are sent.
+    pub image_encoding: ImageEncoding,
+    /// Instructions sent as the system prompt, apart from the user message.
+    pub system_prompt: Option<String>,
+    /// Sequences that end the answer where they appear.
+    pub stop_sequences: Vec<String>,
+    /// Shared `--rpm` budget that every HTTP request waits on, including
+    /// continuations and retries.
+    pub pacer: Option<Arc<RequestPacer>>,
+    /// Shown the pieces of a streamed answer as they arrive.
+    pub on_delta: Option<DeltaCallback>,
+}
+
+/// Called with each piece of a streamed answer as it arrives.
+#[derive(Clone)]
+pub struct DeltaCallback(Arc<dyn Fn(&str) + Send + Sync>);
+
+impl DeltaCallback {
+    pub fn new(callback: impl Fn(&str) + Send + Sync + 'static) -> Self {
+        Self(Arc::new(callback))
+    }
+
+    pub fn call(&self, delta: &str) {
+        (self.0)(delta)
+    }
+}
+
+impl fmt::Debug for DeltaCallback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("DeltaCallback")
+    }
+}
+
+impl ClientOptions {
+    /// Returns the custom prompt if one was given, otherwise `default_prompt`,
+    /// followed by the `prompt_append` text when present.
+    pub fn resolve_prompt(&self, default_prompt: &str) -> String {
+        let mut prompt = match &self.prompt {
+            Some(custom_prompt) => custom_prompt.clone(),
+            None => default_prompt.to_string(),
+        };
+        if let Some(extra) = &self.prompt_append {
+            prompt.push_str("\n\n");
+            prompt.push_str(extra);
+        }
+        prompt
+    }
+
+    /// Waits for a slot from the pacer, if there is one, before a request
+    /// is sent.
+    pub async fn pace(&self) {
+        if let Some(pacer) = &self.pacer {
+            pacer.wait().await;
+        }
+    }
+}
 
 #[async_trait]
-pub trait AiProvider {
+pub trait AiProvider: Send + Sync {
     async fn send_request(&self, file_data: FileData) -> Result<String, NotedError>;
+
+    /// The provider identifier used in the configuration (e.g. `"gemini"`).
+    fn name(&self) -> &'static str;
+
+    /// The model requests are sent to.
+    fn model(&self) -> &str;
+
+    /// Whether the provider can read a PDF sent as-is, without rendering its
+    /// pages to images first.
+    fn supports_pdf(&self) -> bool;
+
+    /// Whether the provider's API accepts a sampling seed.
+    fn supports_seed(&self) -> bool;
+
+    /// Whether the provider's API accepts stop sequences.
+    fn supports_stop_sequences(&self) -> bool;
+
+    /// How long to wait before the next request to stay within the rate limit
+    /// the provider reported on its last response, if the budget is used up.
+    fn pacing_delay(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Tokens the provider reported using since the last call, for providers
+    /// whose responses include usage.
+    fn take_usage(&self) -> Option<TokenUsage> {
+        None
+    }
+}
+
+/// Token counts reported by a provider.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct TokenUsage {
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+}
+
+impl std::ops::Add for TokenUsage {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            input_tokens: self.input_tokens + other.input_tokens,
+            output_tokens: self.output_tokens + other.output_tokens,
+        }
+    }
+}
+
+/// Adds up the usage of every response a client receives until it is taken.
+#[derive(Debug, Default)]
+pub struct UsageCounter(Mutex<Option<TokenUsage>>);
+
+impl UsageCounter {
+    pub fn record(&self, usage: TokenUsage) {
+        let mut total = self.0.lock().unwrap();
+        *total = Some(total.unwrap_or_default() + usage);
+    }
+
+    pub fn take(&self) -> Option<TokenUsage> {
+        self.0.lock().unwrap().take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn usage_counter_adds_up_until_taken() {
+        let counter = UsageCounter::default();
+        assert_eq!(counter.take(), None);
+        counter.record(TokenUsage {
+            input_tokens: 100,
+            output_tokens: 20,
+        });
+        counter.record(TokenUsage {
+            input_tokens: 50,
+            output_tokens: 5,
+        });
+        assert_eq!(
+            counter.take(),
+            Some(TokenUsage {
+                input_tokens: 150,
+                output_tokens: 25,
+            })
+        );
+        assert_eq!(counter.take(), None);
+    }
+
+    #[test]
+    fn resolve_prompt_uses_the_default_without_a_custom_prompt() {
+        let options = ClientOptions::default();
+        assert_eq!(options.resolve_prompt("Transcribe."), "Transcribe.");
+    }
+
+    #[test]
+    fn resolve_prompt_prefers_the_custom_prompt() {
+        let options = ClientOptions {
+            prompt: Some("Summarize.".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(options.resolve_prompt("Transcribe."), "Summarize.");
+    }
+
+    #[test]
+    fn resolve_prompt_appends_to_the_prompt_in_effect() {
+        let default = ClientOptions {
+            prompt_append: Some("Keep German umlauts.".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            default.resolve_prompt("Transcribe."),
+            "Transcribe.\n\nKeep German umlauts."
+        );
+
+        let custom = ClientOptions {
+            prompt: Some("Summarize.".to_string()),
+            prompt_append: Some("Use bullet points.".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            custom.resolve_prompt("Transcribe."),
+            "Summarize.\n\nUse bullet points."
+        );
+    }
 }
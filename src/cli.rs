@@ -1,4 +1,7 @@
-use clap::{Parser, Subcommand};
+use crate::clients::openai_client::ImageDetail;
+use crate::prompt_presets::PromptPreset;
+use clap::{Parser, Subcommand, ValueEnum};
+use std::path::PathBuf;
 
 #[derive(Parser, Debug)]
 #[command(
@@ -10,13 +13,32 @@ pub struct Cli {
     pub command: Commands,
 }
 
+// Parsed once per run, so the size of the `Convert` variant doesn't matter.
+#[allow(clippy::large_enum_variant)]
 #[derive(Subcommand, Debug)]
 pub enum Commands {
     /// Convert files to Markdown format
     Convert {
         /// Path to a file or directory to convert
-        #[arg(required = true)]
-        path: String,
+        #[arg(required_unless_present_any = ["list_supported", "list_presets", "input_list"])]
+        path: Option<String>,
+
+        /// Convert the files listed in a file
+        #[arg(
+            long,
+            value_name = "FILE",
+            conflicts_with_all = ["path", "file_type", "compare", "sample"],
+            help = "Convert the files listed in FILE, one path per line (relative to the current directory). Blank lines and lines starting with # are ignored"
+        )]
+        input_list: Option<String>,
+
+        /// Force the file type
+        #[arg(
+            long = "as",
+            value_name = "TYPE",
+            help = "Treat a single input file as this type (e.g. png or image/png) instead of detecting it from the extension"
+        )]
+        file_type: Option<String>,
 
         /// Output directory to save converted files
         #[arg(
@@ -34,9 +56,561 @@ pub enum Commands {
         #[arg(short, long, help = "Add a custom prompt to pass to the LLM")]
         prompt: Option<String>,
 
+        /// Extend the prompt
+        #[arg(
+            long,
+            help = "Append extra instructions to the prompt in effect (default or --prompt)"
+        )]
+        prompt_append: Option<String>,
+
+        /// Use a built-in prompt
+        #[arg(
+            long,
+            value_enum,
+            help = "Use a built-in prompt tuned for a kind of notes (ignored when --prompt is set)"
+        )]
+        prompt_preset: Option<PromptPreset>,
+
+        /// Describe the document
+        #[arg(
+            long,
+            help = "Describe the whole document (e.g. \"a physics problem set\"); sent with every page ahead of the prompt"
+        )]
+        document_context: Option<String>,
+
+        /// Summarize instead of transcribing
+        #[arg(
+            long,
+            conflicts_with_all = ["prompt", "prompt_preset", "compare", "tile"],
+            help = "Ask for a summary of each file instead of a full transcription and save it as <name>.summary.md"
+        )]
+        summary: bool,
+
+        /// Sampling temperature
+        #[arg(
+            long,
+            help = "Sampling temperature sent to the provider; lower values give more deterministic output"
+        )]
+        temperature: Option<f32>,
+
+        /// System prompt
+        #[arg(
+            long,
+            help = "Instructions sent as the system prompt, separate from the transcription prompt. Overrides system_prompt from [requests] in the config"
+        )]
+        system_prompt: Option<String>,
+
+        /// Stop sequence
+        #[arg(
+            long = "stop",
+            value_name = "SEQUENCE",
+            help = "End the answer where SEQUENCE appears. Can be given more than once. Overrides stop_sequences from [requests] in the config"
+        )]
+        stop_sequences: Vec<String>,
+
+        /// Sampling seed
+        #[arg(
+            long,
+            help = "Sampling seed for reproducible output (Gemini, Ollama and OpenAI chat completions)"
+        )]
+        seed: Option<u64>,
+
+        /// Continue truncated answers
+        #[arg(
+            long,
+            help = "When an answer is cut off at the model's output limit, ask it to continue and join the pieces"
+        )]
+        auto_continue: bool,
+
+        /// OpenAI image detail
+        #[arg(
+            long,
+            value_enum,
+            default_value_t = ImageDetail::Auto,
+            help = "Image detail level for OpenAI-compatible providers; 'high' reads dense handwriting better"
+        )]
+        image_detail: ImageDetail,
+
+        /// Per-run config file
+        #[arg(
+            long,
+            help = "Config file merged over the global config for this run only (not saved)"
+        )]
+        provider_config: Option<PathBuf>,
+
+        /// Ignore .notedmd.toml
+        #[arg(
+            long,
+            help = "Don't look for a .notedmd.toml in the current directory and its parents"
+        )]
+        no_project_config: bool,
+
+        /// Force a provider
+        #[arg(
+            long,
+            value_parser = ["gemini", "claude", "ollama", "openai"],
+            conflicts_with = "compare",
+            help = "Use this provider for every file of this run instead of the active provider and any [[routes]] in the config"
+        )]
+        provider: Option<String>,
+
+        /// Provider base URL
+        #[arg(
+            long,
+            value_name = "URL",
+            conflicts_with = "compare",
+            help = "Base URL of the provider used for this run (e.g. a regional endpoint or a proxy) instead of the configured or default one"
+        )]
+        provider_endpoint: Option<String>,
+
+        /// Compare two providers
+        #[arg(
+            long,
+            value_delimiter = ',',
+            value_parser = ["gemini", "claude", "ollama", "openai"],
+            conflicts_with_all = ["notion", "sample"],
+            help = "Convert a single file with two providers (e.g. gemini,claude), save both results and print a diff"
+        )]
+        compare: Vec<String>,
+
+        /// Ignore an interrupted run
+        #[arg(
+            long,
+            help = "Convert every file of a directory again instead of resuming an interrupted run"
+        )]
+        no_resume: bool,
+
+        /// Stop at the first failure
+        #[arg(
+            long,
+            help = "Stop a directory run at the first file that fails and exit with an error, instead of continuing with the rest"
+        )]
+        abort_on_error: bool,
+
+        /// Move converted sources
+        #[arg(
+            long,
+            conflicts_with = "sample",
+            help = "Move each successfully converted source file into this directory"
+        )]
+        move_done: Option<String>,
+
+        /// Move failed sources
+        #[arg(
+            long,
+            conflicts_with = "sample",
+            help = "Move each source file that failed to convert into this directory"
+        )]
+        move_failed: Option<String>,
+
+        /// Review results
+        #[arg(
+            long,
+            conflicts_with_all = ["compare", "sample", "output_stdout_json"],
+            help = "Show each result and choose to accept, retry, edit or skip it before it is saved (interactive terminals only)"
+        )]
+        review: bool,
+
+        /// Extract PDF images
+        #[arg(
+            long,
+            conflicts_with_all = ["compare", "sample", "output_stdout_json"],
+            help = "Save the images embedded in PDFs as PNG files in a <name>_assets folder and link them at the end of the markdown"
+        )]
+        extract_images: bool,
+
+        /// Add the PDF outline
+        #[arg(
+            long,
+            conflicts_with = "sample",
+            help = "Start the markdown of a PDF with a contents list built from its bookmarks, with page numbers"
+        )]
+        use_outline: bool,
+
+        /// Embed a source thumbnail
+        #[arg(
+            long,
+            conflicts_with = "sample",
+            help = "End the markdown of each image with an HTML comment holding a small thumbnail of the source as a base64 data URI"
+        )]
+        embed_source_thumbnail: bool,
+
+        /// Date subdirectories
+        #[arg(
+            long,
+            value_enum,
+            conflicts_with_all = ["append", "compare", "sample", "output_stdout_json"],
+            help = "Write each markdown file into YYYY/MM subdirectories of the output directory, dated by the source file's modification time or the time of the run"
+        )]
+        date_subdirs: Option<DateSubdirs>,
+
+        /// Append to a document
+        #[arg(
+            long,
+            conflicts_with_all = ["output", "compare", "sample"],
+            help = "Append the markdown to this document, after a '---' separator, instead of writing a file per input"
+        )]
+        append: Option<String>,
+
+        /// Heading for appended parts
+        #[arg(
+            long,
+            requires = "append",
+            help = "Heading added above each appended part. Supports {stem} and {name} placeholders"
+        )]
+        append_heading: Option<String>,
+
+        /// Print JSON results
+        #[arg(
+            long,
+            conflicts_with_all = ["output", "notion", "compare", "sample", "append"],
+            help = "Print one JSON object per file (markdown, source, provider, model, pages) to stdout instead of writing .md files"
+        )]
+        output_stdout_json: bool,
+
+        /// List prompt presets
+        #[arg(long, help = "Print the available prompt presets and exit")]
+        list_presets: bool,
+
         /// Notion Support
         #[arg(short, long, help = "Use Notion to store the generated output")]
         notion: bool,
+
+        /// Sample mode
+        #[arg(
+            long,
+            conflicts_with = "notion",
+            help = "Convert only the first page (or first file of a directory) and print it without saving"
+        )]
+        sample: bool,
+
+        /// Rate limit pacing
+        #[arg(
+            long,
+            help = "Wait for the provider's rate limit to reset instead of sending requests that would be rejected"
+        )]
+        pace: bool,
+
+        /// Requests per minute
+        #[arg(
+            long,
+            value_name = "N",
+            value_parser = clap::value_parser!(u32).range(1..),
+            help = "Send at most this many requests per minute, spread evenly (counts every request, including retries and continuations)"
+        )]
+        rpm: Option<u32>,
+
+        /// Output encoding
+        #[arg(
+            long,
+            value_enum,
+            default_value_t = OutputEncoding::Utf8,
+            help = "Encoding of the written markdown files (use utf-8-bom for Windows editors that expect a BOM)"
+        )]
+        output_encoding: OutputEncoding,
+
+        /// Line endings
+        #[arg(
+            long,
+            value_enum,
+            help = "Normalize line endings of the written markdown files"
+        )]
+        line_endings: Option<LineEnding>,
+
+        /// Sync output to disk
+        #[arg(
+            long,
+            help = "Flush each written file to disk before moving on (safer, but slower)"
+        )]
+        fsync: bool,
+
+        /// Back up overwritten output
+        #[arg(
+            long,
+            conflicts_with_all = ["output_stdout_json", "append"],
+            help = "Before overwriting an existing markdown file, move it to <file>.bak"
+        )]
+        backup: bool,
+
+        /// Number of backups to keep
+        #[arg(
+            long,
+            requires = "backup",
+            default_value_t = 1,
+            value_parser = clap::value_parser!(u32).range(1..),
+            help = "How many backups of each file --backup keeps (<file>.bak, <file>.bak.2, ...)"
+        )]
+        backup_keep: u32,
+
+        /// List supported file types
+        #[arg(long, help = "Print the supported file types and exit")]
+        list_supported: bool,
+
+        /// Deduplicate inputs
+        #[arg(
+            long,
+            help = "Reuse the result for files identical to one already converted in this run instead of sending them again"
+        )]
+        dedupe: bool,
+
+        /// Skip blank pages
+        #[arg(long, help = "Skip images that are blank instead of sending them")]
+        skip_blank_pages: bool,
+
+        /// Blank page threshold
+        #[arg(
+            long,
+            default_value_t = 3.0,
+            requires = "skip_blank_pages",
+            help = "Largest brightness standard deviation (0-255) for an image to count as blank"
+        )]
+        blank_threshold: f64,
+
+        /// Skip non-document images
+        #[arg(
+            long,
+            help = "Skip images that look like photos rather than pages of notes instead of sending them"
+        )]
+        skip_non_documents: bool,
+
+        /// Tile large images
+        #[arg(
+            long,
+            help = "Split images larger than --tile-size into overlapping tiles and send each one"
+        )]
+        tile: bool,
+
+        /// Tile size
+        #[arg(
+            long,
+            default_value_t = 2048,
+            requires = "tile",
+            help = "Width and height of each tile in pixels"
+        )]
+        tile_size: u32,
+
+        /// Tile overlap
+        #[arg(
+            long,
+            default_value_t = 128,
+            requires = "tile",
+            help = "Pixels shared by neighbouring tiles so no line is cut in half"
+        )]
+        tile_overlap: u32,
+
+        /// JPEG quality
+        #[arg(
+            long,
+            value_name = "1-100",
+            value_parser = clap::value_parser!(u8).range(1..=100),
+            help = "Quality of images notedmd re-encodes as JPEG (scaled down to fit Gemini's limit); default 85"
+        )]
+        jpeg_quality: Option<u8>,
+
+        /// PNG compression
+        #[arg(
+            long,
+            value_name = "0-9",
+            value_parser = clap::value_parser!(u8).range(0..=9),
+            help = "Compression level of images notedmd encodes as PNG (tiles, extracted PDF images); 0 is none, 9 is smallest"
+        )]
+        png_compression: Option<u8>,
+
+        /// Empty response policy
+        #[arg(
+            long,
+            value_enum,
+            default_value_t = OnEmpty::Fail,
+            help = "What to do when the provider returns no content: fail the file, skip it, or retry"
+        )]
+        on_empty: OnEmpty,
+
+        /// Cap the response size
+        #[arg(
+            long,
+            help = "Cut off responses longer than this many characters and flag them, to catch models stuck repeating themselves"
+        )]
+        max_output_chars: Option<usize>,
+
+        /// Explain provider errors
+        #[arg(
+            long,
+            help = "Add a hint on how to fix common provider errors (unknown model, context too long, unreadable image, quota) to the error message"
+        )]
+        explain_errors: bool,
+
+        /// Print undecodable responses
+        #[arg(
+            long,
+            help = "Print the raw body of a provider response that could not be decoded, e.g. an error page from a gateway"
+        )]
+        verbose: bool,
+
+        /// Print run statistics
+        #[arg(
+            long,
+            conflicts_with_all = ["sample", "output_stdout_json"],
+            help = "After the run, print the total time, time spent waiting for the provider vs. local processing, average request latency, retries, bytes uploaded and tokens used"
+        )]
+        stats: bool,
+
+        /// Keep whitespace as returned
+        #[arg(
+            long,
+            help = "Don't trim trailing whitespace or add a final newline to the converted markdown"
+        )]
+        no_cleanup: bool,
+
+        /// Keep math delimiters as returned
+        #[arg(
+            long,
+            help = "Don't move inline $$...$$ onto lines of their own or join inline $...$ math broken over several lines"
+        )]
+        no_math_fix: bool,
+
+        /// Display math style
+        #[arg(
+            long,
+            value_enum,
+            default_value_t = MathStyle::Dollars,
+            conflicts_with = "no_math_fix",
+            help = "How to write display math: $$ on lines of its own, or ```math blocks for GitHub"
+        )]
+        math_style: MathStyle,
+
+        /// Inline math style
+        #[arg(
+            long,
+            value_enum,
+            default_value_t = MathStyle::Dollars,
+            conflicts_with = "no_math_fix",
+            help = "How to write inline math: $...$, or $`...`$ for GitHub"
+        )]
+        inline_math_style: MathStyle,
+
+        /// Tag the language
+        #[arg(
+            long,
+            help = "Detect the language of each result and record it in the front matter, the JSON output or the Notion property set as language_property_name"
+        )]
+        detect_language: bool,
+
+        /// Title override
+        #[arg(
+            long,
+            help = "Title for the Notion page instead of the file name. Supports {stem} and {name} placeholders"
+        )]
+        title: Option<String>,
+
+        /// Notion database override
+        #[arg(
+            long,
+            requires = "notion",
+            help = "Upload to this Notion database id instead of the configured one, for this run only"
+        )]
+        notion_database: Option<String>,
+
+        /// Skip pages already in Notion
+        #[arg(
+            long,
+            requires = "notion",
+            help = "Check the Notion database first and skip files that already have a page"
+        )]
+        notion_skip_existing: bool,
+    },
+
+    /// Work with your Notion database without running any AI provider
+    Notion {
+        #[command(subcommand)]
+        command: NotionCommands,
+    },
+
+    /// Convert files sent over HTTP by other apps on this machine
+    Serve {
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1", help = "Address to listen on")]
+        host: std::net::IpAddr,
+
+        /// Port to listen on
+        #[arg(long, default_value_t = 8787, help = "Port to listen on")]
+        port: u16,
+
+        /// Force a provider
+        #[arg(
+            long,
+            value_parser = ["gemini", "claude", "ollama", "openai"],
+            help = "Use this provider instead of the active provider"
+        )]
+        provider: Option<String>,
+
+        /// API key for conversion
+        #[arg(long, env = "GEMINI_API_KEY", hide_env_values = true)]
+        api_key: Option<String>,
+
+        /// Prompt the LLM
+        #[arg(short, long, help = "Add a custom prompt to pass to the LLM")]
+        prompt: Option<String>,
+
+        /// Use a built-in prompt
+        #[arg(
+            long,
+            value_enum,
+            help = "Use a built-in prompt tuned for a kind of notes (ignored when --prompt is set)"
+        )]
+        prompt_preset: Option<PromptPreset>,
+
+        /// Sampling temperature
+        #[arg(
+            long,
+            help = "Sampling temperature sent to the provider; lower values give more deterministic output"
+        )]
+        temperature: Option<f32>,
+
+        /// Token clients have to send
+        #[arg(
+            long,
+            env = "NOTEDMD_SERVE_TOKEN",
+            hide_env_values = true,
+            help = "Token clients have to send as 'Authorization: Bearer <token>'. A random one is printed when not set"
+        )]
+        token: Option<String>,
+
+        /// Upload size limit
+        #[arg(
+            long,
+            value_name = "MB",
+            default_value_t = 20,
+            value_parser = clap::value_parser!(u32).range(1..),
+            help = "Reject uploads larger than this many megabytes"
+        )]
+        max_upload_mb: u32,
+    },
+
+    /// Delete cached data to reclaim disk space
+    #[command(group = clap::ArgGroup::new("target").required(true).multiple(true))]
+    Clean {
+        /// Progress of interrupted directory runs
+        #[arg(
+            long,
+            group = "target",
+            help = "Delete the progress kept for resuming interrupted directory runs"
+        )]
+        manifests: bool,
+
+        /// Everything in the cache directory
+        #[arg(
+            long,
+            group = "target",
+            help = "Delete everything in notedmd's cache directory"
+        )]
+        all: bool,
+    },
+
+    /// Print the parsed markdown tree and the Notion blocks it converts to
+    #[command(hide = true)]
+    DebugAst {
+        /// Path to a markdown file
+        path: String,
     },
 
     /// Configure notedmd settings
@@ -61,8 +635,147 @@ pub enum Commands {
         #[arg(long, help = "Shows the content of your configuration")]
         show: bool,
 
+        /// Show secrets
+        #[arg(
+            long,
+            requires = "show",
+            help = "With --show, print API keys in full instead of hiding them"
+        )]
+        reveal: bool,
+
         /// Trigger onboarding flow
         #[arg(long, help = "Edit the configuration file")]
         edit: bool,
+
+        /// Skip the overwrite confirmation
+        #[arg(
+            long,
+            requires = "edit",
+            help = "With --edit, save without showing the changes and asking before overwriting existing settings"
+        )]
+        force: bool,
+
+        /// Set up a provider without prompts
+        #[arg(
+            long,
+            requires = "provider",
+            conflicts_with = "edit",
+            help = "Configure --provider from flags instead of the interactive wizard and make it the active provider"
+        )]
+        init: bool,
+
+        /// Provider for --init
+        #[arg(
+            long,
+            requires = "init",
+            value_parser = ["gemini", "claude", "ollama", "openai"],
+            help = "Provider to configure with --init"
+        )]
+        provider: Option<String>,
+
+        /// API key for --init
+        #[arg(
+            long,
+            requires = "init",
+            help = "API key for --init (required for gemini and claude, optional for openai)"
+        )]
+        api_key: Option<String>,
+
+        /// Model for --init
+        #[arg(
+            long,
+            requires = "init",
+            help = "Model for --init (claude, ollama and openai; defaults to the wizard's default)"
+        )]
+        model: Option<String>,
+
+        /// Url for --init
+        #[arg(
+            long,
+            requires = "init",
+            help = "Server url for --init (ollama and openai), or the base_url replacing the public endpoint (gemini and claude)"
+        )]
+        url: Option<String>,
+
+        /// Edit a prompt file
+        #[arg(
+            long,
+            value_parser = ["default", "gemini", "claude", "ollama", "openai"],
+            help = "Open the prompt file for a provider (or 'default' for all of them) in $EDITOR"
+        )]
+        edit_prompt: Option<String>,
+
+        /// Fix a saved OpenAI url
+        #[arg(
+            long,
+            help = "Remove a trailing '/v1' or '/' from the saved OpenAI url"
+        )]
+        migrate_openai_url: bool,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputEncoding {
+    #[default]
+    #[value(name = "utf-8")]
+    Utf8,
+    #[value(name = "utf-8-bom")]
+    Utf8Bom,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum MathStyle {
+    /// `$...$` and `$$...$$` on lines of its own, for Obsidian and pandoc
+    #[default]
+    Dollars,
+    /// `$`...`$` and ```math blocks, which GitHub renders
+    Github,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum DateSubdirs {
+    /// When the source file was last modified
+    Modified,
+    /// When the run started
+    Now,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LineEnding {
+    Lf,
+    Crlf,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OnEmpty {
+    /// Report the file as failed
+    #[default]
+    Fail,
+    /// Leave the file out and move on
+    Skip,
+    /// Send the file again a couple of times before failing
+    Retry,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum NotionCommands {
+    /// Upload an existing markdown file as a Notion page
+    Upload {
+        /// Path to a markdown file
+        path: String,
+
+        /// Title override
+        #[arg(
+            long,
+            help = "Title for the Notion page instead of the file name. Supports {stem} and {name} placeholders"
+        )]
+        title: Option<String>,
+
+        /// Skip pages already in Notion
+        #[arg(
+            long,
+            help = "Check the Notion database first and skip the file if it already has a page"
+        )]
+        skip_existing: bool,
     },
 }
@@ -0,0 +1,116 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How long a key that ran out of quota is passed over.
+const QUOTA_COOLDOWN: Duration = Duration::from_secs(60);
+
+/// API keys used in turn, one per request, passing over keys that recently
+/// ran out of quota.
+pub struct ApiKeys {
+    keys: Vec<String>,
+    state: Mutex<RotationState>,
+}
+
+struct RotationState {
+    next: usize,
+    cooling_until: Vec<Option<Instant>>,
+}
+
+impl ApiKeys {
+    /// Expects at least one key.
+    pub fn new(keys: Vec<String>) -> Self {
+        let count = keys.len();
+        Self {
+            keys,
+            state: Mutex::new(RotationState {
+                next: 0,
+                cooling_until: vec![None; count],
+            }),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// Returns the index and value of the key for the next request: the next
+    /// one in turn that is not cooling down, or the one that recovers first
+    /// when all of them are.
+    pub fn next(&self) -> (usize, &str) {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        let count = self.keys.len();
+        let index = (0..count)
+            .map(|offset| (state.next + offset) % count)
+            .find(|&index| state.cooling_until[index].is_none_or(|until| until <= now))
+            .unwrap_or_else(|| {
+                (0..count)
+                    .min_by_key(|&index| state.cooling_until[index])
+                    .unwrap_or(0)
+            });
+        state.next = (index + 1) % count;
+        (index, &self.keys[index])
+    }
+
+    /// Passes over the key for a while after the provider reported its quota
+    /// as used up.
+    pub fn mark_exhausted(&self, index: usize) {
+        self.state.lock().unwrap().cooling_until[index] = Some(Instant::now() + QUOTA_COOLDOWN);
+    }
+
+    /// Returns `true` when at least one key is not cooling down.
+    pub fn any_available(&self) -> bool {
+        let now = Instant::now();
+        self.state
+            .lock()
+            .unwrap()
+            .cooling_until
+            .iter()
+            .any(|until| until.is_none_or(|until| until <= now))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(count: usize) -> ApiKeys {
+        ApiKeys::new((0..count).map(|index| format!("key-{}", index)).collect())
+    }
+
+    #[test]
+    fn takes_keys_in_turn() {
+        let keys = keys(3);
+        let order: Vec<usize> = (0..4).map(|_| keys.next().0).collect();
+        assert_eq!(order, [0, 1, 2, 0]);
+        assert_eq!(keys.next().1, "key-1");
+    }
+
+    #[test]
+    fn passes_over_an_exhausted_key() {
+        let keys = keys(3);
+        keys.mark_exhausted(1);
+        let order: Vec<usize> = (0..4).map(|_| keys.next().0).collect();
+        assert_eq!(order, [0, 2, 0, 2]);
+        assert!(keys.any_available());
+    }
+
+    #[test]
+    fn falls_back_to_the_key_that_recovers_first() {
+        let keys = keys(2);
+        keys.mark_exhausted(1);
+        std::thread::sleep(Duration::from_millis(5));
+        keys.mark_exhausted(0);
+        assert!(!keys.any_available());
+        assert_eq!(keys.next().0, 1);
+    }
+
+    #[test]
+    fn a_key_is_used_again_after_its_cooldown() {
+        let keys = keys(2);
+        keys.mark_exhausted(0);
+        keys.state.lock().unwrap().cooling_until[0] = Some(Instant::now());
+        assert!(keys.any_available());
+        assert_eq!(keys.next().0, 0);
+    }
+}
@@ -1,9 +1,28 @@
-use crate::ai_provider::AiProvider;
+use crate::ai_provider::{AiProvider, ClientOptions, TokenUsage, UsageCounter};
+use crate::clients::continuation::{Answer, CONTINUATION_PROMPT, collect_answer};
+use crate::clients::error_utils::{parse_body, read_body, request_error, status_error};
+use crate::clients::rate_limit::{RateLimit, parse_rate_limit_headers};
 use crate::error::NotedError;
 use crate::file_utils::FileData;
+use crate::markdown_utils::clean_markdown;
 use async_trait::async_trait;
 use reqwest::{Client, StatusCode};
 use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+use std::time::Duration;
+
+const DEFAULT_BASE_URL: &str = "https://api.anthropic.com";
+
+/// Status Anthropic answers with when its API is overloaded.
+const OVERLOADED_STATUS: u16 = 529;
+
+/// Waits before each retry of a request that hit an overloaded API. Overloads
+/// are transient, so they get retried where other errors fail right away.
+const OVERLOADED_BACKOFF: [Duration; 3] = [
+    Duration::from_secs(5),
+    Duration::from_secs(15),
+    Duration::from_secs(30),
+];
 
 // Request structs
 
@@ -11,6 +30,12 @@ use serde::{Deserialize, Serialize};
 struct ClaudeRequest {
     model: String,
     max_tokens: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    stop_sequences: Vec<String>,
     messages: Vec<Message>,
 }
 
@@ -42,13 +67,27 @@ struct Source {
 
 #[derive(Deserialize, Debug)]
 pub struct ClaudeResponse {
+    // Missing from error responses.
+    #[serde(default)]
     pub content: Vec<ContentResponse>,
     #[serde(default)]
+    pub stop_reason: Option<String>,
+    #[serde(default)]
+    pub usage: Option<ClaudeUsage>,
+    #[serde(default)]
     pub error: Option<ClaudeError>,
 }
 
+#[derive(Deserialize, Debug)]
+pub struct ClaudeUsage {
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+}
+
 #[derive(Deserialize, Debug)]
 pub struct ClaudeError {
+    #[serde(rename = "type", default)]
+    pub error_type: String,
     pub message: String,
 }
 
@@ -61,31 +100,45 @@ pub struct ContentResponse {
 pub struct ClaudeClient {
     client: Client,
     api_key: String,
+    base_url: String,
     model: String,
-    prompt: Option<String>,
+    options: ClientOptions,
+    rate_limit: Mutex<Option<RateLimit>>,
+    usage: UsageCounter,
+    /// [`OVERLOADED_BACKOFF`], shortened by tests.
+    overloaded_backoff: &'static [Duration],
 }
 
 impl ClaudeClient {
-    pub fn new(api_key: String, model: String, prompt: Option<String>) -> Self {
+    /// `base_url` replaces the public endpoint when set.
+    pub fn new(
+        api_key: String,
+        model: String,
+        base_url: Option<String>,
+        options: ClientOptions,
+    ) -> Self {
         Self {
             client: Client::new(),
             api_key,
+            base_url: base_url
+                .as_deref()
+                .unwrap_or(DEFAULT_BASE_URL)
+                .trim_end_matches('/')
+                .to_string(),
             model,
-            prompt,
+            options,
+            rate_limit: Mutex::new(None),
+            usage: UsageCounter::default(),
+            overloaded_backoff: &OVERLOADED_BACKOFF,
         }
     }
-}
-
-#[async_trait]
-impl AiProvider for ClaudeClient {
-    async fn send_request(&self, file_data: FileData) -> Result<String, NotedError> {
-        let url = "https://api.anthropic.com/v1/messages".to_string();
 
-        let prompt = if let Some(custom_prompt) = &self.prompt {
-            custom_prompt.clone()
-        } else {
-            "Take the handwritten notes from this image and convert them into a clean, well-structured Markdown file. Pay attention to headings, lists, and any other formatting. Resemble the hierarchy. Use latex for mathematical equations. For latex use the $$ syntax instead of ```latex. Do not skip anything from the original text. The output should be suitable for use in Obsidian. Just give me the markdown, do not include other text in the response apart from the markdown file. No explanation on how the changes were made is needed".to_string()
-        };
+    /// Builds the body of one request. With `partial` set, the answer so far is
+    /// replayed as the assistant's turn followed by a request to continue it.
+    fn request_body(&self, file_data: &FileData, partial: Option<String>) -> ClaudeRequest {
+        let prompt = self.options.resolve_prompt(
+            "Take the handwritten notes from this image and convert them into a clean, well-structured Markdown file. Pay attention to headings, lists, and any other formatting. Resemble the hierarchy. Use latex for mathematical equations. For latex use the $$ syntax instead of ```latex. Do not skip anything from the original text. The output should be suitable for use in Obsidian. Just give me the markdown, do not include other text in the response apart from the markdown file. No explanation on how the changes were made is needed",
+        );
 
         let file_type = if file_data.mime_type == "application/pdf" {
             "document".to_string()
@@ -93,75 +146,309 @@ impl AiProvider for ClaudeClient {
             "image".to_string()
         };
 
-        let request_body = ClaudeRequest {
+        let mut content = Vec::new();
+        if let Some(context) = &self.options.document_context {
+            content.push(Content {
+                content_type: "text".to_string(),
+                text: Some(context.clone()),
+                source: None,
+            });
+        }
+        content.push(Content {
+            content_type: file_type,
+            text: None,
+            source: Some(Source {
+                source_type: "base64".to_string(),
+                media_type: file_data.mime_type.clone(),
+                data: file_data.encoded_data.clone(),
+            }),
+        });
+        content.push(Content {
+            content_type: "text".to_string(),
+            text: Some(prompt),
+            source: None,
+        });
+
+        let mut messages = vec![Message {
+            role: "user".to_string(),
+            content,
+        }];
+        if let Some(partial) = partial {
+            messages.push(Message {
+                role: "assistant".to_string(),
+                content: vec![Content {
+                    content_type: "text".to_string(),
+                    text: Some(partial),
+                    source: None,
+                }],
+            });
+            messages.push(Message {
+                role: "user".to_string(),
+                content: vec![Content {
+                    content_type: "text".to_string(),
+                    text: Some(CONTINUATION_PROMPT.to_string()),
+                    source: None,
+                }],
+            });
+        }
+
+        ClaudeRequest {
             model: self.model.clone(),
             max_tokens: 4096,
-            messages: vec![Message {
-                role: "user".to_string(),
-                content: vec![
-                    Content {
-                        content_type: file_type,
-                        text: None,
-                        source: Some(Source {
-                            source_type: "base64".to_string(),
-                            media_type: file_data.mime_type,
-                            data: file_data.encoded_data,
-                        }),
-                    },
-                    Content {
-                        content_type: "text".to_string(),
-                        text: Some(prompt),
-                        source: None,
-                    },
-                ],
-            }],
-        };
+            temperature: self.options.temperature,
+            system: self.options.system_prompt.clone(),
+            stop_sequences: self.options.stop_sequences.clone(),
+            messages,
+        }
+    }
+
+    /// Sends one request, see [`Self::request_body`].
+    async fn request(
+        &self,
+        file_data: &FileData,
+        partial: Option<String>,
+    ) -> Result<Answer, NotedError> {
+        let url = format!("{}/v1/messages", self.base_url);
+        let request_body = self.request_body(file_data, partial);
+
+        let mut backoff = self.overloaded_backoff.iter();
+        let response_body = loop {
+            self.options.pace().await;
+            let response = self
+                .client
+                .post(&url)
+                .header("x-api-key", &self.api_key)
+                .header("anthropic-version", "2023-06-01")
+                .json(&request_body)
+                .send()
+                .await
+                .map_err(|e| request_error(self.name(), &url, e))?;
 
-        let response = self
-            .client
-            .post(&url)
-            .header("x-api-key", &self.api_key)
-            .header("anthropic-version", "2023-06-01")
-            .json(&request_body)
-            .send()
-            .await?;
-
-        let status = response.status();
-        let response_body = response.text().await?;
-
-        if status != StatusCode::OK {
-            if status == StatusCode::UNAUTHORIZED {
-                return Err(NotedError::InvalidApiKey);
+            *self.rate_limit.lock().unwrap() = parse_rate_limit_headers(response.headers());
+
+            let status = response.status();
+            let response_body = read_body(response).await?;
+            if status == StatusCode::OK {
+                break response_body;
             }
-            let error_response: Result<ClaudeResponse, _> = serde_json::from_str(&response_body);
-            if let Ok(err_resp) = error_response {
-                if let Some(error) = err_resp.error {
-                    return Err(NotedError::ApiError(error.message));
-                }
+            let error = serde_json::from_str::<ClaudeResponse>(&response_body)
+                .ok()
+                .and_then(|err_resp| err_resp.error);
+            let overloaded = status.as_u16() == OVERLOADED_STATUS
+                || error
+                    .as_ref()
+                    .is_some_and(|error| error.error_type == "overloaded_error");
+            if !overloaded {
+                return Err(status_error(
+                    self.name(),
+                    status,
+                    error.map(|error| error.message),
+                ));
             }
-            return Err(NotedError::ApiError(format!(
-                "Received status code: {}",
-                status
-            )));
-        }
+            match backoff.next() {
+                Some(delay) => tokio::time::sleep(*delay).await,
+                None => return Err(NotedError::ProviderOverloaded(self.name().to_string())),
+            }
+        };
 
-        let claude_response: ClaudeResponse = serde_json::from_str(&response_body)
-            .map_err(|e| NotedError::ResponseDecodeError(e.to_string()))?;
+        let claude_response: ClaudeResponse = parse_body(&response_body)?;
 
         if let Some(error) = claude_response.error {
             return Err(NotedError::ApiError(error.message));
         }
+        if let Some(usage) = claude_response.usage {
+            self.usage.record(TokenUsage {
+                input_tokens: usage.input_tokens,
+                output_tokens: usage.output_tokens,
+            });
+        }
 
-        let markdown_text = claude_response
+        let text = claude_response
             .content
             .first()
-            .map(|c| c.text.as_str())
-            .unwrap_or("");
+            .map(|c| c.text.clone())
+            .unwrap_or_default();
+
+        Ok(Answer {
+            text,
+            truncated: claude_response.stop_reason.as_deref() == Some("max_tokens"),
+        })
+    }
+}
+
+#[async_trait]
+impl AiProvider for ClaudeClient {
+    async fn send_request(&self, file_data: FileData) -> Result<String, NotedError> {
+        let markdown = collect_answer(self.options.auto_continue, |partial| {
+            self.request(&file_data, partial)
+        })
+        .await?;
+        Ok(clean_markdown(&markdown))
+    }
+
+    fn name(&self) -> &'static str {
+        "claude"
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn supports_pdf(&self) -> bool {
+        true
+    }
+
+    // The Messages API has no seed parameter.
+    fn supports_seed(&self) -> bool {
+        false
+    }
+
+    fn supports_stop_sequences(&self) -> bool {
+        true
+    }
+
+    fn pacing_delay(&self) -> Option<Duration> {
+        self.rate_limit
+            .lock()
+            .unwrap()
+            .and_then(|rate_limit| rate_limit.pacing_delay())
+    }
+
+    fn take_usage(&self) -> Option<TokenUsage> {
+        self.usage.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::Router;
+    use axum::http::Uri;
+    use std::sync::Arc;
+
+    const ANSWER: &str = r#"{"content":[{"type":"text","text":"Notes"}],"stop_reason":"end_turn"}"#;
+
+    /// Starts a fake Messages API behind an `/anthropic/` prefix, like a
+    /// gateway's, that gives `answers` in order. Returns its base url and the
+    /// paths requested.
+    async fn fake_claude(answers: Vec<(u16, &'static str)>) -> (String, Arc<Mutex<Vec<String>>>) {
+        let paths = Arc::new(Mutex::new(Vec::new()));
+        let answers = Arc::new(Mutex::new(answers));
+        let requested = paths.clone();
+        let app = Router::new().fallback(move |uri: Uri| {
+            let (paths, answers) = (requested.clone(), answers.clone());
+            async move {
+                paths.lock().unwrap().push(uri.path().to_string());
+                let (status, body) = answers.lock().unwrap().remove(0);
+                (StatusCode::from_u16(status).unwrap(), body)
+            }
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/anthropic/", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (url, paths)
+    }
+
+    async fn send(base_url: String) -> Result<String, NotedError> {
+        let mut client = ClaudeClient::new(
+            "key".to_string(),
+            "claude".to_string(),
+            Some(base_url),
+            ClientOptions::default(),
+        );
+        client.overloaded_backoff = &[Duration::ZERO; 3];
+        client
+            .send_request(FileData::new(b"png", "image/png".to_string()))
+            .await
+    }
+
+    fn body(options: ClientOptions) -> serde_json::Value {
+        let client = ClaudeClient::new("key".to_string(), "claude".to_string(), None, options);
+        let file_data = FileData::new(b"png", "image/png".to_string());
+        serde_json::to_value(client.request_body(&file_data, None)).unwrap()
+    }
+
+    #[test]
+    fn sends_the_system_prompt() {
+        let body = body(ClientOptions {
+            system_prompt: Some("You transcribe lecture notes.".to_string()),
+            ..Default::default()
+        });
+        assert_eq!(body["system"], "You transcribe lecture notes.");
+        assert_eq!(body["messages"].as_array().unwrap().len(), 1);
+
+        let body = self::body(ClientOptions::default());
+        assert!(body.get("system").is_none());
+    }
+
+    #[test]
+    fn sends_stop_sequences() {
+        let body = body(ClientOptions {
+            stop_sequences: vec!["</notes>".to_string()],
+            ..Default::default()
+        });
+        assert_eq!(body["stop_sequences"], serde_json::json!(["</notes>"]));
+
+        let body = self::body(ClientOptions::default());
+        assert!(body.get("stop_sequences").is_none());
+    }
+
+    #[test]
+    fn parses_usage() {
+        let response: ClaudeResponse = parse_body(
+            r#"{"content":[{"type":"text","text":"Notes"}],"stop_reason":"end_turn",
+                "usage":{"input_tokens":1500,"output_tokens":240,"cache_read_input_tokens":0}}"#,
+        )
+        .unwrap();
+        let usage = response.usage.unwrap();
+        assert_eq!((usage.input_tokens, usage.output_tokens), (1500, 240));
+    }
+
+    #[tokio::test]
+    async fn sends_requests_to_the_configured_base_url() {
+        let (url, paths) = fake_claude(vec![(200, ANSWER)]).await;
+        assert_eq!(send(url).await.unwrap(), "Notes");
+        assert_eq!(*paths.lock().unwrap(), ["/anthropic/v1/messages"]);
+    }
+
+    const OVERLOADED: &str =
+        r#"{"type":"error","error":{"type":"overloaded_error","message":"Overloaded"}}"#;
+
+    #[tokio::test]
+    async fn retries_while_overloaded() {
+        let (url, paths) =
+            fake_claude(vec![(529, OVERLOADED), (529, OVERLOADED), (200, ANSWER)]).await;
+        assert_eq!(send(url).await.unwrap(), "Notes");
+        assert_eq!(paths.lock().unwrap().len(), 3);
+
+        // An overloaded_error body counts whatever the status.
+        let (url, paths) = fake_claude(vec![(500, OVERLOADED), (200, ANSWER)]).await;
+        assert_eq!(send(url).await.unwrap(), "Notes");
+        assert_eq!(paths.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_the_last_backoff() {
+        let (url, paths) = fake_claude(vec![(529, OVERLOADED); 4]).await;
+        assert!(matches!(
+            send(url).await,
+            Err(NotedError::ProviderOverloaded(provider)) if provider == "claude"
+        ));
+        assert_eq!(paths.lock().unwrap().len(), 1 + OVERLOADED_BACKOFF.len());
+    }
 
-        let cleaned_markdown = markdown_text
-            .trim_start_matches("```markdown\n")
-            .trim_end_matches("```");
+    #[tokio::test]
+    async fn fails_other_errors_right_away() {
+        let invalid = r#"{"type":"error","error":{"type":"invalid_request_error","message":"Could not process image"}}"#;
+        let (url, paths) = fake_claude(vec![(400, invalid)]).await;
+        assert!(matches!(
+            send(url).await,
+            Err(NotedError::ApiError(message)) if message == "Could not process image"
+        ));
+        assert_eq!(paths.lock().unwrap().len(), 1);
 
-        Ok(cleaned_markdown.to_string())
+        let (url, paths) = fake_claude(vec![(401, "")]).await;
+        assert!(matches!(send(url).await, Err(NotedError::InvalidApiKey)));
+        assert_eq!(paths.lock().unwrap().len(), 1);
     }
 }
@@ -0,0 +1,108 @@
+use crate::error::NotedError;
+use std::future::Future;
+
+/// Sent after the partial answer to ask the model to pick up where it stopped.
+pub const CONTINUATION_PROMPT: &str = "Your previous answer was cut off because it reached the output limit. Continue the transcription exactly where it stopped. Do not repeat anything you already wrote and do not add any other text.";
+
+/// Most continuation requests sent for a single file.
+const MAX_CONTINUATIONS: u32 = 3;
+
+/// The text of one response and whether the provider cut it off at its output
+/// limit.
+pub struct Answer {
+    pub text: String,
+    pub truncated: bool,
+}
+
+/// Sends the first request and, when `auto_continue` is set and the answer was
+/// truncated, follow-up requests carrying everything received so far, joining
+/// the pieces into one answer.
+pub async fn collect_answer<F, Fut>(
+    auto_continue: bool,
+    mut request: F,
+) -> Result<String, NotedError>
+where
+    F: FnMut(Option<String>) -> Fut,
+    Fut: Future<Output = Result<Answer, NotedError>>,
+{
+    let mut answer = request(None).await?;
+    let mut continuations = 0;
+    while auto_continue && answer.truncated && continuations < MAX_CONTINUATIONS {
+        let next = request(Some(answer.text.clone())).await?;
+        answer.text.push_str(&next.text);
+        answer.truncated = next.truncated;
+        continuations += 1;
+    }
+    Ok(answer.text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Answers with `pieces` in order, each truncated but the last, and
+    /// keeps the answer so far each request was sent.
+    async fn collect(auto_continue: bool, pieces: &[&str]) -> (String, Vec<Option<String>>) {
+        let mut sent = Vec::new();
+        let mut index = 0;
+        let text = collect_answer(auto_continue, |partial| {
+            sent.push(partial);
+            let piece = pieces[index].to_string();
+            index += 1;
+            let truncated = index < pieces.len();
+            async move {
+                Ok(Answer {
+                    text: piece,
+                    truncated,
+                })
+            }
+        })
+        .await
+        .unwrap();
+        (text, sent)
+    }
+
+    #[tokio::test]
+    async fn continues_truncated_answers() {
+        let (text, sent) = collect(true, &["# Wa", "ves\n", "Light"]).await;
+        assert_eq!(text, "# Waves\nLight");
+        assert_eq!(
+            sent,
+            [
+                None,
+                Some("# Wa".to_string()),
+                Some("# Waves\n".to_string())
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn stops_after_max_continuations() {
+        let pieces = ["a", "b", "c", "d", "e", "f"];
+        let (text, sent) = collect(true, &pieces).await;
+        assert_eq!(sent.len(), 1 + MAX_CONTINUATIONS as usize);
+        assert_eq!(text, "abcd");
+    }
+
+    #[tokio::test]
+    async fn sends_one_request_without_auto_continue() {
+        let (text, sent) = collect(false, &["# Wa", "ves"]).await;
+        assert_eq!(text, "# Wa");
+        assert_eq!(sent, [None]);
+    }
+
+    #[tokio::test]
+    async fn returns_the_first_error() {
+        let result = collect_answer(true, |partial| async move {
+            match partial {
+                None => Ok(Answer {
+                    text: "# Wa".to_string(),
+                    truncated: true,
+                }),
+                Some(_) => Err(NotedError::ApiError("overloaded".to_string())),
+            }
+        })
+        .await;
+        assert!(matches!(result, Err(NotedError::ApiError(message)) if message == "overloaded"));
+    }
+}
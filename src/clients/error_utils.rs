@@ -0,0 +1,347 @@
+use crate::error::NotedError;
+use reqwest::{Response, StatusCode, header::CONTENT_TYPE};
+use serde::de::DeserializeOwned;
+use std::error::Error;
+use std::io;
+
+/// Turns a non-success response into the most specific error available.
+/// `message` is the error text the provider put in the body, if any.
+pub fn status_error(provider: &str, status: StatusCode, message: Option<String>) -> NotedError {
+    let detail = message.unwrap_or_else(|| format!("Received status code: {}", status));
+    match status {
+        StatusCode::UNAUTHORIZED => NotedError::InvalidApiKey,
+        StatusCode::PAYMENT_REQUIRED => NotedError::PaymentRequired(provider.to_string(), detail),
+        StatusCode::FORBIDDEN => NotedError::PermissionDenied(provider.to_string(), detail),
+        _ => NotedError::ApiError(detail),
+    }
+}
+
+const CONTEXT_HINT: &str = "The file is too long for the model's context window. Split large PDFs into smaller files or pick a model with a longer context.";
+
+/// Known provider error messages, as lowercase substrings that must all
+/// appear, and what usually fixes them.
+const ERROR_HINTS: &[(&[&str], &str)] = &[
+    (
+        &["model", "not found"],
+        "The model name is wrong or not available to your account. Check it with 'notedmd config --show'; for Ollama, download the model first with 'ollama pull <model>'.",
+    ),
+    (&["context length"], CONTEXT_HINT),
+    (&["maximum context"], CONTEXT_HINT),
+    (
+        &["invalid image"],
+        "The provider could not read the image. Check that the file opens in an image viewer, or re-export it as PNG or JPEG.",
+    ),
+    (
+        &["image input"],
+        "The model does not accept images. Pick a vision model, e.g. 'llava' or 'gemma3' for Ollama.",
+    ),
+    (
+        &["quota"],
+        "The API key ran out of quota. Wait for it to reset, slow the run down with '--rpm', or add more Gemini keys with 'api_keys'.",
+    ),
+    (
+        &["rate limit"],
+        "Requests are being rate limited. Slow the run down with '--rpm' and run it again; finished files are skipped.",
+    ),
+    (
+        &["overloaded"],
+        "The provider is temporarily overloaded. Wait a few minutes and try again.",
+    ),
+];
+
+/// Appends a plain-English fix to a provider error whose message matches a
+/// known problem, keeping the original message. Other errors are returned
+/// unchanged.
+pub fn explain_error(error: NotedError) -> NotedError {
+    let NotedError::ApiError(message) = error else {
+        return error;
+    };
+    let lowercase = message.to_lowercase();
+    match ERROR_HINTS
+        .iter()
+        .find(|(patterns, _)| patterns.iter().all(|pattern| lowercase.contains(pattern)))
+    {
+        Some((_, hint)) => NotedError::ApiError(format!("{}\n  Hint: {}", message, hint)),
+        None => NotedError::ApiError(message),
+    }
+}
+
+/// Reads a response body as text. Unlike `Response::text`, bytes that are not
+/// valid UTF-8 are an error instead of being replaced, so a mangled response
+/// never ends up in the markdown. Bodies that declare another charset are
+/// decoded with it.
+pub async fn read_body(response: Response) -> Result<String, NotedError> {
+    let charset = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|content_type| {
+            content_type
+                .split(';')
+                .find_map(|param| param.trim().strip_prefix("charset="))
+        })
+        .map(|charset| charset.trim_matches('"').to_ascii_lowercase());
+    if let Some(charset) = charset
+        && charset != "utf-8"
+        && charset != "utf8"
+    {
+        return Ok(response.text().await?);
+    }
+
+    decode_utf8(response.bytes().await?.to_vec())
+}
+
+/// Parses the body of a success response, keeping the body in the error when
+/// it doesn't have the expected shape.
+pub fn parse_body<T: DeserializeOwned>(body: &str) -> Result<T, NotedError> {
+    serde_json::from_str(body).map_err(|e| {
+        let reason = if body.trim_start().starts_with('<') {
+            "the body is an HTML page, not JSON".to_string()
+        } else {
+            e.to_string()
+        };
+        NotedError::UnexpectedResponseBody {
+            reason,
+            body: body.to_string(),
+        }
+    })
+}
+
+pub fn decode_utf8(bytes: Vec<u8>) -> Result<String, NotedError> {
+    String::from_utf8(bytes).map_err(|e| {
+        NotedError::ResponseDecodeError(format!(
+            "the response is not valid UTF-8 (invalid byte at offset {}). A proxy between you and the provider may be changing its encoding",
+            e.utf8_error().valid_up_to()
+        ))
+    })
+}
+
+/// Turns a request that never got a response into a specific error for the
+/// common setup problems: nothing listening at the url, a host name that
+/// doesn't resolve, and TLS failures.
+pub fn request_error(provider: &str, url: &str, error: reqwest::Error) -> NotedError {
+    if !error.is_connect() {
+        return error.into();
+    }
+    // The query string can hold an API key (Gemini), so it is never shown.
+    let url = url.split('?').next().unwrap_or_default().to_string();
+
+    let mut source = error.source();
+    while let Some(cause) = source {
+        if let Some(io_error) = cause.downcast_ref::<io::Error>()
+            && io_error.kind() == io::ErrorKind::ConnectionRefused
+        {
+            return NotedError::ConnectionRefused(provider.to_string(), url);
+        }
+        let message = cause.to_string();
+        let lowercase = message.to_lowercase();
+        if lowercase.contains("dns error") || lowercase.contains("failed to lookup address") {
+            return NotedError::DnsError(url);
+        }
+        if lowercase.contains("certificate")
+            || lowercase.contains("tls")
+            || lowercase.contains("ssl")
+        {
+            return NotedError::TlsError(url, message);
+        }
+        source = cause.source();
+    }
+    error.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::time::Duration;
+
+    /// Returns a localhost address nothing is listening on.
+    fn closed_port_url() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+        format!("http://127.0.0.1:{}/api/generate?key=secret-key", port)
+    }
+
+    async fn send(client: &reqwest::Client, url: &str) -> NotedError {
+        let error = client.get(url).send().await.unwrap_err();
+        request_error("ollama", url, error)
+    }
+
+    #[test]
+    fn maps_402_and_403_to_actionable_errors() {
+        let error = status_error(
+            "openai",
+            StatusCode::PAYMENT_REQUIRED,
+            Some("You exceeded your current quota".to_string()),
+        );
+        assert!(matches!(error, NotedError::PaymentRequired(..)));
+        let message = error.to_string();
+        assert!(message.contains("'openai'"));
+        assert!(message.contains("billing is not set up"));
+        assert!(message.contains("You exceeded your current quota"));
+
+        let error = status_error("claude", StatusCode::FORBIDDEN, None);
+        assert!(matches!(error, NotedError::PermissionDenied(..)));
+        let message = error.to_string();
+        assert!(message.contains("lacks permission for this model"));
+        assert!(message.contains("403 Forbidden"));
+
+        assert!(matches!(
+            status_error("gemini", StatusCode::UNAUTHORIZED, None),
+            NotedError::InvalidApiKey
+        ));
+        assert!(matches!(
+            status_error("gemini", StatusCode::BAD_REQUEST, Some("bad".to_string())),
+            NotedError::ApiError(message) if message == "bad"
+        ));
+    }
+
+    fn explained(message: &str) -> String {
+        match explain_error(NotedError::ApiError(message.to_string())) {
+            NotedError::ApiError(message) => message,
+            other => panic!("expected ApiError, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn explain_error_adds_a_hint_to_known_messages() {
+        let message = explained("model 'llava:13b' not found, try pulling it first");
+        assert!(message.starts_with("model 'llava:13b' not found, try pulling it first\n  Hint: "));
+        assert!(message.contains("ollama pull"));
+
+        assert!(
+            explained("This model's maximum context length is 128000 tokens")
+                .contains("context window")
+        );
+        assert!(explained("Invalid image data").contains("re-export it as PNG"));
+        assert!(explained("Rate limit reached for requests").contains("--rpm"));
+        assert!(explained("Overloaded").contains("temporarily overloaded"));
+
+        // Every pattern of an entry has to match.
+        assert_eq!(explained("model is loading"), "model is loading");
+        assert!(matches!(
+            explain_error(NotedError::InvalidApiKey),
+            NotedError::InvalidApiKey
+        ));
+    }
+
+    fn response(content_type: &str, body: &[u8]) -> Response {
+        axum::http::Response::builder()
+            .header(CONTENT_TYPE, content_type)
+            .body(body.to_vec())
+            .unwrap()
+            .into()
+    }
+
+    #[test]
+    fn decode_utf8_rejects_invalid_bytes() {
+        assert_eq!(
+            decode_utf8("# Grüße".as_bytes().to_vec()).unwrap(),
+            "# Grüße"
+        );
+
+        let mut bytes = b"# Notes".to_vec();
+        bytes.push(0xff);
+        match decode_utf8(bytes) {
+            Err(NotedError::ResponseDecodeError(message)) => {
+                assert!(message.contains("not valid UTF-8"));
+                assert!(message.contains("offset 7"));
+            }
+            other => panic!("expected ResponseDecodeError, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn read_body_decodes_the_declared_charset() {
+        let body = read_body(response("application/json", b"{\"a\":\"\xe9\"}")).await;
+        assert!(matches!(body, Err(NotedError::ResponseDecodeError(_))));
+
+        let body = read_body(response(
+            "application/json; charset=ISO-8859-1",
+            b"{\"a\":\"\xe9\"}",
+        ))
+        .await;
+        assert_eq!(body.unwrap(), "{\"a\":\"é\"}");
+
+        let body = read_body(response("application/json; charset=utf-8", "é".as_bytes())).await;
+        assert_eq!(body.unwrap(), "é");
+    }
+
+    #[derive(serde::Deserialize, Debug)]
+    struct Body {
+        text: String,
+    }
+
+    #[test]
+    fn parse_body_keeps_the_body_it_could_not_parse() {
+        assert_eq!(
+            parse_body::<Body>(r#"{"text":"Notes"}"#).unwrap().text,
+            "Notes"
+        );
+
+        match parse_body::<Body>(r#"{"choices":[]}"#) {
+            Err(NotedError::UnexpectedResponseBody { reason, body }) => {
+                assert!(reason.contains("missing field `text`"));
+                assert_eq!(body, r#"{"choices":[]}"#);
+            }
+            other => panic!("expected UnexpectedResponseBody, got {:?}", other),
+        }
+
+        let page = "\n <html><body>502 Bad Gateway</body></html>";
+        let error = parse_body::<Body>(page).unwrap_err();
+        assert!(matches!(
+            &error,
+            NotedError::UnexpectedResponseBody { reason, body }
+                if reason == "the body is an HTML page, not JSON" && body == page
+        ));
+        // The body is only printed with --verbose.
+        assert!(!error.to_string().contains("Bad Gateway"));
+    }
+
+    #[tokio::test]
+    async fn reports_a_closed_port_as_connection_refused() {
+        let url = closed_port_url();
+        match send(&reqwest::Client::new(), &url).await {
+            NotedError::ConnectionRefused(provider, shown_url) => {
+                assert_eq!(provider, "ollama");
+                assert_eq!(shown_url, url.split('?').next().unwrap());
+            }
+            other => panic!("expected ConnectionRefused, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn reports_an_unknown_host_as_a_dns_error() {
+        let url = "http://notedmd-test.invalid/api/generate";
+        match send(&reqwest::Client::new(), url).await {
+            NotedError::DnsError(shown_url) => assert_eq!(shown_url, url),
+            other => panic!("expected DnsError, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn never_shows_the_query_string() {
+        // Accepts the connection but never answers, so the request times out,
+        // which is not a connect error.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!(
+            "http://{}/v1beta/models/gemma:generateContent?key=secret-key",
+            listener.local_addr().unwrap()
+        );
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_millis(200))
+            .build()
+            .unwrap();
+        let timeout = send(&client, &url).await;
+        assert!(matches!(timeout, NotedError::NetworkError(_)));
+        assert!(!timeout.to_string().contains("secret-key"));
+
+        let refused = send(&reqwest::Client::new(), &closed_port_url()).await;
+        assert!(!refused.to_string().contains("secret-key"));
+
+        // Nor when the request can't even be built.
+        let invalid = send(&reqwest::Client::new(), "gopher://host/?key=secret-key").await;
+        assert!(!invalid.to_string().contains("secret-key"));
+    }
+}
@@ -1,19 +1,62 @@
-use crate::ai_provider::AiProvider;
+use crate::ai_provider::{AiProvider, ClientOptions, TokenUsage, UsageCounter};
+use crate::clients::api_keys::ApiKeys;
+use crate::clients::continuation::{Answer, CONTINUATION_PROMPT, collect_answer};
+use crate::clients::error_utils::{parse_body, read_body, request_error, status_error};
+use crate::clients::rate_limit::{RateLimit, parse_rate_limit_headers};
 use crate::error::NotedError;
 use crate::file_utils::FileData;
+use crate::image_utils;
+use crate::markdown_utils::clean_markdown;
 use async_trait::async_trait;
-use reqwest::{Client, StatusCode};
+use base64::{Engine, engine::general_purpose};
+use reqwest::{Client, Response, StatusCode};
 use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+use std::time::Duration;
+
+const GEMINI_MODEL: &str = "gemma-3-27b-it";
+
+/// Gemma models are served by the Gemini API with fewer options than Gemini
+/// models: no system instruction and no seed.
+fn is_gemma(model: &str) -> bool {
+    model.starts_with("gemma-")
+}
+
+const DEFAULT_BASE_URL: &str = "https://generativelanguage.googleapis.com";
+
+/// Largest base64 payload Gemini accepts as `inlineData`. The API caps the
+/// whole request at 20 MB, so this leaves room for the prompt.
+const INLINE_DATA_LIMIT: usize = 19 * 1024 * 1024;
 
 // Request structs
 
 #[derive(Serialize)]
 struct GeminiRequest {
+    #[serde(rename = "systemInstruction", skip_serializing_if = "Option::is_none")]
+    system_instruction: Option<SystemInstruction>,
     contents: Vec<Content>,
+    #[serde(rename = "generationConfig", skip_serializing_if = "Option::is_none")]
+    generation_config: Option<GenerationConfig>,
+}
+
+#[derive(Serialize)]
+struct GenerationConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<u64>,
+    #[serde(rename = "stopSequences", skip_serializing_if = "Vec::is_empty")]
+    stop_sequences: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct SystemInstruction {
+    parts: Vec<Part>,
 }
 
 #[derive(Serialize)]
 struct Content {
+    role: String,
     parts: Vec<Part>,
 }
 
@@ -38,10 +81,20 @@ struct InlineData {
 #[derive(Deserialize, Debug)]
 pub struct GeminiResponse {
     pub candidates: Option<Vec<Candidate>>,
+    #[serde(rename = "usageMetadata", default)]
+    pub usage_metadata: Option<UsageMetadata>,
     #[serde(default)]
     pub error: Option<GeminiError>,
 }
 
+#[derive(Deserialize, Debug)]
+pub struct UsageMetadata {
+    #[serde(rename = "promptTokenCount", default)]
+    pub prompt_token_count: u64,
+    #[serde(rename = "candidatesTokenCount", default)]
+    pub candidates_token_count: u64,
+}
+
 #[derive(Deserialize, Debug)]
 pub struct GeminiError {
     pub message: String,
@@ -50,6 +103,8 @@ pub struct GeminiError {
 #[derive(Deserialize, Debug)]
 pub struct Candidate {
     pub content: ContentResponse,
+    #[serde(rename = "finishReason", default)]
+    pub finish_reason: Option<String>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -65,92 +120,361 @@ pub struct PartResponse {
 // Client
 pub struct GeminiClient {
     client: Client,
-    api_key: String,
-    prompt: Option<String>,
+    api_keys: ApiKeys,
+    base_url: String,
+    options: ClientOptions,
+    rate_limit: Mutex<Option<RateLimit>>,
+    usage: UsageCounter,
 }
 
 impl GeminiClient {
-    pub fn new(api_key: String, prompt: Option<String>) -> Self {
+    /// `api_keys` are used in turn and must not be empty. `base_url` replaces
+    /// the public endpoint when set.
+    pub fn new(api_keys: Vec<String>, base_url: Option<String>, options: ClientOptions) -> Self {
         Self {
             client: Client::new(),
-            api_key,
-            prompt,
+            api_keys: ApiKeys::new(api_keys),
+            base_url: base_url
+                .as_deref()
+                .unwrap_or(DEFAULT_BASE_URL)
+                .trim_end_matches('/')
+                .to_string(),
+            options,
+            rate_limit: Mutex::new(None),
+            usage: UsageCounter::default(),
         }
     }
-}
 
-#[async_trait]
-impl AiProvider for GeminiClient {
-    async fn send_request(&self, file_data: FileData) -> Result<String, NotedError> {
-        let url = format!(
-            "https://generativelanguage.googleapis.com/v1beta/models/gemma-3-27b-it:generateContent?key={}",
-            self.api_key
+    /// Returns `file_data` unchanged when it fits in a request, and otherwise
+    /// an image scaled down (as JPEG) until it does.
+    fn fit_inline_limit(&self, file_data: FileData) -> Result<FileData, NotedError> {
+        if file_data.encoded_data.len() <= INLINE_DATA_LIMIT {
+            return Ok(file_data);
+        }
+        let too_large = NotedError::InlineDataTooLarge(INLINE_DATA_LIMIT / (1024 * 1024));
+        if !file_data.mime_type.starts_with("image/") {
+            return Err(too_large);
+        }
+        let data = general_purpose::STANDARD
+            .decode(&file_data.encoded_data)
+            .map_err(|e| NotedError::ImageError(e.to_string()))?;
+        // Base64 turns every 3 bytes into 4 characters.
+        let shrunk = image_utils::shrink_to_fit(
+            &data,
+            INLINE_DATA_LIMIT / 4 * 3,
+            &self.options.image_encoding,
+        )?;
+        let file_data = FileData::new(&shrunk, "image/jpeg".to_string());
+        if file_data.encoded_data.len() > INLINE_DATA_LIMIT {
+            return Err(too_large);
+        }
+        Ok(file_data)
+    }
+
+    /// Posts the request with the next API key, moving on to another key
+    /// while the provider answers that the current one is out of quota.
+    async fn post(&self, request_body: &GeminiRequest) -> Result<Response, NotedError> {
+        let mut attempts = 0;
+        loop {
+            let (index, api_key) = self.api_keys.next();
+            let url = format!(
+                "{}/v1beta/models/{}:generateContent?key={}",
+                self.base_url, GEMINI_MODEL, api_key
+            );
+            self.options.pace().await;
+            let response = self
+                .client
+                .post(&url)
+                .json(request_body)
+                .send()
+                .await
+                .map_err(|e| request_error(self.name(), &url, e))?;
+            attempts += 1;
+
+            if response.status() == StatusCode::TOO_MANY_REQUESTS {
+                self.api_keys.mark_exhausted(index);
+                if attempts < self.api_keys.len() && self.api_keys.any_available() {
+                    continue;
+                }
+            }
+            return Ok(response);
+        }
+    }
+
+    /// Builds the request for `file_data`. With `partial` set, the answer so
+    /// far is replayed as the model's turn followed by a request to continue
+    /// it.
+    fn request_body(&self, file_data: &FileData, partial: Option<String>) -> GeminiRequest {
+        let prompt = self.options.resolve_prompt(
+            "Take the handwritten notes from this image and convert them into a clean, well-structured Markdown file. Pay attention to headings, lists, and any other formatting. Resemble the hierarchy. Use latex for mathematical equations. For latex use the $$ syntax instead of ```latex. Do not skip anything from the original text. The output should be suitable for use in Obsidian. Just give me the markdown, do not include other text in the response apart from the markdown file. No explanation on how the changes were made is needed",
         );
 
-        let prompt = if let Some(custom_prompt) = &self.prompt {
-            custom_prompt.clone()
-        } else {
-            "Take the handwritten notes from this image and convert them into a clean, well-structured Markdown file. Pay attention to headings, lists, and any other formatting. Resemble the hierarchy. Use latex for mathematical equations. For latex use the $$ syntax instead of ```latex. Do not skip anything from the original text. The output should be suitable for use in Obsidian. Just give me the markdown, do not include other text in the response apart from the markdown file. No explanation on how the changes were made is needed".to_string()
+        // Gemma models reject `systemInstruction`, so for them the system
+        // prompt leads the user turn instead.
+        let (system_prompt, inline_system_prompt) = match self.options.system_prompt.clone() {
+            Some(text) if is_gemma(GEMINI_MODEL) => (None, Some(text)),
+            system_prompt => (system_prompt, None),
         };
 
-        let request_body = GeminiRequest {
-            contents: vec![Content {
-                parts: vec![
-                    Part {
-                        text: Some(prompt),
-                        inline_data: None,
-                    },
-                    Part {
-                        text: None,
-                        inline_data: Some(InlineData {
-                            mime_type: file_data.mime_type,
-                            data: file_data.encoded_data,
-                        }),
-                    },
-                ],
-            }],
-        };
+        let mut parts = Vec::new();
+        if let Some(text) = inline_system_prompt {
+            parts.push(Part {
+                text: Some(text),
+                inline_data: None,
+            });
+        }
+        if let Some(context) = &self.options.document_context {
+            parts.push(Part {
+                text: Some(context.clone()),
+                inline_data: None,
+            });
+        }
+        parts.push(Part {
+            text: Some(prompt),
+            inline_data: None,
+        });
+        parts.push(Part {
+            text: None,
+            inline_data: Some(InlineData {
+                mime_type: file_data.mime_type.clone(),
+                data: file_data.encoded_data.clone(),
+            }),
+        });
+
+        let seed = self.options.seed.filter(|_| self.supports_seed());
+        let generation_config = (self.options.temperature.is_some()
+            || seed.is_some()
+            || !self.options.stop_sequences.is_empty())
+        .then(|| GenerationConfig {
+            temperature: self.options.temperature,
+            seed,
+            stop_sequences: self.options.stop_sequences.clone(),
+        });
+        let mut contents = vec![Content {
+            role: "user".to_string(),
+            parts,
+        }];
+        if let Some(partial) = partial {
+            contents.push(Content {
+                role: "model".to_string(),
+                parts: vec![Part {
+                    text: Some(partial),
+                    inline_data: None,
+                }],
+            });
+            contents.push(Content {
+                role: "user".to_string(),
+                parts: vec![Part {
+                    text: Some(CONTINUATION_PROMPT.to_string()),
+                    inline_data: None,
+                }],
+            });
+        }
+
+        GeminiRequest {
+            system_instruction: system_prompt.map(|text| SystemInstruction {
+                parts: vec![Part {
+                    text: Some(text),
+                    inline_data: None,
+                }],
+            }),
+            contents,
+            generation_config,
+        }
+    }
+
+    /// Sends one request, see [`Self::request_body`].
+    async fn request(
+        &self,
+        file_data: &FileData,
+        partial: Option<String>,
+    ) -> Result<Answer, NotedError> {
+        let request_body = self.request_body(file_data, partial);
+        let response = self.post(&request_body).await?;
 
-        let response = self.client.post(&url).json(&request_body).send().await?;
+        *self.rate_limit.lock().unwrap() = parse_rate_limit_headers(response.headers());
 
         let status = response.status();
-        let response_body = response.text().await?;
+        let response_body = read_body(response).await?;
 
         if status != StatusCode::OK {
-            if status == StatusCode::UNAUTHORIZED {
-                return Err(NotedError::InvalidApiKey);
-            }
-            let error_response: Result<GeminiResponse, _> = serde_json::from_str(&response_body);
-            if let Ok(err_resp) = error_response {
-                if let Some(error) = err_resp.error {
-                    return Err(NotedError::ApiError(error.message));
-                }
-            }
-            return Err(NotedError::ApiError(format!(
-                "Received status code: {}",
-                status
-            )));
+            let message = serde_json::from_str::<GeminiResponse>(&response_body)
+                .ok()
+                .and_then(|err_resp| err_resp.error)
+                .map(|error| error.message);
+            return Err(status_error(self.name(), status, message));
         }
 
-        let gemini_response: GeminiResponse = serde_json::from_str(&response_body)
-            .map_err(|e| NotedError::ResponseDecodeError(e.to_string()))?;
+        let gemini_response: GeminiResponse = parse_body(&response_body)?;
 
         if let Some(error) = gemini_response.error {
             return Err(NotedError::ApiError(error.message));
         }
+        if let Some(usage) = &gemini_response.usage_metadata {
+            self.usage.record(TokenUsage {
+                input_tokens: usage.prompt_token_count,
+                output_tokens: usage.candidates_token_count,
+            });
+        }
 
-        let markdown_text = gemini_response
+        let candidate = gemini_response
             .candidates
             .as_ref()
-            .and_then(|candidates| candidates.first())
+            .and_then(|candidates| candidates.first());
+        let text = candidate
             .and_then(|candidate| candidate.content.parts.first())
-            .map(|part| part.text.as_str())
-            .unwrap_or("");
+            .map(|part| part.text.clone())
+            .unwrap_or_default();
+
+        Ok(Answer {
+            text,
+            truncated: candidate.and_then(|candidate| candidate.finish_reason.as_deref())
+                == Some("MAX_TOKENS"),
+        })
+    }
+}
+
+#[async_trait]
+impl AiProvider for GeminiClient {
+    async fn send_request(&self, file_data: FileData) -> Result<String, NotedError> {
+        let file_data = self.fit_inline_limit(file_data)?;
+        let markdown = collect_answer(self.options.auto_continue, |partial| {
+            self.request(&file_data, partial)
+        })
+        .await?;
+        Ok(clean_markdown(&markdown))
+    }
+
+    fn name(&self) -> &'static str {
+        "gemini"
+    }
+
+    fn model(&self) -> &str {
+        GEMINI_MODEL
+    }
+
+    fn supports_pdf(&self) -> bool {
+        true
+    }
+
+    // Gemma models on the Gemini API reject a sampling seed.
+    fn supports_seed(&self) -> bool {
+        !is_gemma(GEMINI_MODEL)
+    }
+
+    fn supports_stop_sequences(&self) -> bool {
+        true
+    }
+
+    fn pacing_delay(&self) -> Option<Duration> {
+        self.rate_limit
+            .lock()
+            .unwrap()
+            .and_then(|rate_limit| rate_limit.pacing_delay())
+    }
+
+    fn take_usage(&self) -> Option<TokenUsage> {
+        self.usage.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::Router;
+    use axum::extract::RawQuery;
+    use axum::http::Uri;
+    use std::sync::Arc;
 
-        let cleaned_markdown = markdown_text
-            .trim_start_matches("```markdown\n")
-            .trim_end_matches("```");
+    fn client(options: ClientOptions) -> GeminiClient {
+        GeminiClient::new(vec!["key".to_string()], None, options)
+    }
+
+    fn body(options: ClientOptions) -> serde_json::Value {
+        let file_data = FileData::new(b"png", "image/png".to_string());
+        serde_json::to_value(client(options).request_body(&file_data, None)).unwrap()
+    }
+
+    #[test]
+    fn leaves_out_the_seed_for_gemma() {
+        let body = body(ClientOptions {
+            seed: Some(7),
+            temperature: Some(0.5),
+            ..Default::default()
+        });
+        assert!(body["generationConfig"].get("seed").is_none());
+        assert_eq!(body["generationConfig"]["temperature"], 0.5);
+
+        let body = self::body(ClientOptions {
+            seed: Some(7),
+            ..Default::default()
+        });
+        assert!(body.get("generationConfig").is_none());
+    }
 
-        Ok(cleaned_markdown.to_string())
+    #[test]
+    fn sends_the_system_prompt_in_the_user_turn_for_gemma() {
+        let body = body(ClientOptions {
+            system_prompt: Some("You transcribe physics notes.".to_string()),
+            document_context: Some("A problem set".to_string()),
+            ..Default::default()
+        });
+        assert!(body.get("systemInstruction").is_none());
+        let parts = &body["contents"][0]["parts"];
+        assert_eq!(parts[0]["text"], "You transcribe physics notes.");
+        assert_eq!(parts[1]["text"], "A problem set");
+        assert!(parts[3]["inline_data"].is_object());
+    }
+
+    #[test]
+    fn parses_usage_metadata() {
+        let response: GeminiResponse = parse_body(
+            r#"{"candidates":[{"content":{"parts":[{"text":"Notes"}]}}],
+                "usageMetadata":{"promptTokenCount":1290,"candidatesTokenCount":85,"totalTokenCount":1375}}"#,
+        )
+        .unwrap();
+        let usage = response.usage_metadata.unwrap();
+        assert_eq!(usage.prompt_token_count, 1290);
+        assert_eq!(usage.candidates_token_count, 85);
+
+        let response: GeminiResponse =
+            parse_body(r#"{"usageMetadata":{"promptTokenCount":12}}"#).unwrap();
+        assert_eq!(response.usage_metadata.unwrap().candidates_token_count, 0);
+    }
+
+    #[tokio::test]
+    async fn sends_requests_to_the_configured_base_url() {
+        let requested = Arc::new(Mutex::new(Vec::new()));
+        let app = Router::new().fallback({
+            let requested = requested.clone();
+            move |uri: Uri, RawQuery(query): RawQuery| {
+                let requested = requested.clone();
+                async move {
+                    requested
+                        .lock()
+                        .unwrap()
+                        .push((uri.path().to_string(), query));
+                    r#"{"candidates":[{"content":{"parts":[{"text":"Notes"}]}}]}"#
+                }
+            }
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}/gemini/", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let client = GeminiClient::new(
+            vec!["key".to_string()],
+            Some(base_url),
+            ClientOptions::default(),
+        );
+        let file_data = FileData::new(b"png", "image/png".to_string());
+        assert_eq!(client.send_request(file_data).await.unwrap(), "Notes");
+        assert_eq!(
+            *requested.lock().unwrap(),
+            [(
+                format!("/gemini/v1beta/models/{}:generateContent", GEMINI_MODEL),
+                Some("key=key".to_string())
+            )]
+        );
     }
 }
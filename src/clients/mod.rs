@@ -1,5 +1,9 @@
+pub mod api_keys;
 pub mod claude_client;
+pub mod continuation;
+pub mod error_utils;
 pub mod gemini_client;
 pub mod notion_client;
 pub mod ollama_client;
 pub mod openai_client;
+pub mod rate_limit;
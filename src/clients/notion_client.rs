@@ -7,7 +7,12 @@ use notion_client::objects::block::Block;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 
-use crate::{config, error::NotedError, notion::converter};
+use crate::{
+    clients::error_utils::{read_body, request_error},
+    config,
+    error::NotedError,
+    notion::converter,
+};
 
 // Request structs
 #[derive(Serialize)]
@@ -30,6 +35,11 @@ pub struct NotionResponse {
     pub url: String,
 }
 
+#[derive(Deserialize, Debug)]
+pub struct NotionQueryResponse {
+    pub results: Vec<NotionResponse>,
+}
+
 #[derive(Deserialize, Debug)]
 pub struct NotionDatabase {
     pub properties: HashMap<String, DatabaseProperty>,
@@ -51,8 +61,12 @@ pub enum PropertyType {
     Title(EmptyStruct),
     RichText(EmptyStruct),
     Number(EmptyStruct),
-    Select { select: SelectStruct },
-    MultiSelect { multi_select: SelectStruct },
+    Select {
+        select: SelectStruct,
+    },
+    MultiSelect {
+        multi_select: SelectStruct,
+    },
     Date(EmptyStruct),
     Checkbox(EmptyStruct),
     People(EmptyStruct),
@@ -63,7 +77,10 @@ pub enum PropertyType {
     CreatedBy(EmptyStruct),
     LastEditedTime(EmptyStruct),
     LastEditedBy(EmptyStruct),
-    Status { status: SelectStruct },
+    Status {
+        #[serde(rename = "status")]
+        _status: SelectStruct,
+    },
     Formula(EmptyStruct),
     Relation(EmptyStruct),
     Rollup(EmptyStruct),
@@ -87,16 +104,6 @@ pub struct DatabaseSelectOption {
     pub _color: String,
 }
 
-#[derive(Deserialize, Debug)]
-pub struct NumberStruct {
-    pub _number: NumberFormat,
-}
-
-#[derive(Deserialize, Debug)]
-pub struct NumberFormat {
-    pub _format: String,
-}
-
 #[derive(Deserialize, Debug)]
 pub struct EmptyStruct {}
 
@@ -105,34 +112,94 @@ pub struct NotionError {
     pub message: String,
 }
 
+/// A rich text property holding a stable identifier of the converted file.
+pub struct SourceMarker<'a> {
+    pub property_name: &'a str,
+    pub value: &'a str,
+}
+
 // Client
+/// Checks that `id` looks like a Notion database id: 32 hex digits, with or
+/// without the dashes of the UUID form.
+pub fn validate_database_id(id: &str) -> Result<String, NotedError> {
+    let id = id.trim();
+    let digits: String = id.chars().filter(|c| *c != '-').collect();
+    if digits.len() == 32 && digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        Ok(id.to_string())
+    } else {
+        Err(NotedError::InvalidArgument(format!(
+            "'{}' is not a Notion database id; expected 32 hex digits such as the one in the database url",
+            id
+        )))
+    }
+}
+
+/// Builds the database query for [`NotionClient::find_existing_page`].
+fn existing_page_query(
+    title: &str,
+    title_property_name: &str,
+    source: Option<&SourceMarker<'_>>,
+) -> serde_json::Value {
+    let filter = match source {
+        Some(source) => serde_json::json!({
+            "property": source.property_name,
+            "rich_text": { "equals": source.value }
+        }),
+        None => serde_json::json!({
+            "property": title_property_name,
+            "title": { "equals": title }
+        }),
+    };
+    serde_json::json!({
+        "filter": filter,
+        "page_size": 1
+    })
+}
+
+const DEFAULT_BASE_URL: &str = "https://api.notion.com";
+
 pub struct NotionClient {
     client: Client,
     api_key: String,
     database_id: String,
+    api_version: String,
+    base_url: String,
 }
 
 impl NotionClient {
-    pub fn new(api_key: String, database_id: String) -> Self {
+    /// `base_url` replaces the public endpoint when set.
+    pub fn new(
+        api_key: String,
+        database_id: String,
+        api_version: String,
+        base_url: Option<String>,
+    ) -> Self {
         Self {
             client: Client::new(),
             api_key,
             database_id,
+            api_version,
+            base_url: base_url
+                .as_deref()
+                .unwrap_or(DEFAULT_BASE_URL)
+                .trim_end_matches('/')
+                .to_string(),
         }
     }
 
     pub async fn get_database_schema(&self) -> Result<NotionDatabase, NotedError> {
-        let url = format!("https://api.notion.com/v1/databases/{}", self.database_id);
+        let url = format!("{}/v1/databases/{}", self.base_url, self.database_id);
         let response = self
             .client
-            .get(url)
+            .get(&url)
             .header("Authorization", format!("Bearer {}", self.api_key))
-            .header("Notion-Version", "2022-06-28")
+            .header("Notion-Version", &self.api_version)
             .send()
-            .await?;
+            .await
+            .map_err(|e| request_error("notion", &url, e))?;
 
         let status = response.status();
-        let response_body = response.text().await?;
+        let response_body = read_body(response).await?;
         if status.is_success() {
             let notion_database: NotionDatabase = serde_json::from_str(&response_body)
                 .map_err(|e| NotedError::ResponseDecodeError(e.to_string()))?;
@@ -148,16 +215,54 @@ impl NotionClient {
         }
     }
 
+    /// Looks for a page created from the same source, matching on the source
+    /// marker when one is configured and on the title otherwise.
+    pub async fn find_existing_page(
+        &self,
+        title: &str,
+        title_property_name: &str,
+        source: Option<&SourceMarker<'_>>,
+    ) -> Result<Option<NotionResponse>, NotedError> {
+        let url = format!("{}/v1/databases/{}/query", self.base_url, self.database_id);
+        let request_body = existing_page_query(title, title_property_name, source);
+
+        let response = self
+            .client
+            .post(&url)
+            .header("Authorization", format!("Bearer {}", self.api_key))
+            .header("Notion-Version", &self.api_version)
+            .json(&request_body)
+            .send()
+            .await
+            .map_err(|e| request_error("notion", &url, e))?;
+
+        let status = response.status();
+        let response_body = read_body(response).await?;
+        if status.is_success() {
+            let query_response: NotionQueryResponse = serde_json::from_str(&response_body)
+                .map_err(|e| NotedError::ResponseDecodeError(e.to_string()))?;
+            Ok(query_response.results.into_iter().next())
+        } else {
+            let error_response: NotionError = serde_json::from_str(&response_body)
+                .map_err(|e| NotedError::ResponseDecodeError(e.to_string()))?;
+            Err(NotedError::ApiError(format!(
+                "Notion API Error ({}): {}",
+                status, error_response.message
+            )))
+        }
+    }
+
     pub async fn create_notion_page(
         &self,
         title: &str,
         title_property_name: &str,
         properties: &[config::NotionPropertyConfig],
+        source: Option<&SourceMarker<'_>>,
         markdown_content: &str,
     ) -> Result<NotionResponse, NotedError> {
-        let url = "https://api.notion.com/v1/pages";
+        let url = &format!("{}/v1/pages", self.base_url);
         let arena = Arena::new();
-        let blocks = converter::Converter::run(&markdown_content, &arena)
+        let blocks = converter::Converter::run(markdown_content, &arena)
             .map_err(|e| NotedError::ApiError(e.to_string()))?;
 
         let mut props_map = serde_json::Map::new();
@@ -223,6 +328,22 @@ impl NotionClient {
 
             props_map.insert(prop_name.clone(), notion_property_value);
         }
+
+        if let Some(source) = source {
+            props_map.insert(
+                source.property_name.to_string(),
+                serde_json::json!({
+                    "rich_text": [
+                        {
+                            "type": "text",
+                            "text": {
+                                "content": source.value
+                            }
+                        }
+                    ]
+                }),
+            );
+        }
         let request_body = NotionRequest {
             parent: Parent {
                 database_id: self.database_id.clone(),
@@ -235,13 +356,14 @@ impl NotionClient {
             .client
             .post(url)
             .header("Authorization", format!("Bearer {}", self.api_key))
-            .header("Notion-Version", "2022-06-28")
+            .header("Notion-Version", &self.api_version)
             .json(&request_body)
             .send()
-            .await?;
+            .await
+            .map_err(|e| request_error("notion", url, e))?;
 
         let status = response.status();
-        let response_body = response.text().await?;
+        let response_body = read_body(response).await?;
 
         if status.is_success() {
             let notion_reponse: NotionResponse = serde_json::from_str(&response_body)
@@ -257,3 +379,52 @@ impl NotionClient {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate_database_id_accepts_both_forms() {
+        for id in [
+            "0123456789abcdef0123456789ABCDEF",
+            "01234567-89ab-cdef-0123-456789abcdef",
+        ] {
+            assert_eq!(validate_database_id(&format!(" {} ", id)).unwrap(), id);
+        }
+        for id in [
+            "",
+            "0123456789abcdef",
+            "0123456789abcdef0123456789abcdeg",
+            "https://www.notion.so/0123456789abcdef0123456789abcdef",
+        ] {
+            assert!(matches!(
+                validate_database_id(id),
+                Err(NotedError::InvalidArgument(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn existing_page_query_matches_the_title_without_a_source_marker() {
+        assert_eq!(
+            existing_page_query("notes.png", "Name", None),
+            serde_json::json!({
+                "filter": {"property": "Name", "title": {"equals": "notes.png"}},
+                "page_size": 1
+            })
+        );
+    }
+
+    #[test]
+    fn existing_page_query_prefers_the_source_marker() {
+        let source = SourceMarker {
+            property_name: "Source",
+            value: "scans/notes.png",
+        };
+        assert_eq!(
+            existing_page_query("notes.png", "Name", Some(&source))["filter"],
+            serde_json::json!({"property": "Source", "rich_text": {"equals": "scans/notes.png"}})
+        );
+    }
+}
@@ -2,15 +2,56 @@ use async_trait::async_trait;
 use reqwest::{Client, StatusCode};
 use serde::{Deserialize, Serialize};
 
-use crate::{ai_provider::AiProvider, error::NotedError, file_utils::FileData};
+use crate::{
+    ai_provider::{AiProvider, ClientOptions},
+    clients::{
+        continuation::{Answer, CONTINUATION_PROMPT, collect_answer},
+        error_utils::{parse_body, read_body, request_error, status_error},
+    },
+    config::OllamaApi,
+    error::NotedError,
+    file_utils::FileData,
+    markdown_utils::clean_markdown,
+};
 
 // Request struct
 #[derive(Serialize)]
 struct OllamaRequest {
     model: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system: Option<String>,
     prompt: String,
     images: Vec<String>,
     stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    options: Option<ModelOptions>,
+}
+
+#[derive(Serialize)]
+struct ChatRequest {
+    model: String,
+    messages: Vec<ChatMessage>,
+    stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    options: Option<ModelOptions>,
+}
+
+#[derive(Serialize)]
+struct ModelOptions {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<u64>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    stop: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct ChatMessage {
+    role: String,
+    content: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    images: Vec<String>,
 }
 
 // Response struct
@@ -18,75 +59,352 @@ struct OllamaRequest {
 pub struct OllamaResponse {
     pub response: String,
     #[serde(default)]
+    pub done_reason: Option<String>,
+    #[serde(default)]
     pub error: Option<String>,
 }
 
+#[derive(Deserialize, Debug)]
+struct ChatResponse {
+    message: ChatResponseMessage,
+    #[serde(default)]
+    done_reason: Option<String>,
+    #[serde(default)]
+    error: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ChatResponseMessage {
+    content: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct ErrorResponse {
+    #[serde(default)]
+    error: Option<String>,
+}
+
 // Client struct
 pub struct OllamaClient {
     client: Client,
     url: String,
     model: String,
-    prompt: Option<String>,
+    api: OllamaApi,
+    options: ClientOptions,
 }
 
 impl OllamaClient {
-    pub fn new(url: String, model: String, prompt: Option<String>) -> Self {
+    pub fn new(url: String, model: String, api: OllamaApi, options: ClientOptions) -> Self {
         Self {
             client: Client::new(),
             url,
             model,
-            prompt,
+            api,
+            options,
         }
     }
-}
 
-#[async_trait]
-impl AiProvider for OllamaClient {
-    async fn send_request(&self, file_data: FileData) -> Result<String, NotedError> {
-        let url = format!("{}/api/generate", self.url);
-        let prompt = if let Some(custom_prompt) = &self.prompt {
-            custom_prompt.clone()
-        } else {
-            "The user has provided an image of handwritten notes. Your task is to accurately transcribe these notes into a well-structured Markdown file. Preserve the original hierarchy, including headings and lists. Use LaTeX for any mathematical equations that appear in the notes. The output should only be the markdown content.".to_string()
-        };
+    fn prompt(&self) -> String {
+        self.options.resolve_prompt(
+            "The user has provided an image of handwritten notes. Your task is to accurately transcribe these notes into a well-structured Markdown file. Preserve the original hierarchy, including headings and lists. Use LaTeX for any mathematical equations that appear in the notes. The output should only be the markdown content.",
+        )
+    }
 
-        let request_body = OllamaRequest {
-            model: self.model.clone(),
-            prompt,
-            images: vec![file_data.encoded_data],
-            stream: false,
-        };
+    /// The prompt, led by the document context when one is set. Ollama takes
+    /// text and images as separate fields, so the context can't be a part of
+    /// its own like with the other providers.
+    fn user_prompt(&self) -> String {
+        match &self.options.document_context {
+            Some(context) => format!("{}\n\n{}", context, self.prompt()),
+            None => self.prompt(),
+        }
+    }
+
+    fn model_options(&self) -> Option<ModelOptions> {
+        (self.options.temperature.is_some()
+            || self.options.seed.is_some()
+            || !self.options.stop_sequences.is_empty())
+        .then(|| ModelOptions {
+            temperature: self.options.temperature,
+            seed: self.options.seed,
+            stop: self.options.stop_sequences.clone(),
+        })
+    }
 
-        let response = self.client.post(&url).json(&request_body).send().await?;
+    /// Posts `body` to `url` and returns the response body, turning a non-OK
+    /// status into the matching error. Both endpoints report errors the same way.
+    async fn post(&self, url: &str, body: &impl Serialize) -> Result<String, NotedError> {
+        self.options.pace().await;
+        let response = self
+            .client
+            .post(url)
+            .json(body)
+            .send()
+            .await
+            .map_err(|e| request_error(self.name(), url, e))?;
 
         let status = response.status();
-        let response_body = response.text().await?;
+        let response_body = read_body(response).await?;
 
         if status != StatusCode::OK {
-            let error_response: Result<OllamaResponse, _> = serde_json::from_str(&response_body);
-            if let Ok(err_resp) = error_response {
-                if let Some(error) = err_resp.error {
-                    return Err(NotedError::ApiError(error));
-                }
-            }
-            return Err(NotedError::ApiError(format!(
-                "Received status code: {}",
-                status
-            )));
+            let message = serde_json::from_str::<ErrorResponse>(&response_body)
+                .ok()
+                .and_then(|err_resp| err_resp.error);
+            return Err(status_error(self.name(), status, message));
         }
 
-        let ollama_response: OllamaResponse = serde_json::from_str(&response_body)
-            .map_err(|e| NotedError::ResponseDecodeError(e.to_string()))?;
+        Ok(response_body)
+    }
+
+    /// Builds the body of one generate request. The endpoint has no turns, so
+    /// with `partial` set the answer so far is quoted in the prompt instead.
+    fn generate_request_body(
+        &self,
+        file_data: &FileData,
+        partial: Option<String>,
+    ) -> OllamaRequest {
+        let mut prompt = self.user_prompt();
+        if let Some(partial) = partial {
+            prompt = format!(
+                "{}\n\nYour answer so far:\n\n{}\n\n{}",
+                prompt, partial, CONTINUATION_PROMPT
+            );
+        }
+
+        OllamaRequest {
+            model: self.model.clone(),
+            system: self.options.system_prompt.clone(),
+            prompt,
+            images: vec![file_data.encoded_data.clone()],
+            stream: false,
+            options: self.model_options(),
+        }
+    }
+
+    /// Sends one generate request, see [`Self::generate_request_body`].
+    async fn send_generate(
+        &self,
+        file_data: &FileData,
+        partial: Option<String>,
+    ) -> Result<Answer, NotedError> {
+        let url = format!("{}/api/generate", self.url);
+        let request_body = self.generate_request_body(file_data, partial);
+        let response_body = self.post(&url, &request_body).await?;
+        let ollama_response: OllamaResponse = parse_body(&response_body)?;
 
         if let Some(error) = ollama_response.error {
             return Err(NotedError::ApiError(error));
         }
 
-        let cleaned_markdown = ollama_response
-            .response
-            .trim_start_matches("```markdown\n")
-            .trim_end_matches("```");
+        Ok(Answer {
+            truncated: ollama_response.done_reason.as_deref() == Some("length"),
+            text: ollama_response.response,
+        })
+    }
+
+    /// Builds the body of one chat request. With `partial` set, the answer so
+    /// far is replayed as the assistant's turn followed by a request to
+    /// continue it.
+    fn chat_request_body(&self, file_data: &FileData, partial: Option<String>) -> ChatRequest {
+        let mut messages = Vec::new();
+        if let Some(system) = &self.options.system_prompt {
+            messages.push(ChatMessage {
+                role: "system".to_string(),
+                content: system.clone(),
+                images: Vec::new(),
+            });
+        }
+        messages.push(ChatMessage {
+            role: "user".to_string(),
+            content: self.user_prompt(),
+            images: vec![file_data.encoded_data.clone()],
+        });
+        if let Some(partial) = partial {
+            messages.push(ChatMessage {
+                role: "assistant".to_string(),
+                content: partial,
+                images: Vec::new(),
+            });
+            messages.push(ChatMessage {
+                role: "user".to_string(),
+                content: CONTINUATION_PROMPT.to_string(),
+                images: Vec::new(),
+            });
+        }
+
+        ChatRequest {
+            model: self.model.clone(),
+            messages,
+            stream: false,
+            options: self.model_options(),
+        }
+    }
+
+    /// Sends one chat request, see [`Self::chat_request_body`].
+    async fn send_chat(
+        &self,
+        file_data: &FileData,
+        partial: Option<String>,
+    ) -> Result<Answer, NotedError> {
+        let url = format!("{}/api/chat", self.url);
+        let request_body = self.chat_request_body(file_data, partial);
+        let response_body = self.post(&url, &request_body).await?;
+        let chat_response: ChatResponse = parse_body(&response_body)?;
+
+        if let Some(error) = chat_response.error {
+            return Err(NotedError::ApiError(error));
+        }
+
+        Ok(Answer {
+            truncated: chat_response.done_reason.as_deref() == Some("length"),
+            text: chat_response.message.content,
+        })
+    }
+}
+
+#[async_trait]
+impl AiProvider for OllamaClient {
+    async fn send_request(&self, file_data: FileData) -> Result<String, NotedError> {
+        let markdown = collect_answer(self.options.auto_continue, |partial| async {
+            match self.api {
+                OllamaApi::Generate => self.send_generate(&file_data, partial).await,
+                OllamaApi::Chat => self.send_chat(&file_data, partial).await,
+            }
+        })
+        .await?;
+        Ok(clean_markdown(&markdown))
+    }
+
+    fn name(&self) -> &'static str {
+        "ollama"
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn supports_pdf(&self) -> bool {
+        false
+    }
+
+    fn supports_seed(&self) -> bool {
+        true
+    }
+
+    fn supports_stop_sequences(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client(options: ClientOptions) -> OllamaClient {
+        OllamaClient::new(
+            "http://localhost".to_string(),
+            "llava".to_string(),
+            OllamaApi::default(),
+            options,
+        )
+    }
+
+    fn file_data() -> FileData {
+        FileData::new(b"png", "image/png".to_string())
+    }
+
+    fn generate_body(options: ClientOptions) -> serde_json::Value {
+        serde_json::to_value(client(options).generate_request_body(&file_data(), None)).unwrap()
+    }
+
+    fn chat_body(options: ClientOptions) -> serde_json::Value {
+        serde_json::to_value(client(options).chat_request_body(&file_data(), None)).unwrap()
+    }
+
+    #[test]
+    fn sends_stop_sequences_in_the_options() {
+        let options = || ClientOptions {
+            stop_sequences: vec!["</notes>".to_string()],
+            ..Default::default()
+        };
+        let stop = serde_json::json!(["</notes>"]);
+        assert_eq!(generate_body(options())["options"]["stop"], stop);
+        assert_eq!(chat_body(options())["options"]["stop"], stop);
+        assert!(
+            generate_body(ClientOptions::default())["options"]
+                .get("stop")
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn sends_the_system_prompt() {
+        let options = || ClientOptions {
+            system_prompt: Some("You transcribe lecture notes.".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            generate_body(options())["system"],
+            "You transcribe lecture notes."
+        );
+
+        let body = chat_body(options());
+        let messages = body["messages"].as_array().unwrap();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0]["role"], "system");
+        assert_eq!(messages[0]["content"], "You transcribe lecture notes.");
+        assert_eq!(messages[1]["role"], "user");
+    }
+
+    #[test]
+    fn sends_the_document_context_with_the_prompt() {
+        let options = || ClientOptions {
+            prompt: Some("Transcribe this page.".to_string()),
+            document_context: Some("A physics problem set".to_string()),
+            ..Default::default()
+        };
+        let prompt = "A physics problem set\n\nTranscribe this page.";
+
+        let body = generate_body(options());
+        assert_eq!(body["prompt"], prompt);
+        assert!(body.get("system").is_none());
+
+        let body = chat_body(options());
+        let messages = body["messages"].as_array().unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0]["role"], "user");
+        assert_eq!(messages[0]["content"], prompt);
+    }
+
+    #[test]
+    fn builds_a_chat_request() {
+        let body = chat_body(ClientOptions {
+            prompt: Some("Transcribe this page.".to_string()),
+            ..Default::default()
+        });
+        assert_eq!(body["model"], "llava");
+        assert_eq!(body["stream"], false);
+        assert!(body.get("options").is_none());
+        let messages = body["messages"].as_array().unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0]["role"], "user");
+        assert_eq!(messages[0]["content"], "Transcribe this page.");
+        assert_eq!(
+            messages[0]["images"],
+            serde_json::json!([file_data().encoded_data])
+        );
 
-        Ok(cleaned_markdown.to_string())
+        // A continuation replays the answer so far as the assistant's turn.
+        let body = serde_json::to_value(
+            client(ClientOptions::default())
+                .chat_request_body(&file_data(), Some("# Wav".to_string())),
+        )
+        .unwrap();
+        let messages = body["messages"].as_array().unwrap();
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[1]["role"], "assistant");
+        assert_eq!(messages[1]["content"], "# Wav");
+        assert_eq!(messages[2]["role"], "user");
+        assert_eq!(messages[2]["content"], CONTINUATION_PROMPT);
     }
 }
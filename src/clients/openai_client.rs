@@ -1,7 +1,18 @@
-use crate::{ai_provider::AiProvider, error::NotedError, file_utils::FileData};
+use crate::{
+    ai_provider::{AiProvider, ClientOptions, TokenUsage, UsageCounter},
+    clients::{
+        continuation::{Answer, CONTINUATION_PROMPT, collect_answer},
+        error_utils::{decode_utf8, parse_body, read_body, request_error, status_error},
+    },
+    config::OpenAIApi,
+    error::NotedError,
+    file_utils::FileData,
+    markdown_utils::clean_markdown,
+};
 use async_trait::async_trait;
-use reqwest::{Client, StatusCode};
-use serde::{Deserialize, Serialize};
+use clap::ValueEnum;
+use reqwest::{Client, Response, StatusCode};
+use serde::{Deserialize, Deserializer, Serialize};
 
 // Request structs
 
@@ -9,6 +20,14 @@ use serde::{Deserialize, Serialize};
 struct OpenAIRequest {
     model: String,
     messages: Vec<Message>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<u64>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    stop: Vec<String>,
 }
 
 #[derive(Serialize)]
@@ -32,6 +51,51 @@ struct Content {
 #[derive(Serialize)]
 struct Image {
     url: String,
+    detail: ImageDetail,
+}
+
+/// Resolution the model looks at the image in; `high` costs more tokens but
+/// reads dense handwriting better.
+#[derive(Serialize, ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageDetail {
+    Low,
+    High,
+    #[default]
+    Auto,
+}
+
+#[derive(Serialize)]
+struct ResponsesRequest {
+    model: String,
+    /// The system prompt. The Responses API has no stop sequences, see
+    /// [`OpenAIClient::supports_stop_sequences`].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    instructions: Option<String>,
+    input: Vec<ResponsesInput>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+}
+
+#[derive(Serialize)]
+struct ResponsesInput {
+    role: String,
+    content: Vec<ResponsesInputContent>,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ResponsesInputContent {
+    InputText {
+        text: String,
+    },
+    InputImage {
+        image_url: String,
+        detail: ImageDetail,
+    },
+    OutputText {
+        text: String,
+    },
 }
 
 // Response structs
@@ -39,10 +103,21 @@ struct Image {
 pub struct OpenAIResponse {
     pub choices: Vec<Choice>,
 
+    #[serde(default)]
+    pub usage: Option<ChatUsage>,
+
     #[serde(default)]
     pub error: Option<OpenAIError>,
 }
 
+#[derive(Deserialize, Debug)]
+pub struct ChatUsage {
+    #[serde(default)]
+    pub prompt_tokens: u64,
+    #[serde(default)]
+    pub completion_tokens: u64,
+}
+
 #[derive(Deserialize, Debug)]
 pub struct OpenAIError {
     pub message: String,
@@ -51,20 +126,124 @@ pub struct OpenAIError {
 #[derive(Deserialize, Debug)]
 pub struct Choice {
     pub message: ResponseMessage,
+
+    #[serde(default)]
+    pub finish_reason: Option<String>,
 }
 
 #[derive(Deserialize, Debug)]
 pub struct ResponseMessage {
+    #[serde(deserialize_with = "deserialize_message_content")]
     pub content: String,
 }
 
+/// Message content as sent by OpenAI, or as an array of `{type, text}` parts
+/// like some gateways send even for chat completions.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum MessageContent {
+    Text(String),
+    Parts(Vec<MessageContentPart>),
+}
+
+#[derive(Deserialize)]
+struct MessageContentPart {
+    #[serde(default)]
+    text: String,
+}
+
+/// Reads either shape of [`MessageContent`], joining the text of all parts.
+fn deserialize_message_content<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<String, D::Error> {
+    Ok(match MessageContent::deserialize(deserializer)? {
+        MessageContent::Text(text) => text,
+        MessageContent::Parts(parts) => parts.into_iter().map(|part| part.text).collect(),
+    })
+}
+
+#[derive(Deserialize, Debug)]
+pub struct StreamChunk {
+    #[serde(default)]
+    pub choices: Vec<StreamChoice>,
+
+    #[serde(default)]
+    pub error: Option<OpenAIError>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct StreamChoice {
+    #[serde(default)]
+    pub delta: Delta,
+
+    #[serde(default)]
+    pub finish_reason: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct Delta {
+    #[serde(default)]
+    pub content: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ResponsesResponse {
+    #[serde(default)]
+    pub output: Vec<ResponsesOutput>,
+
+    #[serde(default)]
+    pub incomplete_details: Option<IncompleteDetails>,
+
+    #[serde(default)]
+    pub usage: Option<ResponsesUsage>,
+
+    #[serde(default)]
+    pub error: Option<OpenAIError>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ResponsesUsage {
+    #[serde(default)]
+    pub input_tokens: u64,
+    #[serde(default)]
+    pub output_tokens: u64,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct IncompleteDetails {
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ResponsesOutput {
+    #[serde(rename = "type")]
+    pub output_type: String,
+
+    #[serde(default)]
+    pub content: Vec<ResponsesOutputContent>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ResponsesOutputContent {
+    #[serde(rename = "type")]
+    pub content_type: String,
+
+    #[serde(default)]
+    pub text: String,
+}
+
 //Client
 pub struct OpenAIClient {
     client: Client,
     url: String,
     model: String,
     api_key: Option<String>,
-    prompt: Option<String>,
+    api: OpenAIApi,
+    image_detail: ImageDetail,
+    stream: bool,
+    options: ClientOptions,
+    usage: UsageCounter,
 }
 
 impl OpenAIClient {
@@ -72,92 +251,589 @@ impl OpenAIClient {
         url: String,
         model: String,
         api_key: Option<String>,
-        prompt: Option<String>,
+        api: OpenAIApi,
+        image_detail: ImageDetail,
+        stream: bool,
+        options: ClientOptions,
     ) -> Self {
         Self {
             client: Client::new(),
             url,
             model,
             api_key,
-            prompt,
+            api,
+            image_detail,
+            stream,
+            options,
+            usage: UsageCounter::default(),
         }
     }
-}
 
-#[async_trait]
-impl AiProvider for OpenAIClient {
-    async fn send_request(&self, file_data: FileData) -> Result<String, NotedError> {
-        let url = format!("{}/v1/chat/completions", self.url);
-        let prompt = if let Some(custom_prompt) = &self.prompt {
-            custom_prompt.clone()
-        } else {
-            "The user has provided an image of handwritten notes. Your task is to accurately transcribe these notes into a well-structured Markdown file. Preserve the original hierarchy, including headings and lists. Use LaTeX for any mathematical equations that appear in the notes. The output should only be the markdown content.".to_string()
-        };
+    fn prompt(&self) -> String {
+        self.options.resolve_prompt(
+            "The user has provided an image of handwritten notes. Your task is to accurately transcribe these notes into a well-structured Markdown file. Preserve the original hierarchy, including headings and lists. Use LaTeX for any mathematical equations that appear in the notes. The output should only be the markdown content.",
+        )
+    }
+
+    async fn post(&self, url: &str, body: &impl Serialize) -> Result<Response, NotedError> {
+        self.options.pace().await;
+        let mut request = self.client.post(url);
+
+        if let Some(api_key) = &self.api_key {
+            request = request.header("Authorization", format!("Bearer {}", api_key));
+        }
+
+        request
+            .json(body)
+            .send()
+            .await
+            .map_err(|e| request_error(self.name(), url, e))
+    }
+
+    /// Builds the body of one chat completions request. With `partial` set,
+    /// the answer so far is replayed as the assistant's turn followed by a
+    /// request to continue it.
+    fn chat_request_body(&self, file_data: &FileData, partial: Option<String>) -> OpenAIRequest {
+        let prompt = self.prompt();
+
         let image_url = format!(
             "data:{};base64,{}",
             file_data.mime_type, file_data.encoded_data
         );
 
-        let request_body = OpenAIRequest {
-            model: self.model.clone(),
-            messages: vec![Message {
-                role: "user".to_string(),
-                content: vec![
-                    Content {
-                        content_type: "text".to_string(),
-                        text: Some(prompt),
-                        image_url: None,
-                    },
-                    Content {
-                        content_type: "image_url".to_string(),
-                        text: None,
-                        image_url: Some(Image { url: image_url }),
-                    },
-                ],
-            }],
-        };
+        let mut content = Vec::new();
+        if let Some(context) = &self.options.document_context {
+            content.push(Content {
+                content_type: "text".to_string(),
+                text: Some(context.clone()),
+                image_url: None,
+            });
+        }
+        content.push(Content {
+            content_type: "text".to_string(),
+            text: Some(prompt),
+            image_url: None,
+        });
+        content.push(Content {
+            content_type: "image_url".to_string(),
+            text: None,
+            image_url: Some(Image {
+                url: image_url,
+                detail: self.image_detail,
+            }),
+        });
 
-        let mut request = self.client.post(&url);
+        let mut messages = Vec::new();
+        if let Some(system_prompt) = &self.options.system_prompt {
+            messages.push(Message {
+                role: "system".to_string(),
+                content: vec![Content {
+                    content_type: "text".to_string(),
+                    text: Some(system_prompt.clone()),
+                    image_url: None,
+                }],
+            });
+        }
+        messages.push(Message {
+            role: "user".to_string(),
+            content,
+        });
+        if let Some(partial) = partial {
+            messages.push(Message {
+                role: "assistant".to_string(),
+                content: vec![Content {
+                    content_type: "text".to_string(),
+                    text: Some(partial),
+                    image_url: None,
+                }],
+            });
+            messages.push(Message {
+                role: "user".to_string(),
+                content: vec![Content {
+                    content_type: "text".to_string(),
+                    text: Some(CONTINUATION_PROMPT.to_string()),
+                    image_url: None,
+                }],
+            });
+        }
 
-        if let Some(api_key) = &self.api_key {
-            request = request.header("Authorization", format!("Bearer {}", api_key));
+        OpenAIRequest {
+            model: self.model.clone(),
+            messages,
+            stream: self.stream,
+            temperature: self.options.temperature,
+            seed: self.options.seed,
+            stop: self.options.stop_sequences.clone(),
         }
+    }
 
-        let response = request.json(&request_body).send().await?;
+    /// Sends one chat completions request, see [`Self::chat_request_body`].
+    async fn send_chat_completions(
+        &self,
+        file_data: &FileData,
+        partial: Option<String>,
+    ) -> Result<Answer, NotedError> {
+        let url = format!("{}/v1/chat/completions", self.url);
+        let request_body = self.chat_request_body(file_data, partial);
+        let response = self.post(&url, &request_body).await?;
 
         let status = response.status();
-        let response_body = response.text().await?;
-
         if status != StatusCode::OK {
-            let error_response: Result<OpenAIResponse, _> = serde_json::from_str(&response_body);
-            if let Ok(err_resp) = error_response {
-                if let Some(error) = err_resp.error {
-                    return Err(NotedError::ApiError(error.message));
+            let response_body = read_body(response).await?;
+            let message = serde_json::from_str::<OpenAIResponse>(&response_body)
+                .ok()
+                .and_then(|err_resp| err_resp.error)
+                .map(|error| error.message);
+            return Err(status_error(self.name(), status, message));
+        }
+
+        if self.stream {
+            return read_event_stream(response, |delta| {
+                if let Some(on_delta) = &self.options.on_delta {
+                    on_delta.call(delta);
                 }
-            }
-            return Err(NotedError::ApiError(format!(
-                "Received status code: {}",
-                status
-            )));
+            })
+            .await;
         }
 
-        let openai_response: OpenAIResponse = serde_json::from_str(&response_body)
-            .map_err(|e| NotedError::ResponseDecodeError(e.to_string()))?;
+        let response_body = read_body(response).await?;
+
+        let openai_response: OpenAIResponse = parse_body(&response_body)?;
 
         if let Some(error) = openai_response.error {
             return Err(NotedError::ApiError(error.message));
         }
+        if let Some(usage) = &openai_response.usage {
+            self.usage.record(TokenUsage {
+                input_tokens: usage.prompt_tokens,
+                output_tokens: usage.completion_tokens,
+            });
+        }
+
+        let choice = openai_response.choices.first();
+        Ok(Answer {
+            text: choice
+                .map(|c| c.message.content.clone())
+                .unwrap_or_default(),
+            truncated: choice.and_then(|c| c.finish_reason.as_deref()) == Some("length"),
+        })
+    }
+
+    /// Builds the body of one Responses API request, continuing `partial` the
+    /// same way as [`Self::chat_request_body`].
+    fn responses_request_body(
+        &self,
+        file_data: &FileData,
+        partial: Option<String>,
+    ) -> ResponsesRequest {
+        let image_url = format!(
+            "data:{};base64,{}",
+            file_data.mime_type, file_data.encoded_data
+        );
+
+        let mut content = Vec::new();
+        if let Some(context) = &self.options.document_context {
+            content.push(ResponsesInputContent::InputText {
+                text: context.clone(),
+            });
+        }
+        content.push(ResponsesInputContent::InputText {
+            text: self.prompt(),
+        });
+        content.push(ResponsesInputContent::InputImage {
+            image_url,
+            detail: self.image_detail,
+        });
+
+        let mut input = vec![ResponsesInput {
+            role: "user".to_string(),
+            content,
+        }];
+        if let Some(partial) = partial {
+            input.push(ResponsesInput {
+                role: "assistant".to_string(),
+                content: vec![ResponsesInputContent::OutputText { text: partial }],
+            });
+            input.push(ResponsesInput {
+                role: "user".to_string(),
+                content: vec![ResponsesInputContent::InputText {
+                    text: CONTINUATION_PROMPT.to_string(),
+                }],
+            });
+        }
+
+        ResponsesRequest {
+            model: self.model.clone(),
+            instructions: self.options.system_prompt.clone(),
+            input,
+            temperature: self.options.temperature,
+        }
+    }
+
+    /// Sends one Responses API request, see [`Self::responses_request_body`].
+    async fn send_responses(
+        &self,
+        file_data: &FileData,
+        partial: Option<String>,
+    ) -> Result<Answer, NotedError> {
+        let url = format!("{}/v1/responses", self.url);
+        let request_body = self.responses_request_body(file_data, partial);
+        let response = self.post(&url, &request_body).await?;
+
+        let status = response.status();
+        let response_body = read_body(response).await?;
+
+        if status != StatusCode::OK {
+            let message = serde_json::from_str::<ResponsesResponse>(&response_body)
+                .ok()
+                .and_then(|err_resp| err_resp.error)
+                .map(|error| error.message);
+            return Err(status_error(self.name(), status, message));
+        }
+
+        let responses_response: ResponsesResponse = parse_body(&response_body)?;
+
+        if let Some(error) = responses_response.error {
+            return Err(NotedError::ApiError(error.message));
+        }
+        if let Some(usage) = &responses_response.usage {
+            self.usage.record(TokenUsage {
+                input_tokens: usage.input_tokens,
+                output_tokens: usage.output_tokens,
+            });
+        }
+
+        let text: String = responses_response
+            .output
+            .iter()
+            .filter(|output| output.output_type == "message")
+            .flat_map(|output| &output.content)
+            .filter(|content| content.content_type == "output_text")
+            .map(|content| content.text.as_str())
+            .collect();
+
+        Ok(Answer {
+            text,
+            truncated: responses_response
+                .incomplete_details
+                .and_then(|details| details.reason)
+                .as_deref()
+                == Some("max_output_tokens"),
+        })
+    }
+}
+
+/// Collects the `delta.content` of every chunk in a chat completions
+/// Server-Sent Events stream, up to the `[DONE]` sentinel, passing each piece
+/// to `on_delta` as it arrives.
+async fn read_event_stream(
+    mut response: Response,
+    mut on_delta: impl FnMut(&str),
+) -> Result<Answer, NotedError> {
+    let mut answer = Answer {
+        text: String::new(),
+        truncated: false,
+    };
+    let mut pending: Vec<u8> = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        if read_event_chunk(&chunk, &mut pending, &mut answer, &mut on_delta)? {
+            return Ok(answer);
+        }
+    }
+    read_event_line(&decode_utf8(pending)?, &mut answer, &mut on_delta)?;
+    Ok(answer)
+}
+
+/// Reads the lines `chunk` completes, keeping the rest in `pending` for the
+/// next chunk. Only complete lines are parsed, so a chunk boundary never
+/// splits an event or a multi-byte character. Returns `true` once the stream
+/// is done.
+fn read_event_chunk(
+    chunk: &[u8],
+    pending: &mut Vec<u8>,
+    answer: &mut Answer,
+    on_delta: &mut impl FnMut(&str),
+) -> Result<bool, NotedError> {
+    pending.extend_from_slice(chunk);
+    while let Some(end) = pending.iter().position(|byte| *byte == b'\n') {
+        let line: Vec<u8> = pending.drain(..=end).collect();
+        if read_event_line(&decode_utf8(line)?, answer, on_delta)? {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Appends the content of one `data:` line to `answer` and passes it to
+/// `on_delta`. Returns `true` once the stream is done. Other lines, like
+/// comments and `event:` fields, are skipped; data that isn't a valid chunk is
+/// an error.
+fn read_event_line(
+    line: &str,
+    answer: &mut Answer,
+    on_delta: &mut impl FnMut(&str),
+) -> Result<bool, NotedError> {
+    let Some(data) = line.trim().strip_prefix("data:") else {
+        return Ok(false);
+    };
+    let data = data.trim();
+    if data == "[DONE]" {
+        return Ok(true);
+    }
+    let chunk: StreamChunk = parse_body(data)?;
+    if let Some(error) = chunk.error {
+        return Err(NotedError::ApiError(error.message));
+    }
+    for choice in chunk.choices {
+        if let Some(content) = choice.delta.content {
+            on_delta(&content);
+            answer.text.push_str(&content);
+        }
+        if choice.finish_reason.as_deref() == Some("length") {
+            answer.truncated = true;
+        }
+    }
+    Ok(false)
+}
+
+#[async_trait]
+impl AiProvider for OpenAIClient {
+    async fn send_request(&self, file_data: FileData) -> Result<String, NotedError> {
+        let markdown = collect_answer(self.options.auto_continue, |partial| async {
+            match self.api {
+                OpenAIApi::ChatCompletions => self.send_chat_completions(&file_data, partial).await,
+                OpenAIApi::Responses => self.send_responses(&file_data, partial).await,
+            }
+        })
+        .await?;
+        Ok(clean_markdown(&markdown))
+    }
+
+    fn name(&self) -> &'static str {
+        "openai"
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn supports_pdf(&self) -> bool {
+        false
+    }
+
+    // Only chat completions take a seed; the Responses API has none.
+    fn supports_seed(&self) -> bool {
+        self.api == OpenAIApi::ChatCompletions
+    }
+
+    // Nor does it take stop sequences.
+    fn supports_stop_sequences(&self) -> bool {
+        self.api == OpenAIApi::ChatCompletions
+    }
+
+    // Streamed chat completions carry no usage, so only the other modes count.
+    fn take_usage(&self) -> Option<TokenUsage> {
+        self.usage.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client(api: OpenAIApi, options: ClientOptions) -> OpenAIClient {
+        OpenAIClient::new(
+            "http://localhost".to_string(),
+            "gpt".to_string(),
+            None,
+            api,
+            ImageDetail::default(),
+            false,
+            options,
+        )
+    }
+
+    fn file_data() -> FileData {
+        FileData::new(b"png", "image/png".to_string())
+    }
+
+    fn chat_body(options: ClientOptions) -> serde_json::Value {
+        let client = client(OpenAIApi::ChatCompletions, options);
+        serde_json::to_value(client.chat_request_body(&file_data(), None)).unwrap()
+    }
+
+    fn responses_body(options: ClientOptions) -> serde_json::Value {
+        let client = client(OpenAIApi::Responses, options);
+        serde_json::to_value(client.responses_request_body(&file_data(), None)).unwrap()
+    }
+
+    fn stop_options() -> ClientOptions {
+        ClientOptions {
+            stop_sequences: vec!["</notes>".to_string()],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn sends_the_system_prompt() {
+        let options = || ClientOptions {
+            system_prompt: Some("You transcribe lecture notes.".to_string()),
+            ..Default::default()
+        };
+
+        let body = chat_body(options());
+        let messages = body["messages"].as_array().unwrap();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0]["role"], "system");
+        assert_eq!(
+            messages[0]["content"][0]["text"],
+            "You transcribe lecture notes."
+        );
+        assert_eq!(messages[1]["role"], "user");
+        assert_eq!(
+            chat_body(ClientOptions::default())["messages"]
+                .as_array()
+                .unwrap()
+                .len(),
+            1
+        );
+
+        let body = responses_body(options());
+        assert_eq!(body["instructions"], "You transcribe lecture notes.");
+        assert!(
+            responses_body(ClientOptions::default())
+                .get("instructions")
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn sends_stop_sequences_to_chat_completions() {
+        assert_eq!(
+            chat_body(stop_options())["stop"],
+            serde_json::json!(["</notes>"])
+        );
+        assert!(chat_body(ClientOptions::default()).get("stop").is_none());
+    }
+
+    #[test]
+    fn only_chat_completions_take_stop_sequences() {
+        assert!(client(OpenAIApi::ChatCompletions, stop_options()).supports_stop_sequences());
+        assert!(!client(OpenAIApi::Responses, stop_options()).supports_stop_sequences());
+        assert!(responses_body(stop_options()).get("stop").is_none());
+    }
+
+    #[test]
+    fn sets_the_image_detail() {
+        let assert_detail = |body: &serde_json::Value, detail| {
+            assert_eq!(body["messages"][0]["content"][1]["type"], "image_url");
+            assert_eq!(
+                body["messages"][0]["content"][1]["image_url"]["detail"],
+                detail
+            );
+            assert!(
+                body["messages"][0]["content"][1]["image_url"]["url"]
+                    .as_str()
+                    .unwrap()
+                    .starts_with("data:image/png;base64,")
+            );
+        };
+        assert_detail(&chat_body(ClientOptions::default()), "auto");
+
+        let mut high = client(OpenAIApi::ChatCompletions, ClientOptions::default());
+        high.image_detail = ImageDetail::High;
+        let body = serde_json::to_value(high.chat_request_body(&file_data(), None)).unwrap();
+        assert_detail(&body, "high");
+
+        let mut low = client(OpenAIApi::Responses, ClientOptions::default());
+        low.image_detail = ImageDetail::Low;
+        let body = serde_json::to_value(low.responses_request_body(&file_data(), None)).unwrap();
+        assert_eq!(body["input"][0]["content"][1]["type"], "input_image");
+        assert_eq!(body["input"][0]["content"][1]["detail"], "low");
+    }
+
+    fn empty_answer() -> Answer {
+        Answer {
+            text: String::new(),
+            truncated: false,
+        }
+    }
+
+    #[test]
+    fn reads_stream_events() {
+        let mut answer = empty_answer();
+        let mut deltas = Vec::new();
+        let mut on_delta = |delta: &str| deltas.push(delta.to_string());
+
+        let line = r##"data: {"choices":[{"delta":{"content":"# Wa"}}]}"##;
+        assert!(!read_event_line(line, &mut answer, &mut on_delta).unwrap());
+        let line = r#"data: {"choices":[{"delta":{"content":"ves"},"finish_reason":"length"}]}"#;
+        assert!(!read_event_line(line, &mut answer, &mut on_delta).unwrap());
+        assert!(!read_event_line(": keep-alive", &mut answer, &mut on_delta).unwrap());
+        assert!(!read_event_line("", &mut answer, &mut on_delta).unwrap());
+        assert!(read_event_line("data: [DONE]", &mut answer, &mut on_delta).unwrap());
+
+        assert_eq!(answer.text, "# Waves");
+        assert!(answer.truncated);
+        assert_eq!(deltas, ["# Wa", "ves"]);
+    }
+
+    #[test]
+    fn reports_error_and_invalid_stream_events() {
+        let line = r#"data: {"error":{"message":"model overloaded"}}"#;
+        match read_event_line(line, &mut empty_answer(), &mut |_| {}) {
+            Err(NotedError::ApiError(message)) => assert_eq!(message, "model overloaded"),
+            other => panic!("expected ApiError, got {:?}", other.map(|_| ())),
+        }
+
+        let line = r#"data: {"choices":[{"delta":"#;
+        assert!(matches!(
+            read_event_line(line, &mut empty_answer(), &mut |_| {}),
+            Err(NotedError::UnexpectedResponseBody { .. })
+        ));
+    }
+
+    #[test]
+    fn reads_an_event_split_across_chunks() {
+        let stream = "data: {\"choices\":[{\"delta\":{\"content\":\"Grüße\"}}]}\n\ndata: [DONE]\n";
+        // Split inside the event, and inside the two bytes of `ü`.
+        let split = stream.find('ü').unwrap() + 1;
+        let (first, second) = stream.as_bytes().split_at(split);
+
+        let mut answer = empty_answer();
+        let mut pending = Vec::new();
+        assert!(!read_event_chunk(first, &mut pending, &mut answer, &mut |_| {}).unwrap());
+        assert_eq!(answer.text, "");
+        assert!(read_event_chunk(second, &mut pending, &mut answer, &mut |_| {}).unwrap());
+        assert_eq!(answer.text, "Grüße");
+    }
+
+    #[test]
+    fn parses_usage() {
+        let response: OpenAIResponse = parse_body(
+            r#"{"choices":[{"message":{"role":"assistant","content":"Notes"}}],
+                "usage":{"prompt_tokens":800,"completion_tokens":120,"total_tokens":920}}"#,
+        )
+        .unwrap();
+        let usage = response.usage.unwrap();
+        assert_eq!((usage.prompt_tokens, usage.completion_tokens), (800, 120));
+
+        let response: OpenAIResponse =
+            parse_body(r#"{"choices":[{"message":{"content":"Notes"}}]}"#).unwrap();
+        assert!(response.usage.is_none());
+    }
 
-        let markdown_text = openai_response
-            .choices
-            .first()
-            .map(|c| c.message.content.as_str())
-            .unwrap_or("");
+    #[test]
+    fn reads_message_content_as_a_string_or_parts() {
+        let message: ResponseMessage = parse_body(r##"{"content":"# Notes"}"##).unwrap();
+        assert_eq!(message.content, "# Notes");
 
-        let cleaned_markdown = markdown_text
-            .trim_start_matches("```markdown\n")
-            .trim_end_matches("```");
+        let message: ResponseMessage = parse_body(
+            r##"{"content":[{"type":"text","text":"# Notes"},{"type":"image"},{"type":"text","text":"\n\nBody"}]}"##,
+        )
+        .unwrap();
+        assert_eq!(message.content, "# Notes\n\nBody");
 
-        Ok(cleaned_markdown.to_string())
+        assert!(parse_body::<ResponseMessage>(r#"{"content":42}"#).is_err());
     }
 }
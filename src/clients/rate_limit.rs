@@ -0,0 +1,238 @@
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use reqwest::header::HeaderMap;
+
+/// Longest a run waits for a rate limit to reset, so a reset time that is
+/// misread or far off doesn't stall it indefinitely.
+const MAX_PACING_DELAY: Duration = Duration::from_secs(15 * 60);
+
+/// Reset values from this many seconds up are Unix timestamps rather than a
+/// number of seconds to wait (this is September 2001).
+const EPOCH_THRESHOLD_SECS: f64 = 1_000_000_000.0;
+
+/// The request budget a provider advertised in its last response headers.
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimit {
+    pub remaining: u64,
+    pub reset_at: Instant,
+}
+
+impl RateLimit {
+    /// Returns how long to wait before the next request when the budget is
+    /// used up, at most [`MAX_PACING_DELAY`], or `None` if it is safe to send
+    /// right away.
+    pub fn pacing_delay(&self) -> Option<Duration> {
+        if self.remaining > 0 {
+            return None;
+        }
+        let delay = self
+            .reset_at
+            .saturating_duration_since(Instant::now())
+            .min(MAX_PACING_DELAY);
+        (!delay.is_zero()).then_some(delay)
+    }
+}
+
+/// Reads the request rate-limit headers sent by Anthropic
+/// (`anthropic-ratelimit-requests-*`) or the common `x-ratelimit-*` family,
+/// falling back to `retry-after` for the reset time.
+pub fn parse_rate_limit_headers(headers: &HeaderMap) -> Option<RateLimit> {
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+
+    let remaining = header("anthropic-ratelimit-requests-remaining")
+        .or_else(|| header("x-ratelimit-remaining-requests"))
+        .or_else(|| header("x-ratelimit-remaining"))
+        .and_then(|value| value.trim().parse::<u64>().ok())?;
+
+    let reset_after = header("anthropic-ratelimit-requests-reset")
+        .and_then(parse_reset_timestamp)
+        .or_else(|| header("x-ratelimit-reset-requests").and_then(parse_reset_duration))
+        .or_else(|| header("x-ratelimit-reset").and_then(parse_reset_duration))
+        .or_else(|| header("retry-after").and_then(parse_reset_duration))
+        .unwrap_or_default();
+
+    Some(RateLimit {
+        remaining,
+        reset_at: Instant::now() + reset_after.min(MAX_PACING_DELAY),
+    })
+}
+
+/// Parses an RFC 3339 timestamp into the time left until it is reached.
+fn parse_reset_timestamp(value: &str) -> Option<Duration> {
+    let reset = chrono::DateTime::parse_from_rfc3339(value.trim()).ok()?;
+    let delta = reset.with_timezone(&chrono::Utc) - chrono::Utc::now();
+    Some(delta.to_std().unwrap_or_default())
+}
+
+/// Parses plain seconds (`"30"`, `"1.5"`), a Go-style duration such as
+/// `"6m0s"` or `"250ms"`, or a Unix timestamp (`"1760623200"`), which some
+/// providers send in `x-ratelimit-reset`.
+fn parse_reset_duration(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<f64>() {
+        if seconds >= EPOCH_THRESHOLD_SECS {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?;
+            let reset = Duration::try_from_secs_f64(seconds).ok()?;
+            return Some(reset.saturating_sub(now));
+        }
+        return Duration::try_from_secs_f64(seconds).ok();
+    }
+
+    let mut total = Duration::ZERO;
+    let mut number = String::new();
+    let mut chars = value.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c.is_ascii_digit() || c == '.' {
+            number.push(c);
+            continue;
+        }
+        let amount: f64 = number.parse().ok()?;
+        number.clear();
+        let seconds = match c {
+            'h' => amount * 3600.0,
+            'm' if chars.peek() == Some(&'s') => {
+                chars.next();
+                amount / 1000.0
+            }
+            'm' => amount * 60.0,
+            's' => amount,
+            _ => return None,
+        };
+        total += Duration::try_from_secs_f64(seconds).ok()?;
+    }
+    number.is_empty().then_some(total)
+}
+
+/// Spaces requests evenly so that no more than a set number are sent per
+/// minute. Shared between clients, so every request of a run counts against
+/// the same budget.
+#[derive(Debug)]
+pub struct RequestPacer {
+    interval: Duration,
+    next_slot: tokio::sync::Mutex<Option<Instant>>,
+}
+
+impl RequestPacer {
+    pub fn per_minute(requests: u32) -> Self {
+        Self {
+            interval: Duration::from_secs(60) / requests.max(1),
+            next_slot: tokio::sync::Mutex::new(None),
+        }
+    }
+
+    /// Waits until the next request may be sent and reserves that slot.
+    pub async fn wait(&self) {
+        let mut next_slot = self.next_slot.lock().await;
+        let now = Instant::now();
+        let slot = next_slot.map_or(now, |slot| slot.max(now));
+        *next_slot = Some(slot + self.interval);
+        drop(next_slot);
+        tokio::time::sleep_until(slot.into()).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::{HeaderName, HeaderValue};
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(name, value)| {
+                (
+                    HeaderName::from_static(name),
+                    HeaderValue::from_str(value).unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    fn seconds_until(limit: &RateLimit) -> u64 {
+        limit
+            .reset_at
+            .saturating_duration_since(Instant::now())
+            .as_secs_f64()
+            .round() as u64
+    }
+
+    #[test]
+    fn parses_anthropic_headers() {
+        let reset = (chrono::Utc::now() + chrono::Duration::seconds(30)).to_rfc3339();
+        let limit = parse_rate_limit_headers(&headers(&[
+            ("anthropic-ratelimit-requests-remaining", "0"),
+            ("anthropic-ratelimit-requests-reset", &reset),
+        ]))
+        .unwrap();
+        assert_eq!(limit.remaining, 0);
+        assert_eq!(seconds_until(&limit), 30);
+        assert!(limit.pacing_delay().is_some());
+    }
+
+    #[test]
+    fn parses_openai_style_headers() {
+        let limit = parse_rate_limit_headers(&headers(&[
+            ("x-ratelimit-remaining-requests", "12"),
+            ("x-ratelimit-reset-requests", "1m30s"),
+        ]))
+        .unwrap();
+        assert_eq!(limit.remaining, 12);
+        assert_eq!(seconds_until(&limit), 90);
+        assert_eq!(limit.pacing_delay(), None);
+    }
+
+    #[test]
+    fn reads_an_epoch_reset_as_a_timestamp() {
+        let reset = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+            + 20;
+        let limit = parse_rate_limit_headers(&headers(&[
+            ("x-ratelimit-remaining", "0"),
+            ("x-ratelimit-reset", &reset.to_string()),
+        ]))
+        .unwrap();
+        assert!((19..=20).contains(&seconds_until(&limit)));
+    }
+
+    #[test]
+    fn caps_the_wait_for_a_far_off_reset() {
+        let limit = parse_rate_limit_headers(&headers(&[
+            ("x-ratelimit-remaining", "0"),
+            ("retry-after", "86400"),
+        ]))
+        .unwrap();
+        assert!(limit.pacing_delay().unwrap() <= MAX_PACING_DELAY);
+    }
+
+    #[test]
+    fn ignores_responses_without_a_remaining_count() {
+        assert!(parse_rate_limit_headers(&headers(&[("retry-after", "5")])).is_none());
+    }
+
+    #[tokio::test]
+    async fn spaces_requests_by_the_interval() {
+        let pacer = RequestPacer::per_minute(600);
+        let start = Instant::now();
+        for _ in 0..3 {
+            pacer.wait().await;
+        }
+        // The first request goes out right away, the next two 100ms apart.
+        assert!(start.elapsed() >= Duration::from_millis(200));
+    }
+
+    #[test]
+    fn parses_go_durations() {
+        assert_eq!(parse_reset_duration("6m0s"), Some(Duration::from_secs(360)));
+        assert_eq!(
+            parse_reset_duration("250ms"),
+            Some(Duration::from_millis(250))
+        );
+        assert_eq!(
+            parse_reset_duration("1.5"),
+            Some(Duration::from_millis(1500))
+        );
+        assert_eq!(parse_reset_duration("soon"), None);
+    }
+}
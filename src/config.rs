@@ -1,7 +1,15 @@
 use crate::error::NotedError;
+use crate::file_utils;
 use directories::ProjectDirs;
 use serde::{Deserialize, Serialize};
-use std::{fs, path::PathBuf};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Names of the AI providers a config can select.
+pub const PROVIDERS: [&str; 4] = ["gemini", "claude", "ollama", "openai"];
 
 #[derive(Serialize, Deserialize, Debug, Default)]
 pub struct Config {
@@ -11,9 +19,67 @@ pub struct Config {
     pub claude: Option<ClaudeConfig>,
     pub openai: Option<OpenAIConfig>,
     pub notion: Option<NotionConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub images: Option<ImagesConfig>,
+    /// Rules that send some files to another provider than the active one.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub routes: Vec<RouteConfig>,
+    /// Request settings keyed by provider, or `default` for every provider
+    /// without an entry of its own.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub requests: BTreeMap<String, RequestConfig>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Default)]
+/// Request settings that `--system-prompt` and `--stop` override.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct RequestConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system_prompt: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stop_sequences: Vec<String>,
+}
+
+/// Compression of the images notedmd encodes itself; see
+/// [`crate::image_utils::ImageEncoding`].
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy)]
+pub struct ImagesConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jpeg_quality: Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub png_compression: Option<u8>,
+}
+
+/// Sends the files it matches to `provider`. A file matches when every
+/// condition that is set holds; a rule without conditions matches everything.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RouteConfig {
+    pub provider: String,
+    /// Extensions or mime types, e.g. `["pdf"]`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub file_types: Vec<String>,
+    /// Smallest page count; images count as one page.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_pages: Option<usize>,
+    /// Smallest file size in megabytes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_size_mb: Option<f64>,
+}
+
+impl RouteConfig {
+    pub fn matches(&self, mime_type: &str, size: u64, pages: usize) -> bool {
+        let type_matches = self.file_types.is_empty()
+            || self.file_types.iter().any(|file_type| {
+                file_utils::parse_file_type(file_type).is_ok_and(|mime| mime == mime_type)
+            });
+        type_matches
+            && self.min_pages.is_none_or(|min_pages| pages >= min_pages)
+            && self
+                .min_size_mb
+                .is_none_or(|min_size| size as f64 >= min_size * 1024.0 * 1024.0)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
 pub struct NotionConfig {
     pub api_key: String,
     pub database_id: String,
@@ -21,6 +87,32 @@ pub struct NotionConfig {
     pub title_property_name: String,
     #[serde(default)]
     pub properties: Vec<NotionPropertyConfig>,
+    /// Rich text property that stores the source file of each page, used to
+    /// recognize pages that were already uploaded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_property_name: Option<String>,
+    /// Select property that `convert --detect-language` fills with the
+    /// language of each page.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language_property_name: Option<String>,
+    /// Value of the `Notion-Version` header sent with every request.
+    #[serde(default = "default_notion_api_version")]
+    pub api_version: String,
+    /// Replaces `https://api.notion.com`, e.g. for a proxy.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_url: Option<String>,
+}
+
+pub const DEFAULT_NOTION_API_VERSION: &str = "2022-06-28";
+
+/// Defaults offered by the setup wizard and used by `config --init`.
+pub const DEFAULT_CLAUDE_MODEL: &str = "claude-opus-4-20250514";
+pub const DEFAULT_OLLAMA_URL: &str = "http://localhost:11434";
+pub const DEFAULT_OPENAI_URL: &str = "http://localhost:1234";
+pub const DEFAULT_LOCAL_MODEL: &str = "gemma3:27b";
+
+fn default_notion_api_version() -> String {
+    DEFAULT_NOTION_API_VERSION.to_string()
 }
 
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
@@ -34,17 +126,41 @@ pub struct NotionPropertyConfig {
 pub struct ClaudeConfig {
     pub api_key: String,
     pub model: String,
+    /// Replaces `https://api.anthropic.com`, e.g. for a proxy or gateway.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_url: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Default)]
 pub struct GeminiConfig {
     pub api_key: String,
+    /// More keys used in turn with `api_key`, one per request, to spread the
+    /// quota.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub api_keys: Vec<String>,
+    /// Replaces `https://generativelanguage.googleapis.com`, e.g. for a
+    /// regional endpoint or a proxy.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_url: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Default)]
 pub struct OllamaConfig {
     pub url: String,
     pub model: String,
+    #[serde(default)]
+    pub api: OllamaApi,
+}
+
+/// Which Ollama endpoint the client talks to.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OllamaApi {
+    /// `/api/generate`, a single prompt with images attached.
+    #[default]
+    Generate,
+    /// `/api/chat`, a list of messages; recommended for newer vision models.
+    Chat,
 }
 
 #[derive(Serialize, Deserialize, Debug, Default)]
@@ -52,6 +168,49 @@ pub struct OpenAIConfig {
     pub url: String,
     pub model: String,
     pub api_key: Option<String>,
+    #[serde(default)]
+    pub api: OpenAIApi,
+    /// Receive chat completions as a Server-Sent Events stream.
+    #[serde(default)]
+    pub stream: bool,
+}
+
+/// Which OpenAI endpoint the client talks to.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OpenAIApi {
+    /// `/v1/chat/completions`, supported by every OpenAI-compatible server.
+    #[default]
+    ChatCompletions,
+    /// `/v1/responses`, used by newer OpenAI models.
+    Responses,
+}
+
+/// Strips a trailing `/` or `/v1` from an OpenAI-compatible base URL, since the
+/// client appends `/v1/chat/completions` itself.
+pub fn normalize_openai_url(url: &str) -> String {
+    let url = url.trim().trim_end_matches('/');
+    url.strip_suffix("/v1")
+        .unwrap_or(url)
+        .trim_end_matches('/')
+        .to_string()
+}
+
+/// File name of the per-project config found by [`find_project_config`].
+pub const PROJECT_CONFIG_FILE_NAME: &str = ".notedmd.toml";
+
+/// Keys a discovered `.notedmd.toml` may not set, at any level. They decide
+/// where requests and API keys go, and such a file can come with any cloned
+/// repository.
+const UNTRUSTED_PROJECT_KEYS: &[&str] = &["api_key", "api_keys", "url", "base_url", "database_id"];
+
+/// Looks for a `.notedmd.toml` in `start` and then in each of its ancestors,
+/// returning the closest one.
+pub fn find_project_config(start: &Path) -> Option<PathBuf> {
+    start
+        .ancestors()
+        .map(|dir| dir.join(PROJECT_CONFIG_FILE_NAME))
+        .find(|path| path.is_file())
 }
 
 pub fn get_config_path() -> Option<PathBuf> {
@@ -64,17 +223,121 @@ pub fn get_config_path() -> Option<PathBuf> {
     })
 }
 
+/// Directory holding the prompt files: `<provider>.txt` for one provider and
+/// `default.txt` for all of them.
+pub fn get_prompts_dir() -> Option<PathBuf> {
+    get_config_path().and_then(|config_path| config_path.parent().map(|dir| dir.join("prompts")))
+}
+
+/// Returns the prompt saved in the prompts directory for `provider`, falling
+/// back to `default.txt`. Files that are empty or only whitespace are ignored.
+pub fn load_prompt_file(provider: &str) -> Result<Option<String>, NotedError> {
+    let Some(prompts_dir) = get_prompts_dir() else {
+        return Ok(None);
+    };
+    for name in [provider, "default"] {
+        let path = prompts_dir.join(format!("{}.txt", name));
+        if path.is_file() {
+            let prompt = fs::read_to_string(path)?;
+            if !prompt.trim().is_empty() {
+                return Ok(Some(prompt.trim().to_string()));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Directory for data notedmd can recreate, such as the manifests of
+/// interrupted directory runs.
+pub fn get_cache_dir() -> Option<PathBuf> {
+    ProjectDirs::from("com", "company", "notedmd").map(|dirs| dirs.cache_dir().to_path_buf())
+}
+
 impl Config {
     pub fn load() -> Result<Self, NotedError> {
-        if let Some(config_path) = get_config_path() {
-            if config_path.exists() {
-                let content = fs::read_to_string(config_path)?;
-                return Ok(toml::from_str(&content)?);
-            }
+        if let Some(config_path) = get_config_path()
+            && config_path.exists()
+        {
+            let content = fs::read_to_string(config_path)?;
+            return Ok(toml::from_str(&content)?);
         }
         Ok(Self::default())
     }
 
+    /// Loads the global config with the discovered `project` config and then
+    /// each file in `overrides` merged on top, in order. Tables are merged key
+    /// by key, so an override only needs the settings it changes. The result
+    /// is not saved.
+    pub fn load_with_overrides(
+        project: Option<&Path>,
+        overrides: &[PathBuf],
+    ) -> Result<Self, NotedError> {
+        if project.is_none() && overrides.is_empty() {
+            return Self::load();
+        }
+
+        let global = match get_config_path() {
+            Some(config_path) if config_path.exists() => {
+                toml::from_str(&fs::read_to_string(config_path)?)?
+            }
+            _ => toml::Table::new(),
+        };
+        merge_config_files(global, project, overrides)
+    }
+
+    /// Checks that every route names a known provider and file type, so a
+    /// typo fails the run up front instead of silently matching nothing.
+    pub fn check_routes(&self) -> Result<(), NotedError> {
+        for route in &self.routes {
+            if !PROVIDERS.contains(&route.provider.as_str()) {
+                return Err(NotedError::InvalidArgument(format!(
+                    "unknown provider '{}' in [[routes]], expected one of {}",
+                    route.provider,
+                    PROVIDERS.join(", ")
+                )));
+            }
+            for file_type in &route.file_types {
+                file_utils::parse_file_type(file_type)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The `[requests]` entry for `provider`, falling back to `default`.
+    pub fn request_config(&self, provider: &str) -> Option<&RequestConfig> {
+        self.requests
+            .get(provider)
+            .or_else(|| self.requests.get("default"))
+    }
+
+    /// Points `provider` at another base URL for this run. Providers without a
+    /// config section are left alone; building their client reports that.
+    pub fn override_base_url(&mut self, provider: &str, url: &str) {
+        match provider {
+            "gemini" => {
+                if let Some(gemini) = &mut self.gemini {
+                    gemini.base_url = Some(url.to_string());
+                }
+            }
+            "claude" => {
+                if let Some(claude) = &mut self.claude {
+                    claude.base_url = Some(url.to_string());
+                }
+            }
+            "ollama" => {
+                if let Some(ollama) = &mut self.ollama {
+                    ollama.url = url.trim_end_matches('/').to_string();
+                }
+            }
+            "openai" => {
+                if let Some(openai) = &mut self.openai {
+                    openai.url = normalize_openai_url(url);
+                }
+            }
+            _ => {}
+        }
+    }
+
     pub fn save(&self) -> Result<(), NotedError> {
         if let Some(config_path) = get_config_path() {
             let toml_string = toml::to_string_pretty(self)?;
@@ -83,3 +346,382 @@ impl Config {
         Ok(())
     }
 }
+
+/// A setting that differs between two configs.
+pub struct ConfigChange {
+    /// Dotted path of the setting, e.g. `claude.model`.
+    pub key: String,
+    pub old: Option<toml::Value>,
+    pub new: Option<toml::Value>,
+}
+
+impl ConfigChange {
+    /// API keys, whose values are hidden when the change is shown.
+    pub fn is_secret(&self) -> bool {
+        self.key
+            .rsplit('.')
+            .next()
+            .is_some_and(|name| name.starts_with("api_key"))
+    }
+
+    /// Changes or removes an existing setting, which `config --edit` asks
+    /// about before saving.
+    pub fn overwrites(&self) -> bool {
+        self.old.is_some()
+    }
+}
+
+/// Lists the settings `new` adds, changes or removes compared to `old`.
+/// Arrays, such as Notion's default properties, count as one setting.
+pub fn config_changes(old: &Config, new: &Config) -> Result<Vec<ConfigChange>, NotedError> {
+    let mut old_values = BTreeMap::new();
+    flatten_value(String::new(), toml::Value::try_from(old)?, &mut old_values);
+    let mut new_values = BTreeMap::new();
+    flatten_value(String::new(), toml::Value::try_from(new)?, &mut new_values);
+
+    let mut changes: Vec<ConfigChange> = Vec::new();
+    for (key, old) in &old_values {
+        let new = new_values.remove(key);
+        if new.as_ref() != Some(old) {
+            changes.push(ConfigChange {
+                key: key.clone(),
+                old: Some(old.clone()),
+                new,
+            });
+        }
+    }
+    changes.extend(new_values.into_iter().map(|(key, new)| ConfigChange {
+        key,
+        old: None,
+        new: Some(new),
+    }));
+    changes.sort_by(|a, b| a.key.cmp(&b.key));
+    Ok(changes)
+}
+
+fn flatten_value(key: String, value: toml::Value, values: &mut BTreeMap<String, toml::Value>) {
+    match value {
+        toml::Value::Table(table) => {
+            for (name, value) in table {
+                let key = if key.is_empty() {
+                    name
+                } else {
+                    format!("{}.{}", key, name)
+                };
+                flatten_value(key, value, values);
+            }
+        }
+        value => {
+            values.insert(key, value);
+        }
+    }
+}
+
+/// Merges the `project` config, which may not set any of
+/// [`UNTRUSTED_PROJECT_KEYS`], and then `overrides` over `global`.
+fn merge_config_files(
+    mut global: toml::Table,
+    project: Option<&Path>,
+    overrides: &[PathBuf],
+) -> Result<Config, NotedError> {
+    if let Some(path) = project {
+        let overlay: toml::Table = toml::from_str(&fs::read_to_string(path)?)?;
+        if let Some(key) = untrusted_key(&overlay) {
+            return Err(NotedError::UntrustedProjectConfig(path.to_path_buf(), key));
+        }
+        merge_tables(&mut global, overlay);
+    }
+    for path in overrides {
+        let overlay: toml::Table = toml::from_str(&fs::read_to_string(path)?)?;
+        merge_tables(&mut global, overlay);
+    }
+    Ok(toml::Value::Table(global).try_into()?)
+}
+
+/// Returns the dotted name of the first key of `table` that is one of
+/// [`UNTRUSTED_PROJECT_KEYS`], looking into nested tables and arrays of tables.
+fn untrusted_key(table: &toml::Table) -> Option<String> {
+    table.iter().find_map(|(key, value)| {
+        if UNTRUSTED_PROJECT_KEYS.contains(&key.as_str()) {
+            return Some(key.clone());
+        }
+        let nested = match value {
+            toml::Value::Table(nested) => untrusted_key(nested),
+            toml::Value::Array(items) => items.iter().find_map(|item| match item {
+                toml::Value::Table(nested) => untrusted_key(nested),
+                _ => None,
+            }),
+            _ => None,
+        };
+        nested.map(|nested| format!("{}.{}", key, nested))
+    })
+}
+
+fn merge_tables(base: &mut toml::Table, overlay: toml::Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base_table)), toml::Value::Table(overlay_table)) => {
+                merge_tables(base_table, overlay_table);
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TempDir;
+
+    fn global() -> toml::Table {
+        toml::from_str(
+            r#"
+            active_provider = "gemini"
+
+            [gemini]
+            api_key = "global-key"
+            model = "gemini-2.5-flash"
+
+            [claude]
+            api_key = "claude-key"
+            model = "claude-3-5-sonnet"
+            "#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn finds_the_closest_project_config() {
+        let tree = TempDir::new("config-discovery");
+        let outer = tree.write(".notedmd.toml", "active_provider = \"claude\"\n");
+        let nested = tree.path().join("notes/physics/scans");
+        fs::create_dir_all(&nested).unwrap();
+        assert_eq!(find_project_config(&nested), Some(outer));
+
+        let inner = tree.write("notes/.notedmd.toml", "active_provider = \"ollama\"\n");
+        assert_eq!(find_project_config(&nested), Some(inner));
+        assert_eq!(
+            find_project_config(tree.path()).as_deref(),
+            Some(tree.path().join(".notedmd.toml").as_path())
+        );
+    }
+
+    #[test]
+    fn merges_project_then_explicit_config_over_global() {
+        let tree = TempDir::new("config-merge");
+        let project = tree.write(
+            "notes/.notedmd.toml",
+            "active_provider = \"claude\"\n\n[claude]\nmodel = \"claude-sonnet-4\"\n",
+        );
+        let explicit = tree.write("physics.toml", "[claude]\nmodel = \"claude-opus-4\"\n");
+
+        let config = merge_config_files(global(), Some(&project), &[]).unwrap();
+        assert_eq!(config.active_provider.as_deref(), Some("claude"));
+        let claude = config.claude.unwrap();
+        assert_eq!(claude.model, "claude-sonnet-4");
+        // Keys the project file doesn't set come from the global config.
+        assert_eq!(claude.api_key, "claude-key");
+        assert_eq!(config.gemini.unwrap().api_key, "global-key");
+
+        let config = merge_config_files(global(), Some(&project), &[explicit]).unwrap();
+        assert_eq!(config.active_provider.as_deref(), Some("claude"));
+        assert_eq!(config.claude.unwrap().model, "claude-opus-4");
+    }
+
+    #[test]
+    fn refuses_endpoints_and_keys_in_a_project_config() {
+        let tree = TempDir::new("config-untrusted");
+        for (content, key) in [
+            (
+                "[gemini]\nbase_url = \"https://example.com\"\n",
+                "gemini.base_url",
+            ),
+            ("[claude]\napi_key = \"sk-other\"\n", "claude.api_key"),
+            ("[openai]\nurl = \"https://example.com\"\n", "openai.url"),
+            ("[gemini]\napi_keys = [\"a\", \"b\"]\n", "gemini.api_keys"),
+        ] {
+            let project = tree.write(".notedmd.toml", content);
+            match merge_config_files(global(), Some(&project), &[]) {
+                Err(NotedError::UntrustedProjectConfig(path, found)) => {
+                    assert_eq!(path, project);
+                    assert_eq!(found, key);
+                }
+                other => panic!(
+                    "expected {} to be refused, got {:?}",
+                    key,
+                    other.map(|_| ())
+                ),
+            }
+        }
+    }
+
+    #[test]
+    fn allows_endpoints_in_an_explicit_config() {
+        let tree = TempDir::new("config-explicit");
+        let explicit = tree.write(
+            "gateway.toml",
+            "[gemini]\nbase_url = \"https://example.com\"\n",
+        );
+        let config = merge_config_files(global(), None, &[explicit]).unwrap();
+        assert_eq!(
+            config.gemini.unwrap().base_url.as_deref(),
+            Some("https://example.com")
+        );
+    }
+
+    fn route(
+        file_types: &[&str],
+        min_pages: Option<usize>,
+        min_size_mb: Option<f64>,
+    ) -> RouteConfig {
+        RouteConfig {
+            provider: "claude".to_string(),
+            file_types: file_types
+                .iter()
+                .map(|file_type| file_type.to_string())
+                .collect(),
+            min_pages,
+            min_size_mb,
+        }
+    }
+
+    #[test]
+    fn route_matches_when_every_condition_holds() {
+        const MB: u64 = 1024 * 1024;
+        assert!(route(&[], None, None).matches("image/png", 10, 1));
+
+        let pdfs = route(&["pdf"], None, None);
+        assert!(pdfs.matches("application/pdf", 10, 1));
+        assert!(!pdfs.matches("image/png", 10, 1));
+        let images = route(&["image/png", "jpg"], None, None);
+        assert!(images.matches("image/png", 10, 1));
+        assert!(images.matches("image/jpeg", 10, 1));
+
+        let long_pdfs = route(&["pdf"], Some(10), None);
+        assert!(long_pdfs.matches("application/pdf", 10, 10));
+        assert!(!long_pdfs.matches("application/pdf", 10, 9));
+
+        let large = route(&[], None, Some(1.5));
+        assert!(large.matches("image/png", 2 * MB, 1));
+        assert!(!large.matches("image/png", MB, 1));
+    }
+
+    #[test]
+    fn check_routes_rejects_unknown_providers_and_types() {
+        let mut config = Config {
+            routes: vec![route(&["pdf", "image/jpeg"], None, None)],
+            ..Default::default()
+        };
+        assert!(config.check_routes().is_ok());
+
+        config.routes[0].provider = "mistral".to_string();
+        assert!(matches!(
+            config.check_routes(),
+            Err(NotedError::InvalidArgument(message)) if message.contains("'mistral'")
+        ));
+
+        config.routes = vec![route(&["webp"], None, None)];
+        assert!(matches!(
+            config.check_routes(),
+            Err(NotedError::UnsupportedFileType(_))
+        ));
+    }
+
+    #[test]
+    fn override_base_url_points_the_provider_elsewhere() {
+        let mut config: Config = global().try_into().unwrap();
+        config.override_base_url("gemini", "https://eu.example.com");
+        config.override_base_url("claude", "https://gateway.example.com/anthropic");
+        assert_eq!(
+            config.gemini.as_ref().unwrap().base_url.as_deref(),
+            Some("https://eu.example.com")
+        );
+        assert_eq!(
+            config.claude.as_ref().unwrap().base_url.as_deref(),
+            Some("https://gateway.example.com/anthropic")
+        );
+
+        // Providers without a section are left for building the client to
+        // report.
+        config.override_base_url("ollama", "http://gpu-box:11434/");
+        assert!(config.ollama.is_none());
+        config.ollama = Some(OllamaConfig {
+            url: DEFAULT_OLLAMA_URL.to_string(),
+            model: DEFAULT_LOCAL_MODEL.to_string(),
+            ..Default::default()
+        });
+        config.override_base_url("ollama", "http://gpu-box:11434/");
+        assert_eq!(config.ollama.unwrap().url, "http://gpu-box:11434");
+    }
+
+    #[test]
+    fn config_changes_lists_what_the_wizard_overwrites() {
+        let old: Config = global().try_into().unwrap();
+        let mut new: Config = global().try_into().unwrap();
+        assert!(config_changes(&old, &new).unwrap().is_empty());
+
+        // Adding a provider changes nothing that was set before.
+        new.ollama = Some(OllamaConfig {
+            url: DEFAULT_OLLAMA_URL.to_string(),
+            model: DEFAULT_LOCAL_MODEL.to_string(),
+            ..Default::default()
+        });
+        let changes = config_changes(&old, &new).unwrap();
+        assert_eq!(changes.len(), 3);
+        assert!(!changes.iter().any(ConfigChange::overwrites));
+
+        new.active_provider = Some("claude".to_string());
+        new.claude = Some(ClaudeConfig {
+            api_key: "new-key".to_string(),
+            model: "claude-3-5-sonnet".to_string(),
+            base_url: None,
+        });
+        new.gemini = None;
+        let changes: Vec<_> = config_changes(&old, &new)
+            .unwrap()
+            .into_iter()
+            .filter(ConfigChange::overwrites)
+            .collect();
+        let keys: Vec<_> = changes.iter().map(|change| change.key.as_str()).collect();
+        assert_eq!(
+            keys,
+            ["active_provider", "claude.api_key", "gemini.api_key"]
+        );
+        assert_eq!(changes[0].new, Some(toml::Value::from("claude")));
+        assert!(changes[1].is_secret());
+        assert!(!changes[0].is_secret());
+        assert_eq!(changes[2].new, None);
+    }
+
+    #[test]
+    fn notion_api_version_defaults_to_the_current_one() {
+        let section = "[notion]\napi_key = \"secret\"\ndatabase_id = \"db\"\n";
+        let config: Config = toml::from_str(section).unwrap();
+        assert_eq!(
+            config.notion.unwrap().api_version,
+            DEFAULT_NOTION_API_VERSION
+        );
+
+        let config: Config =
+            toml::from_str(&format!("{}api_version = \"2025-09-03\"\n", section)).unwrap();
+        assert_eq!(config.notion.unwrap().api_version, "2025-09-03");
+    }
+
+    #[test]
+    fn normalize_openai_url_strips_a_trailing_v1_and_slashes() {
+        for url in [
+            "http://localhost:1234",
+            "http://localhost:1234/",
+            "http://localhost:1234/v1",
+            " http://localhost:1234/v1/ ",
+        ] {
+            assert_eq!(normalize_openai_url(url), "http://localhost:1234");
+        }
+        assert_eq!(
+            normalize_openai_url("https://gateway.example/openai/v1"),
+            "https://gateway.example/openai"
+        );
+    }
+}
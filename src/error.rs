@@ -1,3 +1,4 @@
+use std::path::{Path, PathBuf};
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -15,7 +16,20 @@ pub enum NotedError {
     IoError(#[from] std::io::Error),
 
     #[error(" Network request failed: {0}")]
-    NetworkError(#[from] reqwest::Error),
+    NetworkError(reqwest::Error),
+
+    #[error(
+        " Could not connect to {1}: connection refused. Is the {0} server running at that address?"
+    )]
+    ConnectionRefused(String, String),
+
+    #[error(
+        " Could not resolve the host of {0}. Check the url in your configuration and your internet connection."
+    )]
+    DnsError(String),
+
+    #[error(" Secure connection to {0} failed: {1}")]
+    TlsError(String, String),
 
     #[error(" API key is invalid or missing. Please check your configuration.")]
     InvalidApiKey,
@@ -23,15 +37,72 @@ pub enum NotedError {
     #[error(" The AI provider returned an error: {0}")]
     ApiError(String),
 
+    #[error(
+        " The '{0}' provider requires payment: billing is not set up or the account is out of credit. Details: {1}"
+    )]
+    PaymentRequired(String, String),
+
+    #[error(
+        " The '{0}' provider refused the request: the API key lacks permission for this model or its quota is used up. Details: {1}"
+    )]
+    PermissionDenied(String, String),
+
+    #[error(
+        " The '{0}' provider is overloaded and still was after several retries. Try again in a few minutes."
+    )]
+    ProviderOverloaded(String),
+
     #[error(" Failed to decode API response: {0}")]
     ResponseDecodeError(String),
 
+    /// A success response whose body isn't what the provider's API returns,
+    /// e.g. an HTML page from a gateway. Keeps the body for `--verbose`.
+    #[error(" Failed to decode API response: {reason}")]
+    UnexpectedResponseBody { reason: String, body: String },
+
+    #[error(
+        " The response still could not be decoded after a retry: {0}. The API no longer answers in the format noted.md expects, or a proxy rewrites its responses; check the model and base_url, and rerun with --verbose to see the body."
+    )]
+    ResponseFormatMismatch(String),
+
     #[error(" Could not determine the file name for the path: {0}")]
     FileNameError(String),
 
     #[error(" File type not supported: {0}")]
     UnsupportedFileType(String),
 
+    #[error(" Failed to process PDF: {0}")]
+    PdfError(String),
+
+    #[error(" Failed to process image: {0}")]
+    ImageError(String),
+
+    #[error(
+        " The '{0}' provider cannot read PDF files directly. Convert the pages to images or switch to 'gemini' or 'claude'."
+    )]
+    PdfNotSupported(String),
+
+    #[error(
+        " The file is larger than Gemini's {0} MB limit for inline data and could not be downscaled to fit. Split large PDFs into smaller files."
+    )]
+    InlineDataTooLarge(usize),
+
+    #[error(" The AI provider returned no content for '{0}'.")]
+    EmptyResponse(String),
+
+    #[error(
+        " Several files would be saved to the same markdown file, so some results would be overwritten. Rename the inputs so their names differ:\n{0}"
+    )]
+    OutputCollision(String),
+
+    #[error(" Invalid argument: {0}")]
+    InvalidArgument(String),
+
+    #[error(
+        " The project config {0:?} sets '{1}', which a discovered .notedmd.toml may not set: it decides where requests and API keys are sent. Move it to your global config, pass the file explicitly with --provider-config, or use --no-project-config."
+    )]
+    UntrustedProjectConfig(PathBuf, String),
+
     #[error(" Ollama is not configured properly. Please run 'notedmd config --edit' to set it up.")]
     OllamaNotConfigured,
 
@@ -54,4 +125,49 @@ pub enum NotedError {
 
     #[error(" Dialoguer error: {0}")]
     DialoguerError(#[from] dialoguer::Error),
+
+    #[error(" {path}:{source}")]
+    InFile {
+        path: String,
+        source: Box<NotedError>,
+    },
+}
+
+/// Drops the request URL from the error, since its query string can hold an
+/// API key (Gemini's `?key=`).
+impl From<reqwest::Error> for NotedError {
+    fn from(error: reqwest::Error) -> Self {
+        NotedError::NetworkError(error.without_url())
+    }
+}
+
+impl NotedError {
+    /// Names the input file the error happened on, keeping the error itself
+    /// as the source.
+    pub fn in_file(self, path: &Path) -> Self {
+        NotedError::InFile {
+            path: path.display().to_string(),
+            source: Box::new(self),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error;
+
+    #[test]
+    fn in_file_names_the_file_and_keeps_the_error() {
+        let error = NotedError::PdfError("The PDF has no pages".to_string())
+            .in_file(Path::new("scans/week 3.pdf"));
+        assert_eq!(
+            error.to_string(),
+            " scans/week 3.pdf: Failed to process PDF: The PDF has no pages"
+        );
+        assert_eq!(
+            error.source().unwrap().to_string(),
+            " Failed to process PDF: The PDF has no pages"
+        );
+    }
 }
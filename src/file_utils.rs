@@ -1,21 +1,187 @@
-use crate::error::NotedError;
+use crate::{error::NotedError, pdf_utils};
 use base64::{Engine, engine::general_purpose};
-use std::{fs, path::Path};
+use std::{
+    collections::BTreeSet,
+    fs, io,
+    path::{Path, PathBuf},
+};
 
+/// File extensions accepted as input and the mime type sent for each.
+pub const SUPPORTED_FILE_TYPES: &[(&str, &str)] = &[
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("pdf", "application/pdf"),
+];
+
+/// Image formats that are not accepted directly but convert easily to PNG or
+/// JPEG.
+pub const CONVERTIBLE_FILE_TYPES: &[&str] = &["bmp", "gif", "tif", "tiff", "webp", "heic", "heif"];
+
+#[derive(Clone)]
 pub struct FileData {
     pub encoded_data: String,
     pub mime_type: String,
 }
 
-pub fn process_file(file_path: &str) -> Result<FileData, NotedError> {
-    let data = fs::read(file_path)?;
-    let encoded_data: String = general_purpose::STANDARD.encode(&data);
-    let mime_type = get_file_mime_type(file_path)?;
+impl FileData {
+    pub fn new(data: &[u8], mime_type: String) -> Self {
+        Self {
+            encoded_data: general_purpose::STANDARD.encode(data),
+            mime_type,
+        }
+    }
+}
+
+/// Reads a file for sampling, keeping only the first page of a PDF so a prompt
+/// can be tried out cheaply. `mime_type` overrides the type detected from the
+/// extension. Returns the data and its mime type.
+pub fn read_file_sample(
+    file_path: &str,
+    mime_type: Option<&str>,
+) -> Result<(Vec<u8>, String), NotedError> {
+    let mut data = fs::read(file_path)?;
+    let mime_type = match mime_type {
+        Some(mime_type) => mime_type.to_string(),
+        None => get_file_mime_type(file_path)?,
+    };
+    if mime_type == "application/pdf" {
+        data = pdf_utils::extract_first_page(&data)?;
+    }
+    Ok((data, mime_type))
+}
+
+/// Returns the supported files directly inside `dir`, sorted by path.
+pub fn collect_supported_files(dir: &Path) -> Result<Vec<PathBuf>, NotedError> {
+    let mut files: Vec<_> = fs::read_dir(dir)?
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let path = entry.path();
+            if path.is_file()
+                && let Some(path_str) = path.to_str()
+                && get_file_mime_type(path_str).is_ok()
+            {
+                return Some(path);
+            }
+            None
+        })
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+/// Extensions of the files in `dir` that are skipped because their type is not
+/// supported, sorted and without duplicates.
+pub fn unsupported_extensions(dir: &Path) -> Result<Vec<String>, NotedError> {
+    let extensions: BTreeSet<String> = fs::read_dir(dir)?
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.is_file()
+                && path
+                    .to_str()
+                    .is_some_and(|path_str| get_file_mime_type(path_str).is_err())
+        })
+        .filter_map(|path| {
+            path.extension()
+                .map(|ext| ext.to_string_lossy().into_owned())
+        })
+        .collect();
+    Ok(extensions.into_iter().collect())
+}
 
-    Ok(FileData {
-        encoded_data,
-        mime_type,
-    })
+/// Moves `source` into `dir`, creating it if needed, and returns the new path.
+/// An existing file of the same name is kept by numbering the moved one, and
+/// moves across file systems fall back to copy and delete.
+pub fn move_into_dir(source: &Path, dir: &Path) -> io::Result<PathBuf> {
+    fs::create_dir_all(dir)?;
+    let file_name = source
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"))?;
+    let mut destination = dir.join(file_name);
+    let stem = source.file_stem().unwrap_or(file_name).to_string_lossy();
+    let extension = source.extension().map(|ext| ext.to_string_lossy());
+    let mut counter = 1;
+    while destination.exists() {
+        let numbered = match &extension {
+            Some(ext) => format!("{}-{}.{}", stem, counter, ext),
+            None => format!("{}-{}", stem, counter),
+        };
+        destination = dir.join(numbered);
+        counter += 1;
+    }
+
+    match fs::rename(source, &destination) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            fs::copy(source, &destination)?;
+            fs::remove_file(source)?;
+        }
+        Err(e) => return Err(e),
+    }
+    Ok(destination)
+}
+
+/// Reads the prompt kept next to `source` in a sidecar file named after it
+/// with `.prompt` added (`diagram.png.prompt`). Blank files count as missing.
+pub fn sidecar_prompt(source: &Path) -> io::Result<Option<String>> {
+    let mut sidecar = source.as_os_str().to_owned();
+    sidecar.push(".prompt");
+    match fs::read_to_string(sidecar) {
+        Ok(prompt) if !prompt.trim().is_empty() => Ok(Some(prompt.trim().to_string())),
+        Ok(_) => Ok(None),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Reads the `--input-list` file: one path per line, skipping blank lines and
+/// `#` comments. Relative paths are taken from the working directory, like
+/// paths given on the command line. Returns the listed files that exist, in
+/// list order, and the listed paths that are not files.
+pub fn read_input_list(list: &Path) -> io::Result<(Vec<PathBuf>, Vec<PathBuf>)> {
+    let (files, missing) = fs::read_to_string(list)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(PathBuf::from)
+        .partition(|path| path.is_file());
+    Ok((files, missing))
+}
+
+/// Resolves a type given by the user, either an extension (`png`, `.png`) or a
+/// mime type (`image/png`), to the mime type of a supported file type.
+pub fn parse_file_type(value: &str) -> Result<String, NotedError> {
+    let value = value.trim().to_ascii_lowercase();
+    let extension = value.trim_start_matches('.');
+    SUPPORTED_FILE_TYPES
+        .iter()
+        .find(|(ext, mime_type)| *ext == extension || *mime_type == value)
+        .map(|(_, mime_type)| mime_type.to_string())
+        .ok_or(NotedError::UnsupportedFileType(value))
+}
+
+/// Deletes everything inside `dir`, keeping `dir` itself, and returns the
+/// number of bytes freed. A missing directory frees nothing.
+pub fn clear_dir(dir: &Path) -> io::Result<u64> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+    let mut freed = 0;
+    for entry in entries {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_dir() {
+            freed += clear_dir(&entry.path())?;
+            fs::remove_dir(entry.path())?;
+        } else {
+            freed += metadata.len();
+            fs::remove_file(entry.path())?;
+        }
+    }
+    Ok(freed)
 }
 
 pub fn get_file_mime_type(file_path: &str) -> Result<String, NotedError> {
@@ -24,11 +190,172 @@ pub fn get_file_mime_type(file_path: &str) -> Result<String, NotedError> {
         .and_then(|ext| ext.to_str());
 
     match file_extension {
-        Some("png") => Ok("image/png".to_string()),
-        Some("pdf") => Ok("application/pdf".to_string()),
-        Some("jpg") => Ok("image/jpeg".to_string()),
-        Some("jpeg") => Ok("image/jpeg".to_string()),
-        Some(ext) => Err(NotedError::UnsupportedFileType(ext.to_string())),
+        Some(ext) => SUPPORTED_FILE_TYPES
+            .iter()
+            .find(|(extension, _)| *extension == ext)
+            .map(|(_, mime_type)| mime_type.to_string())
+            .ok_or_else(|| NotedError::UnsupportedFileType(ext.to_string())),
         None => Err(NotedError::UnsupportedFileType("No extension".to_string())),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TempDir;
+
+    #[test]
+    fn sidecar_prompt_reads_the_prompt_next_to_the_file() {
+        let tree = TempDir::new("file-utils-sidecar");
+        let diagram = tree.write("diagram.png", "png");
+        assert_eq!(sidecar_prompt(&diagram).unwrap(), None);
+
+        tree.write("diagram.png.prompt", "  \n");
+        assert_eq!(sidecar_prompt(&diagram).unwrap(), None);
+
+        tree.write("diagram.png.prompt", "\nLabel every arrow.\n");
+        assert_eq!(
+            sidecar_prompt(&diagram).unwrap().as_deref(),
+            Some("Label every arrow.")
+        );
+        // Only the full file name counts, not the stem.
+        tree.write("notes.prompt", "Summarize.");
+        assert_eq!(
+            sidecar_prompt(&tree.write("notes.png", "png")).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn read_input_list_skips_blank_lines_and_comments() {
+        let tree = TempDir::new("file-utils-input-list");
+        let lecture = tree.write("scans/lecture.pdf", "pdf");
+        let page = tree.write("scans/page 2.png", "png");
+        let list = tree.write(
+            "files.txt",
+            format!(
+                "# Week 3\n{}\n\n   \n  {}  \nCargo.toml\n{}\n# {}\n",
+                page.display(),
+                lecture.display(),
+                tree.path().join("scans/missing.png").display(),
+                tree.path().join("scans/skipped.png").display(),
+            ),
+        );
+
+        let (files, missing) = read_input_list(&list).unwrap();
+        assert_eq!(files, [page, lecture, PathBuf::from("Cargo.toml")]);
+        assert_eq!(missing, [tree.path().join("scans/missing.png")]);
+        // A directory is not a file to convert.
+        fs::write(&list, format!("{}\n", tree.path().join("scans").display())).unwrap();
+        assert_eq!(read_input_list(&list).unwrap().1.len(), 1);
+
+        assert!(read_input_list(&tree.path().join("none.txt")).is_err());
+    }
+
+    #[test]
+    fn parse_file_type_accepts_extensions_and_mime_types() {
+        for value in ["png", ".PNG", "image/png", " image/PNG "] {
+            assert_eq!(parse_file_type(value).unwrap(), "image/png");
+        }
+        assert_eq!(parse_file_type("jpg").unwrap(), "image/jpeg");
+        assert_eq!(
+            parse_file_type("application/pdf").unwrap(),
+            "application/pdf"
+        );
+        match parse_file_type("webp") {
+            Err(NotedError::UnsupportedFileType(value)) => assert_eq!(value, "webp"),
+            other => panic!("expected UnsupportedFileType, got {:?}", other),
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn clear_dir_removes_the_cache_but_not_what_links_point_to() {
+        let tree = TempDir::new("file-utils-clear");
+        let cache = tree.path().join("cache");
+        tree.write("cache/responses/a.json", "12345");
+        tree.write("cache/manifest.json", "123");
+        let kept = tree.write("notes/page.md", "# Page");
+        let link = cache.join("notes");
+        std::os::unix::fs::symlink(tree.path().join("notes"), &link).unwrap();
+        // The link itself is removed and counted, not followed.
+        let link_size = fs::symlink_metadata(&link).unwrap().len();
+
+        assert_eq!(clear_dir(&cache).unwrap(), 8 + link_size);
+        assert!(cache.is_dir());
+        assert_eq!(fs::read_dir(&cache).unwrap().count(), 0);
+        assert_eq!(fs::read_to_string(kept).unwrap(), "# Page");
+
+        assert_eq!(clear_dir(&tree.path().join("missing")).unwrap(), 0);
+    }
+
+    #[test]
+    fn move_into_dir_numbers_names_already_taken() {
+        let tree = TempDir::new("file-utils-move");
+        let done = tree.path().join("done");
+        tree.write("done/page.png", "earlier");
+
+        let source = tree.write("inbox/page.png", "scan");
+        let moved = move_into_dir(&source, &done).unwrap();
+        assert_eq!(moved, done.join("page-1.png"));
+        assert!(!source.exists());
+        assert_eq!(fs::read_to_string(&moved).unwrap(), "scan");
+        assert_eq!(
+            fs::read_to_string(done.join("page.png")).unwrap(),
+            "earlier"
+        );
+
+        let source = tree.write("inbox/page.png", "rescan");
+        assert_eq!(
+            move_into_dir(&source, &done).unwrap(),
+            done.join("page-2.png")
+        );
+
+        tree.write("done/scan", "earlier");
+        let source = tree.write("inbox/scan", "scan");
+        assert_eq!(move_into_dir(&source, &done).unwrap(), done.join("scan-1"));
+    }
+
+    #[test]
+    fn move_into_dir_creates_the_directory() {
+        let tree = TempDir::new("file-utils-move-create");
+        let source = tree.write("page.png", "scan");
+        let failed = tree.path().join("sorted/failed");
+        assert_eq!(
+            move_into_dir(&source, &failed).unwrap(),
+            failed.join("page.png")
+        );
+    }
+
+    #[test]
+    fn collect_supported_files_lists_the_top_level_files_in_order() {
+        let tree = TempDir::new("file-utils-collect");
+        tree.write("b.pdf", "pdf");
+        tree.write("a.png", "png");
+        tree.write("notes.txt", "text");
+        tree.write("nested/c.jpg", "jpg");
+        fs::create_dir(tree.path().join("folder.png")).unwrap();
+
+        assert_eq!(
+            collect_supported_files(tree.path()).unwrap(),
+            [tree.path().join("a.png"), tree.path().join("b.pdf")]
+        );
+        assert!(collect_supported_files(&tree.path().join("missing")).is_err());
+    }
+
+    #[test]
+    fn unsupported_extensions_names_each_skipped_type_once() {
+        let tree = TempDir::new("file-utils-unsupported");
+        tree.write("scan.png", "png");
+        tree.write("notes.txt", "text");
+        tree.write("todo.txt", "text");
+        tree.write("slides.docx", "docx");
+        tree.write("README", "readme");
+        tree.write("nested/table.csv", "csv");
+
+        assert_eq!(
+            unsupported_extensions(tree.path()).unwrap(),
+            ["docx", "txt"]
+        );
+    }
+}
@@ -0,0 +1,368 @@
+use crate::error::NotedError;
+use base64::{Engine, engine::general_purpose};
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::{CompressionType, FilterType, PngEncoder};
+use image::{DynamicImage, GenericImageView, RgbImage};
+
+/// JPEG quality used when none is configured.
+pub const DEFAULT_JPEG_QUALITY: u8 = 85;
+
+/// Compression of the images notedmd encodes itself: tiles, images scaled
+/// down to fit a provider's limit and images extracted from PDFs.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ImageEncoding {
+    /// 1 (smallest) to 100 (best quality).
+    pub jpeg_quality: Option<u8>,
+    /// 0 (uncompressed) to 9 (smallest, slowest).
+    pub png_compression: Option<u8>,
+}
+
+impl ImageEncoding {
+    /// Rejects values outside the ranges above, which can come from the config.
+    pub fn check(&self) -> Result<(), NotedError> {
+        if self
+            .jpeg_quality
+            .is_some_and(|quality| !(1..=100).contains(&quality))
+        {
+            return Err(NotedError::InvalidArgument(
+                "jpeg_quality must be between 1 and 100".to_string(),
+            ));
+        }
+        if self.png_compression.is_some_and(|level| level > 9) {
+            return Err(NotedError::InvalidArgument(
+                "png_compression must be between 0 and 9".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn encode_png(&self, image: &DynamicImage) -> Result<Vec<u8>, NotedError> {
+        let compression = match self.png_compression {
+            None => CompressionType::default(),
+            Some(0) => CompressionType::Uncompressed,
+            Some(level) => CompressionType::Level(level),
+        };
+        let mut encoded = Vec::new();
+        image
+            .write_with_encoder(PngEncoder::new_with_quality(
+                &mut encoded,
+                compression,
+                FilterType::default(),
+            ))
+            .map_err(|e| NotedError::ImageError(e.to_string()))?;
+        Ok(encoded)
+    }
+
+    pub fn encode_jpeg(&self, image: &RgbImage) -> Result<Vec<u8>, NotedError> {
+        let quality = self.jpeg_quality.unwrap_or(DEFAULT_JPEG_QUALITY);
+        let mut encoded = Vec::new();
+        JpegEncoder::new_with_quality(&mut encoded, quality)
+            .encode_image(image)
+            .map_err(|e| NotedError::ImageError(e.to_string()))?;
+        Ok(encoded)
+    }
+}
+
+/// Size and overlap, in pixels, of the square tiles a large image is cut into.
+#[derive(Debug, Clone, Copy)]
+pub struct TileOptions {
+    pub size: u32,
+    pub overlap: u32,
+}
+
+/// Returns `true` when the image is (close to) a single flat color, i.e. the
+/// standard deviation of its brightness (0-255) is at most `threshold`.
+pub fn is_blank(data: &[u8], threshold: f64) -> Result<bool, NotedError> {
+    let image = image::load_from_memory(data).map_err(|e| NotedError::ImageError(e.to_string()))?;
+    // A thumbnail is plenty to tell ink from paper and keeps large scans fast.
+    let pixels = image.thumbnail(256, 256).to_luma8();
+
+    let count = pixels.pixels().len() as f64;
+    if count == 0.0 {
+        return Ok(true);
+    }
+    let mean = pixels.pixels().map(|p| p.0[0] as f64).sum::<f64>() / count;
+    let variance = pixels
+        .pixels()
+        .map(|p| (p.0[0] as f64 - mean).powi(2))
+        .sum::<f64>()
+        / count;
+
+    Ok(variance.sqrt() <= threshold)
+}
+
+/// Returns `false` for images that look like photos rather than pages of
+/// notes. A page (paper, whiteboard or chalkboard) is dominated by one flat
+/// background with little color, so an image is only rejected when it has
+/// neither: less than half of it is close to its most common brightness and
+/// it is strongly colored. Anything in between counts as a document.
+pub fn looks_like_document(data: &[u8]) -> Result<bool, NotedError> {
+    let image = image::load_from_memory(data).map_err(|e| NotedError::ImageError(e.to_string()))?;
+    let pixels = image.thumbnail(256, 256).to_rgb8();
+
+    let count = pixels.pixels().len() as f64;
+    if count == 0.0 {
+        return Ok(true);
+    }
+
+    let mut histogram = [0u32; 256];
+    for pixel in pixels.pixels() {
+        let [r, g, b] = pixel.0;
+        let luma = (0.299 * r as f64 + 0.587 * g as f64 + 0.114 * b as f64) as usize;
+        histogram[luma.min(255)] += 1;
+    }
+    let background = (0..256).max_by_key(|&i| histogram[i]).unwrap_or(0);
+    let near_background: u32 = histogram
+        [background.saturating_sub(24)..=(background + 24).min(255)]
+        .iter()
+        .sum();
+    let background_share = near_background as f64 / count;
+
+    // Hasler and Süsstrunk's colorfulness metric: roughly 0 for grayscale,
+    // above 40 for colorful photos.
+    let (rg, yb): (Vec<f64>, Vec<f64>) = pixels
+        .pixels()
+        .map(|p| {
+            let [r, g, b] = p.0.map(f64::from);
+            (r - g, 0.5 * (r + g) - b)
+        })
+        .unzip();
+    let (rg_mean, rg_std) = mean_and_std(&rg);
+    let (yb_mean, yb_std) = mean_and_std(&yb);
+    let colorfulness =
+        (rg_std.powi(2) + yb_std.powi(2)).sqrt() + 0.3 * (rg_mean.powi(2) + yb_mean.powi(2)).sqrt();
+
+    Ok(background_share >= 0.5 || colorfulness < 40.0)
+}
+
+fn mean_and_std(values: &[f64]) -> (f64, f64) {
+    let count = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / count;
+    let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / count;
+    (mean, variance.sqrt())
+}
+
+/// Re-encodes an image as JPEG, scaling it down until the encoded size is at
+/// most `max_bytes`. Gives up after a few rounds, returning the smallest
+/// attempt, so the caller still has to check the size.
+pub fn shrink_to_fit(
+    data: &[u8],
+    max_bytes: usize,
+    encoding: &ImageEncoding,
+) -> Result<Vec<u8>, NotedError> {
+    let mut image = image::load_from_memory(data)
+        .map_err(|e| NotedError::ImageError(e.to_string()))?
+        .to_rgb8();
+    let mut encoded = encoding.encode_jpeg(&image)?;
+    for _ in 0..5 {
+        if encoded.len() <= max_bytes {
+            break;
+        }
+        // Encoded size grows roughly with the pixel count, so scale both sides
+        // by the square root of the ratio, with some margin.
+        let scale = (max_bytes as f64 / encoded.len() as f64).sqrt() * 0.9;
+        let width = ((image.width() as f64 * scale) as u32).max(1);
+        let height = ((image.height() as f64 * scale) as u32).max(1);
+        image =
+            image::imageops::resize(&image, width, height, image::imageops::FilterType::Triangle);
+        encoded = encoding.encode_jpeg(&image)?;
+    }
+    Ok(encoded)
+}
+
+/// Returns a JPEG thumbnail of the image, at most 256 pixels on its longer
+/// side, as a `data:` URI.
+pub fn thumbnail_data_uri(data: &[u8], encoding: &ImageEncoding) -> Result<String, NotedError> {
+    let image = image::load_from_memory(data).map_err(|e| NotedError::ImageError(e.to_string()))?;
+    let thumbnail = encoding.encode_jpeg(&image.thumbnail(256, 256).to_rgb8())?;
+    Ok(format!(
+        "data:image/jpeg;base64,{}",
+        general_purpose::STANDARD.encode(thumbnail)
+    ))
+}
+
+/// Start positions of the tiles covering `length` pixels. Consecutive tiles
+/// share `overlap` pixels and the last one is aligned to the far edge, so no
+/// tile sticks out of the image. Expects `overlap < size`.
+pub fn tile_offsets(length: u32, size: u32, overlap: u32) -> Vec<u32> {
+    if length <= size {
+        return vec![0];
+    }
+    let step = size - overlap;
+    let last = length - size;
+    let mut offsets: Vec<u32> = (0..last).step_by(step as usize).collect();
+    offsets.push(last);
+    offsets
+}
+
+/// Cuts an image larger than one tile into overlapping PNG tiles in reading
+/// order (left to right, top to bottom). Returns `None` when the image already
+/// fits in a single tile.
+pub fn split_into_tiles(
+    data: &[u8],
+    options: &TileOptions,
+    encoding: &ImageEncoding,
+) -> Result<Option<Vec<Vec<u8>>>, NotedError> {
+    let image = image::load_from_memory(data).map_err(|e| NotedError::ImageError(e.to_string()))?;
+    let (width, height) = image.dimensions();
+    if width <= options.size && height <= options.size {
+        return Ok(None);
+    }
+
+    let mut tiles = Vec::new();
+    for y in tile_offsets(height, options.size, options.overlap) {
+        for x in tile_offsets(width, options.size, options.overlap) {
+            let tile = image.crop_imm(x, y, options.size.min(width), options.size.min(height));
+            tiles.push(encoding.encode_png(&tile)?);
+        }
+    }
+    Ok(Some(tiles))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        ImageEncoding::default()
+            .encode_png(&DynamicImage::new_rgb8(width, height))
+            .unwrap()
+    }
+
+    /// A PNG with every pixel colored by `pixel(x, y)`.
+    fn drawn(width: u32, height: u32, pixel: impl Fn(u32, u32) -> [u8; 3]) -> Vec<u8> {
+        let image = RgbImage::from_fn(width, height, |x, y| image::Rgb(pixel(x, y)));
+        ImageEncoding::default()
+            .encode_png(&DynamicImage::ImageRgb8(image))
+            .unwrap()
+    }
+
+    #[test]
+    fn tile_offsets_cover_the_length_with_overlap() {
+        assert_eq!(tile_offsets(1000, 400, 100), vec![0, 300, 600]);
+        assert_eq!(tile_offsets(700, 400, 100), vec![0, 300]);
+    }
+
+    #[test]
+    fn tile_offsets_align_the_last_tile_to_the_edge() {
+        assert_eq!(tile_offsets(1100, 400, 100), vec![0, 300, 600, 700]);
+    }
+
+    #[test]
+    fn tile_offsets_use_one_tile_when_it_fits() {
+        assert_eq!(tile_offsets(400, 400, 100), vec![0]);
+        assert_eq!(tile_offsets(10, 400, 100), vec![0]);
+    }
+
+    #[test]
+    fn split_into_tiles_leaves_small_images_alone() {
+        let options = TileOptions {
+            size: 400,
+            overlap: 50,
+        };
+        let tiles = split_into_tiles(&png(300, 200), &options, &ImageEncoding::default()).unwrap();
+        assert!(tiles.is_none());
+    }
+
+    #[test]
+    fn split_into_tiles_cuts_in_reading_order() {
+        let options = TileOptions {
+            size: 400,
+            overlap: 50,
+        };
+        let tiles = split_into_tiles(&png(700, 300), &options, &ImageEncoding::default())
+            .unwrap()
+            .unwrap();
+        assert_eq!(tiles.len(), 2);
+        for tile in tiles {
+            let tile = image::load_from_memory(&tile).unwrap();
+            assert_eq!(tile.dimensions(), (400, 300));
+        }
+    }
+
+    #[test]
+    fn thumbnail_data_uri_holds_a_small_jpeg() {
+        let uri = thumbnail_data_uri(&png(1000, 500), &ImageEncoding::default()).unwrap();
+        let encoded = uri.strip_prefix("data:image/jpeg;base64,").unwrap();
+        let thumbnail = general_purpose::STANDARD.decode(encoded).unwrap();
+        assert_eq!(
+            image::guess_format(&thumbnail).unwrap(),
+            image::ImageFormat::Jpeg
+        );
+        assert_eq!(
+            image::load_from_memory(&thumbnail).unwrap().dimensions(),
+            (256, 128)
+        );
+
+        assert!(thumbnail_data_uri(b"not an image", &ImageEncoding::default()).is_err());
+    }
+
+    #[test]
+    fn is_blank_tells_an_empty_page_from_a_written_one() {
+        let empty = drawn(100, 100, |_, _| [250, 250, 250]);
+        assert!(is_blank(&empty, 5.0).unwrap());
+
+        let written = drawn(100, 100, |_, y| {
+            if (40..60).contains(&y) {
+                [20, 20, 20]
+            } else {
+                [250, 250, 250]
+            }
+        });
+        assert!(!is_blank(&written, 5.0).unwrap());
+        assert!(is_blank(&written, 255.0).unwrap());
+    }
+
+    #[test]
+    fn looks_like_document_rejects_only_colorful_busy_images() {
+        let page = drawn(100, 100, |x, y| {
+            if y % 10 == 0 && x > 10 {
+                [30, 30, 30]
+            } else {
+                [245, 245, 240]
+            }
+        });
+        assert!(looks_like_document(&page).unwrap());
+
+        // Busy but gray, like a dense chalkboard.
+        let gray = drawn(100, 100, |x, y| [((x * 37 + y * 53) % 256) as u8; 3]);
+        assert!(looks_like_document(&gray).unwrap());
+
+        let photo = drawn(100, 100, |x, y| {
+            [
+                (x * 37 % 256) as u8,
+                (y * 53 % 256) as u8,
+                ((x + y) * 91 % 256) as u8,
+            ]
+        });
+        assert!(!looks_like_document(&photo).unwrap());
+    }
+
+    #[test]
+    fn shrink_to_fit_scales_down_until_the_jpeg_fits() {
+        let encoding = ImageEncoding::default();
+        let noise = drawn(800, 800, |x, y| {
+            [
+                (x * 37 % 256) as u8,
+                (y * 53 % 256) as u8,
+                ((x ^ y) % 256) as u8,
+            ]
+        });
+
+        let shrunk = shrink_to_fit(&noise, 20_000, &encoding).unwrap();
+        assert!(shrunk.len() <= 20_000);
+        let image = image::load_from_memory(&shrunk).unwrap();
+        assert_eq!(
+            image::guess_format(&shrunk).unwrap(),
+            image::ImageFormat::Jpeg
+        );
+        assert!(image.width() < 800 && image.width() == image.height());
+
+        // Already small enough, it's only re-encoded.
+        let kept = shrink_to_fit(&noise, usize::MAX, &encoding).unwrap();
+        assert_eq!(
+            image::load_from_memory(&kept).unwrap().dimensions(),
+            (800, 800)
+        );
+    }
+}
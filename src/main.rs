@@ -4,12 +4,23 @@ mod clients;
 mod config;
 mod error;
 mod file_utils;
+mod image_utils;
+mod manifest;
+mod markdown_utils;
 mod notion;
+mod output;
+mod pdf_utils;
+mod prompt_presets;
+mod run_stats;
+mod server;
+mod sinks;
+#[cfg(test)]
+mod test_utils;
 mod ui;
 
-use ai_provider::AiProvider;
+use ai_provider::{AiProvider, ClientOptions, DeltaCallback, TokenUsage};
 use clap::Parser;
-use cli::{Cli, Commands};
+use cli::{Cli, Commands, DateSubdirs, NotionCommands, OnEmpty};
 use colored::*;
 use config::{ClaudeConfig, Config, GeminiConfig, OllamaConfig};
 use dialoguer::Confirm;
@@ -18,30 +29,732 @@ use dialoguer::MultiSelect;
 use dialoguer::Select;
 use dialoguer::{Password, theme::ColorfulTheme};
 use error::NotedError;
+use file_utils::FileData;
 use indicatif::ProgressBar;
 use indicatif::ProgressStyle;
 
 use crate::clients::claude_client::ClaudeClient;
+use crate::clients::error_utils;
 use crate::clients::gemini_client::GeminiClient;
-use crate::clients::notion_client::NotionClient;
 use crate::clients::notion_client::PropertyType;
+use crate::clients::notion_client::{self, NotionClient};
 use crate::clients::ollama_client::OllamaClient;
-use crate::clients::openai_client::OpenAIClient;
-use crate::config::NotionConfig;
+use crate::clients::openai_client::{ImageDetail, OpenAIClient};
+use crate::clients::rate_limit::RequestPacer;
 use crate::config::OpenAIConfig;
+use crate::config::{DEFAULT_NOTION_API_VERSION, NotionConfig};
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io::IsTerminal;
 use std::path::Path;
-use ui::{ascii_art, print_clean_config};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Instant;
+use ui::{
+    ascii_art, format_bytes, print_clean_config, print_config_changes, print_content_summary,
+    print_no_supported_files, print_prompt_presets, print_run_stats, print_supported_file_types,
+};
 
 use crate::config::get_config_path;
+use crate::image_utils::{ImageEncoding, TileOptions};
+use crate::markdown_utils::{ContentStats, MathOptions};
+use crate::notion::converter::Converter;
+use crate::output::OutputOptions;
+use crate::run_stats::RunStats;
+use crate::sinks::{
+    AppendSink, ConvertedFile, FileSink, NotionSink, OutputSink, StdoutSink, upload_to_notion,
+};
+use comrak::Arena;
+use similar::TextDiff;
+
+/// Rejects inputs the provider cannot read natively before any request is sent.
+fn ensure_provider_accepts(
+    client: &dyn AiProvider,
+    file_data: &FileData,
+) -> Result<(), NotedError> {
+    if file_data.mime_type == "application/pdf" && !client.supports_pdf() {
+        return Err(NotedError::PdfNotSupported(client.name().to_string()));
+    }
+    Ok(())
+}
+
+/// Per-run settings shared by every file `process_and_save_file` handles.
+struct ConvertOptions<'a> {
+    output_dir: Option<&'a str>,
+    /// `YYYY/MM` subdirectories each markdown file goes into.
+    date_subdirs: Option<DateSubdirs>,
+    /// Mime type forced with `--as` instead of detecting it per file.
+    mime_type: Option<&'a str>,
+    /// Where every converted file is written, in order.
+    sinks: &'a [Box<dyn OutputSink + 'a>],
+    output: OutputOptions,
+    /// Extension of the written markdown, `summary.md` with `--summary`.
+    extension: &'static str,
+    dedupe: bool,
+    blank_threshold: Option<f64>,
+    skip_non_documents: bool,
+    tile: Option<TileOptions>,
+    image_encoding: ImageEncoding,
+    on_empty: OnEmpty,
+    cleanup: bool,
+    /// Put display math on lines of its own and join broken inline math,
+    /// unless `--no-math-fix` is set.
+    math: Option<MathOptions>,
+    /// Tag each result with the language it's written in.
+    detect_language: bool,
+    max_output_chars: Option<usize>,
+    /// Add a hint on how to fix common provider errors.
+    explain_errors: bool,
+    /// Print the body of responses that couldn't be decoded.
+    verbose: bool,
+    /// Where `--stats` collects its measurements.
+    stats: Option<&'a RunStats>,
+    /// Show each result and ask before saving it.
+    review: bool,
+    /// Save the images embedded in PDFs next to the markdown and link them.
+    extract_images: bool,
+    /// Start the markdown of a PDF with a contents list from its outline.
+    use_outline: bool,
+    /// End the markdown of an image with a thumbnail of it in a comment.
+    embed_source_thumbnail: bool,
+    move_done: Option<&'a str>,
+    move_failed: Option<&'a str>,
+}
+
+/// Adds the fix for a known provider error when `--explain-errors` is set.
+fn explained(error: NotedError, explain_errors: bool) -> NotedError {
+    if explain_errors {
+        error_utils::explain_error(error)
+    } else {
+        error
+    }
+}
+
+/// Stops a directory run at its first failed file for `--abort-on-error`.
+/// The progress kept for resuming stays, so running again picks up there.
+fn abort_run(error: NotedError, progress_bar: &ProgressBar) -> NotedError {
+    progress_bar.abandon_with_message(format!(
+        "{}",
+        "Stopped at the first failed file (--abort-on-error)".red()
+    ));
+    error
+}
+
+/// How many times `--on-empty retry` sends a file again before giving up.
+const EMPTY_RESPONSE_RETRIES: u32 = 2;
+
+/// Picks the client for each file of a run: the default client (`--provider`
+/// or the active provider) unless a `[[routes]]` entry matches the file.
+/// Clients for routed providers are built the first time a file needs them,
+/// and a file with a sidecar prompt gets a client of its own.
+struct ClientRouter<'a> {
+    config: &'a Config,
+    default_client: Box<dyn AiProvider>,
+    routed_clients: HashMap<String, Box<dyn AiProvider>>,
+    /// Client built for the last file with a sidecar prompt.
+    sidecar_client: Option<Box<dyn AiProvider>>,
+    /// Off when `--provider` forces the default client for every file.
+    use_routes: bool,
+    api_key: Option<String>,
+    image_detail: ImageDetail,
+    client_options: &'a ClientOptions,
+}
+
+impl<'a> ClientRouter<'a> {
+    fn client_for(
+        &mut self,
+        path: &Path,
+        mime_type: Option<&str>,
+    ) -> Result<&dyn AiProvider, NotedError> {
+        let provider = self
+            .route(path, mime_type)?
+            .filter(|&provider| provider != self.default_client.name());
+
+        if let Some(prompt) = file_utils::sidecar_prompt(path)? {
+            let provider = provider.unwrap_or(self.default_client.name());
+            let client_options = ClientOptions {
+                prompt: Some(prompt),
+                ..self.client_options.clone()
+            };
+            let client = build_client(
+                Some(provider),
+                self.config,
+                self.api_key_for(provider),
+                self.image_detail,
+                client_options,
+            )?;
+            return Ok(&**self.sidecar_client.insert(client));
+        }
+
+        let Some(provider) = provider else {
+            return Ok(self.default_client.as_ref());
+        };
+        if !self.routed_clients.contains_key(provider) {
+            let client = build_client(
+                Some(provider),
+                self.config,
+                self.api_key_for(provider),
+                self.image_detail,
+                self.client_options.clone(),
+            )?;
+            self.routed_clients.insert(provider.to_string(), client);
+        }
+        Ok(self.routed_clients[provider].as_ref())
+    }
+
+    /// The --api-key flag (GEMINI_API_KEY) applies to the default provider, as
+    /// it always has, and otherwise only to Gemini.
+    fn api_key_for(&self, provider: &str) -> Option<String> {
+        self.api_key
+            .clone()
+            .filter(|_| provider == self.default_client.name() || provider == "gemini")
+    }
+
+    /// Provider of the first route matching the file, if any.
+    fn route(&self, path: &Path, mime_type: Option<&str>) -> Result<Option<&'a str>, NotedError> {
+        let routes = &self.config.routes;
+        if !self.use_routes || routes.is_empty() {
+            return Ok(None);
+        }
+        let mime_type = match mime_type {
+            Some(mime_type) => mime_type.to_string(),
+            None => file_utils::get_file_mime_type(&path.to_string_lossy())?,
+        };
+        let size = std::fs::metadata(path)?.len();
+        let pages = if mime_type == "application/pdf"
+            && routes.iter().any(|route| route.min_pages.is_some())
+        {
+            pdf_utils::page_count(&std::fs::read(path)?)?
+        } else {
+            1
+        };
+        Ok(routes
+            .iter()
+            .find(|route| route.matches(&mime_type, size, pages))
+            .map(|route| route.provider.as_str()))
+    }
+}
+
+/// Creates the client for `provider` from its section of the config. An
+/// explicit `api_key` takes precedence over the configured one.
+fn build_client(
+    provider: Option<&str>,
+    config: &Config,
+    api_key: Option<String>,
+    image_detail: ImageDetail,
+    mut client_options: ClientOptions,
+) -> Result<Box<dyn AiProvider>, NotedError> {
+    let seed = client_options.seed;
+    if let Some(provider) = provider {
+        if client_options.prompt.is_none() {
+            client_options.prompt = config::load_prompt_file(provider)?;
+        }
+        if let Some(request_config) = config.request_config(provider) {
+            if client_options.system_prompt.is_none() {
+                client_options.system_prompt = request_config.system_prompt.clone();
+            }
+            if client_options.stop_sequences.is_empty() {
+                client_options.stop_sequences = request_config.stop_sequences.clone();
+            }
+        }
+    }
+    let has_stop_sequences = !client_options.stop_sequences.is_empty();
+    let client: Box<dyn AiProvider> = match provider {
+        Some("gemini") => {
+            let api_keys = if let Some(key) = api_key {
+                vec![key]
+            } else if let Some(gemini_config) = &config.gemini {
+                // `api_key` may be left empty when only `api_keys` is set.
+                let mut keys: Vec<String> = Vec::new();
+                for key in std::iter::once(&gemini_config.api_key).chain(&gemini_config.api_keys) {
+                    if !key.is_empty() && !keys.contains(key) {
+                        keys.push(key.clone());
+                    }
+                }
+                keys
+            } else {
+                Vec::new()
+            };
+            if api_keys.is_empty() {
+                return Err(NotedError::GeminiNotConfigured);
+            }
+            let base_url = config
+                .gemini
+                .as_ref()
+                .and_then(|gemini_config| gemini_config.base_url.clone());
+            Box::new(GeminiClient::new(api_keys, base_url, client_options))
+        }
+        Some("ollama") => {
+            let url = if let Some(ollama_config) = &config.ollama {
+                ollama_config.url.clone()
+            } else {
+                return Err(NotedError::OllamaNotConfigured);
+            };
+            let model = if let Some(ollama_config) = &config.ollama {
+                ollama_config.model.clone()
+            } else {
+                return Err(NotedError::OllamaNotConfigured);
+            };
+            let api = config
+                .ollama
+                .as_ref()
+                .map(|ollama_config| ollama_config.api)
+                .unwrap_or_default();
+            Box::new(OllamaClient::new(url, model, api, client_options))
+        }
+        Some("claude") => {
+            let api_key = if let Some(key) = api_key {
+                key
+            } else if let Some(claude_config) = &config.claude {
+                claude_config.api_key.clone()
+            } else {
+                return Err(NotedError::ClaudeNotConfigured);
+            };
+
+            let model = if let Some(claude_config) = &config.claude {
+                claude_config.model.clone()
+            } else {
+                return Err(NotedError::ClaudeNotConfigured);
+            };
+
+            let base_url = config
+                .claude
+                .as_ref()
+                .and_then(|claude_config| claude_config.base_url.clone());
+            Box::new(ClaudeClient::new(api_key, model, base_url, client_options))
+        }
+        Some("openai") => {
+            let url = if let Some(openai_config) = &config.openai {
+                openai_config.url.clone()
+            } else {
+                return Err(NotedError::OpenAINotConfigured);
+            };
+            let model = if let Some(openai_config) = &config.openai {
+                openai_config.model.clone()
+            } else {
+                return Err(NotedError::OpenAINotConfigured);
+            };
+            let api_key = if let Some(openai_config) = &config.openai {
+                openai_config.api_key.clone()
+            } else {
+                return Err(NotedError::OpenAINotConfigured);
+            };
+            let api = if let Some(openai_config) = &config.openai {
+                openai_config.api
+            } else {
+                return Err(NotedError::OpenAINotConfigured);
+            };
+            let stream = if let Some(openai_config) = &config.openai {
+                openai_config.stream
+            } else {
+                return Err(NotedError::OpenAINotConfigured);
+            };
+            Box::new(OpenAIClient::new(
+                url,
+                model,
+                api_key,
+                api,
+                image_detail,
+                stream,
+                client_options,
+            ))
+        }
+        _ => return Err(NotedError::NoActiveProvider),
+    };
+    if seed.is_some() && !client.supports_seed() {
+        eprintln!(
+            "{}",
+            format!(
+                "The '{}' provider doesn't accept a seed, --seed is ignored.",
+                client.name()
+            )
+            .yellow()
+        );
+    }
+    if has_stop_sequences && !client.supports_stop_sequences() {
+        eprintln!(
+            "{}",
+            format!(
+                "The '{}' provider doesn't accept stop sequences with this API, --stop is ignored.",
+                client.name()
+            )
+            .yellow()
+        );
+    }
+    Ok(client)
+}
+
+/// Saves the config built by the `config --edit` wizard. When that would
+/// change or remove existing settings, shows the changes and asks first unless
+/// `force` is set. Returns whether the config was saved.
+fn save_wizard_config(config: &Config, force: bool) -> Result<bool, NotedError> {
+    let changes = config::config_changes(&Config::load()?, config)?;
+    if !force && changes.iter().any(config::ConfigChange::overwrites) {
+        println!("{}", "This changes your existing settings:".bold());
+        print_config_changes(&changes);
+        let confirmed = Confirm::with_theme(&ColorfulTheme::default())
+            .with_prompt("Save these changes?")
+            .default(false)
+            .interact()?;
+        if !confirmed {
+            println!("{}", "Config left unchanged.".yellow());
+            return Ok(false);
+        }
+    }
+    config.save()?;
+    Ok(true)
+}
+
+/// Writes the section of `provider` from `config --init` flags and makes it
+/// the active provider, keeping the rest of the configuration.
+fn init_config(
+    provider: &str,
+    api_key: Option<String>,
+    model: Option<String>,
+    url: Option<String>,
+) -> Result<(), NotedError> {
+    let missing = |flag: &str| {
+        NotedError::InvalidArgument(format!("--init --provider {} needs {}", provider, flag))
+    };
+    let mut config = Config::load()?;
+    match provider {
+        "gemini" => {
+            if model.is_some() {
+                return Err(NotedError::InvalidArgument(
+                    "gemini uses a fixed model, drop --model".to_string(),
+                ));
+            }
+            let previous = config.gemini.take().unwrap_or_default();
+            config.gemini = Some(GeminiConfig {
+                api_key: api_key.ok_or_else(|| missing("--api-key"))?,
+                base_url: url.or(previous.base_url),
+                ..previous
+            });
+        }
+        "claude" => {
+            let previous = config.claude.take().unwrap_or_default();
+            config.claude = Some(ClaudeConfig {
+                api_key: api_key.ok_or_else(|| missing("--api-key"))?,
+                model: model.unwrap_or_else(|| config::DEFAULT_CLAUDE_MODEL.to_string()),
+                base_url: url.or(previous.base_url),
+            });
+        }
+        "ollama" => {
+            if api_key.is_some() {
+                return Err(NotedError::InvalidArgument(
+                    "ollama takes no API key, drop --api-key".to_string(),
+                ));
+            }
+            let previous = config.ollama.take().unwrap_or_default();
+            config.ollama = Some(OllamaConfig {
+                url: url.map_or_else(
+                    || config::DEFAULT_OLLAMA_URL.to_string(),
+                    |url| url.trim_end_matches('/').to_string(),
+                ),
+                model: model.unwrap_or_else(|| config::DEFAULT_LOCAL_MODEL.to_string()),
+                ..previous
+            });
+        }
+        "openai" => {
+            let previous = config.openai.take().unwrap_or_default();
+            config.openai = Some(OpenAIConfig {
+                url: config::normalize_openai_url(
+                    url.as_deref().unwrap_or(config::DEFAULT_OPENAI_URL),
+                ),
+                model: model.unwrap_or_else(|| config::DEFAULT_LOCAL_MODEL.to_string()),
+                api_key,
+                ..previous
+            });
+        }
+        _ => unreachable!("clap only accepts known providers"),
+    }
+    config.active_provider = Some(provider.to_string());
+    config.save()?;
+    println!("{}", "Config saved successfully.".green());
+    Ok(())
+}
+
+/// Opens `path` in `$VISUAL` or `$EDITOR` (falling back to `vi`, or `notepad`
+/// on Windows) and waits for the editor to close.
+fn open_in_editor(path: &Path) -> Result<(), NotedError> {
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| if cfg!(windows) { "notepad" } else { "vi" }.to_string());
+    // $EDITOR may carry arguments, e.g. "code --wait".
+    let mut editor_command = editor.split_whitespace();
+    let status = std::process::Command::new(editor_command.next().unwrap_or("vi"))
+        .args(editor_command)
+        .arg(path)
+        .status()?;
+    if !status.success() {
+        return Err(NotedError::InvalidArgument(format!(
+            "editor '{}' exited with {}",
+            editor, status
+        )));
+    }
+    Ok(())
+}
+
+/// What the user chose for a result shown by `--review`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Review {
+    Accept,
+    Retry,
+    Edit,
+    Skip,
+}
+
+impl Review {
+    const ALL: [Review; 4] = [Review::Accept, Review::Retry, Review::Edit, Review::Skip];
+
+    fn label(self) -> &'static str {
+        match self {
+            Review::Accept => "Accept",
+            Review::Retry => "Retry (send the file again)",
+            Review::Edit => "Edit in $EDITOR",
+            Review::Skip => "Skip (don't save this file)",
+        }
+    }
+}
+
+/// Shows the markdown converted from `file_name` and asks what to do with it.
+fn ask_review(
+    file_name: &std::ffi::OsStr,
+    markdown: &str,
+    progress_bar: &ProgressBar,
+) -> Result<Review, NotedError> {
+    progress_bar.suspend(|| {
+        println!(
+            "\n{}\n{}\n{}",
+            format!("── {} ──", file_name.to_string_lossy()).bold(),
+            markdown,
+            "──".bold()
+        );
+        let labels: Vec<&str> = Review::ALL.iter().map(|review| review.label()).collect();
+        let choice = Select::with_theme(&ColorfulTheme::default())
+            .with_prompt("Save this result?")
+            .items(&labels)
+            .default(0)
+            .interact()?;
+        Ok(Review::ALL[choice])
+    })
+}
+
+/// Lets the user edit `markdown` in their editor through a temporary file.
+fn edit_markdown(markdown: &str) -> Result<String, NotedError> {
+    let path = std::env::temp_dir().join(format!("notedmd-review-{}.md", std::process::id()));
+    std::fs::write(&path, markdown)?;
+    let edited = open_in_editor(&path).and_then(|_| Ok(std::fs::read_to_string(&path)?));
+    let _ = std::fs::remove_file(&path);
+    edited
+}
+
+/// A `## Contents` section listing the PDF's bookmarks, nested by level, with
+/// the page each one points to.
+fn outline_section(outline: &[pdf_utils::OutlineEntry]) -> String {
+    let mut section = String::from("## Contents\n\n");
+    for entry in outline {
+        section.push_str(&format!(
+            "{}- {} (p. {})\n",
+            "  ".repeat(entry.level.saturating_sub(1)),
+            entry.title,
+            entry.page
+        ));
+    }
+    section
+}
+
+/// Pulls the images embedded in a PDF for `--extract-images`, warning about
+/// the ones in a format that can't be extracted.
+fn extract_pdf_images(
+    data: &[u8],
+    encoding: &ImageEncoding,
+    progress_bar: &ProgressBar,
+) -> Result<Vec<pdf_utils::PageImage>, NotedError> {
+    let (images, skipped) = pdf_utils::extract_images(data, encoding)?;
+    if skipped > 0 {
+        progress_bar.println(format!(
+            "{} {}",
+            "➜".yellow(),
+            format!(
+                "{} embedded image(s) use an unsupported format and were not extracted.",
+                skipped
+            )
+            .yellow()
+        ));
+    }
+    Ok(images)
+}
+
+/// Sends one request, recording it for `--stats`. A response body that
+/// can't be decoded is often a one-off error page from a gateway, so the
+/// request is sent once more before the mismatch is reported as persistent.
+async fn send_request(
+    client: &dyn AiProvider,
+    file_data: FileData,
+    progress_bar: &ProgressBar,
+    options: &ConvertOptions<'_>,
+) -> Result<String, NotedError> {
+    let mut retried = false;
+    loop {
+        let bytes = file_data.encoded_data.len();
+        let started = Instant::now();
+        let result = client.send_request(file_data.clone()).await;
+        if let Some(stats) = options.stats {
+            stats.record_request(bytes, started.elapsed(), result.is_ok());
+        }
+        let (reason, body) = match result {
+            Err(NotedError::UnexpectedResponseBody { reason, body }) => (reason, body),
+            result => return result.map_err(|e| explained(e, options.explain_errors)),
+        };
+        if options.verbose {
+            progress_bar.println(format!(
+                "{}\n{}",
+                format!("Response body from {}:", client.name()).dimmed(),
+                body
+            ));
+        }
+        if retried {
+            return Err(NotedError::ResponseFormatMismatch(reason));
+        }
+        retried = true;
+        if let Some(stats) = options.stats {
+            stats.record_retry();
+        }
+        progress_bar.println(format!(
+            "{} {}",
+            "➜".yellow(),
+            format!(
+                "Could not decode the response ({}), retrying once...",
+                reason
+            )
+            .yellow()
+        ));
+    }
+}
+
+/// Takes the token usage the client recorded, counting it for `--stats`.
+fn take_usage(client: &dyn AiProvider, options: &ConvertOptions<'_>) -> Option<TokenUsage> {
+    let usage = client.take_usage();
+    if let Some(stats) = options.stats
+        && let Some(usage) = usage
+    {
+        stats.record_usage(usage);
+    }
+    usage
+}
+
+/// How much of the line a streamed answer is on the progress bar shows.
+const STREAM_PREVIEW_CHARS: usize = 60;
+
+/// Shows the end of the line a streamed answer is on as the progress bar
+/// message, once the run has a progress bar.
+fn stream_display(progress_bar: Arc<OnceLock<ProgressBar>>) -> DeltaCallback {
+    let line = Mutex::new(String::new());
+    DeltaCallback::new(move |delta| {
+        let mut line = line.lock().unwrap();
+        match delta.rsplit_once('\n') {
+            Some((_, rest)) => *line = rest.to_string(),
+            None => line.push_str(delta),
+        }
+        if let Some(progress_bar) = progress_bar.get() {
+            let skip = line.chars().count().saturating_sub(STREAM_PREVIEW_CHARS);
+            let preview: String = line.chars().skip(skip).collect();
+            progress_bar.set_message(format!("{} {}", "Receiving:".yellow(), preview.trim()));
+        }
+    })
+}
+
+/// Applies the clean-up passes turned on for the run to a provider's answer.
+fn normalize_markdown(markdown: String, options: &ConvertOptions) -> String {
+    let markdown = match options.math {
+        Some(math) => markdown_utils::fix_math_delimiters(&markdown, math),
+        None => markdown,
+    };
+    if options.cleanup {
+        markdown_utils::tidy_whitespace(&markdown)
+    } else {
+        markdown
+    }
+}
+
+/// Sends one file to the provider, cutting it into tiles first when `--tile`
+/// applies, and returns the cleaned markdown.
+async fn request_markdown(
+    data: &[u8],
+    mime_type: &str,
+    client: &dyn AiProvider,
+    progress_bar: &ProgressBar,
+    options: &ConvertOptions<'_>,
+) -> Result<String, NotedError> {
+    let file_data = FileData::new(data, mime_type.to_string());
+    ensure_provider_accepts(client, &file_data)?;
+    if mime_type == "application/pdf" {
+        pdf_utils::ensure_has_pages(data)?;
+    }
+    progress_bar.set_message(format!(
+        "{}",
+        format!("Sending to {}...", client.name()).yellow()
+    ));
+
+    let tiles = match options.tile {
+        Some(tile) if file_data.mime_type.starts_with("image/") => {
+            image_utils::split_into_tiles(data, &tile, &options.image_encoding)?
+        }
+        _ => None,
+    };
+    let markdown = match tiles {
+        Some(tiles) => {
+            let mut parts = Vec::with_capacity(tiles.len());
+            for (index, tile) in tiles.iter().enumerate() {
+                progress_bar.set_message(format!(
+                    "{}",
+                    format!(
+                        "Sending tile {}/{} to {}...",
+                        index + 1,
+                        tiles.len(),
+                        client.name()
+                    )
+                    .yellow()
+                ));
+                parts.push(
+                    send_request(
+                        client,
+                        FileData::new(tile, "image/png".to_string()),
+                        progress_bar,
+                        options,
+                    )
+                    .await?,
+                );
+            }
+            markdown_utils::stitch_markdown(&parts)
+        }
+        None => send_request(client, file_data, progress_bar, options).await?,
+    };
+
+    if let Some(max_chars) = options.max_output_chars
+        && let Some(truncated) = markdown_utils::truncate_runaway(&markdown, max_chars)
+    {
+        progress_bar.println(format!(
+            "{} {}",
+            "➜".yellow(),
+            format!(
+                "Response was longer than {} characters and has been cut off. Check this page, the model may have looped.",
+                max_chars
+            )
+            .yellow()
+        ));
+        return Ok(truncated);
+    }
+    Ok(markdown)
+}
 
 async fn process_and_save_file(
     file_path: &str,
     client: &dyn AiProvider,
-    output_dir: Option<&str>,
     progress_bar: &ProgressBar,
-    notion_client: Option<&NotionClient>,
-    notion_config: Option<&NotionConfig>,
-) -> Result<(), NotedError> {
+    options: &ConvertOptions<'_>,
+    converted_files: &mut HashMap<u64, String>,
+) -> Result<Option<ContentStats>, NotedError> {
     let path = Path::new(file_path);
     let file_name = match path.file_name() {
         Some(name) => name,
@@ -49,79 +762,371 @@ async fn process_and_save_file(
             return Err(NotedError::FileNameError(file_path.to_string()));
         }
     };
+    // Usage left over from a file that failed only counts towards --stats,
+    // not towards this file.
+    take_usage(client, options);
 
     progress_bar.println(format!(
         "\n{}",
         format!("Processing file: {:#?}", file_name).bold()
     ));
 
-    let file_data = file_utils::process_file(file_path)?;
+    let data = std::fs::read(file_path)?;
+    let mime_type = match options.mime_type {
+        Some(mime_type) => mime_type.to_string(),
+        None => file_utils::get_file_mime_type(file_path)?,
+    };
     progress_bar.println(format!(
         "{} {}",
         "✔".green(),
         "File read successfully.".green()
     ));
 
-    progress_bar.set_message(format!("{}", "Sending to your AI model...".yellow()));
+    if let Some(threshold) = options.blank_threshold
+        && mime_type.starts_with("image/")
+        && image_utils::is_blank(&data, threshold)?
+    {
+        progress_bar.println(format!(
+            "{} {}",
+            "➜".yellow(),
+            "Skipped blank page.".yellow()
+        ));
+        return Ok(None);
+    }
 
-    let markdown = client.send_request(file_data).await?;
-    progress_bar.println(format!("{} {}", "✔".green(), "Received response.".green()));
+    if options.skip_non_documents
+        && mime_type.starts_with("image/")
+        && !image_utils::looks_like_document(&data)?
+    {
+        progress_bar.println(format!(
+            "{} {}",
+            "➜".yellow(),
+            "Skipped image that doesn't look like a page of notes.".yellow()
+        ));
+        return Ok(None);
+    }
 
-    let output_path = match output_dir {
-        Some(dir) => {
-            let dir_path = Path::new(dir);
-            if !dir_path.exists() {
-                std::fs::create_dir_all(dir_path)?;
-            }
-            let final_path = dir_path.join(file_name);
-            final_path
-                .with_extension("md")
-                .to_string_lossy()
-                .into_owned()
-        }
-        None => path.with_extension("md").to_string_lossy().into_owned(),
-    };
+    let content_hash = options.dedupe.then(|| {
+        let mut hasher = DefaultHasher::new();
+        data.hash(&mut hasher);
+        hasher.finish()
+    });
 
-    match std::fs::write(&output_path, &markdown) {
-        Ok(_) => {
+    let markdown = match content_hash.and_then(|hash| converted_files.get(&hash)) {
+        Some(markdown) => {
             progress_bar.println(format!(
                 "{} {}",
                 "✔".green(),
-                format!("Markdown saved to '{}'", output_path.cyan()).green()
+                "Identical to an earlier file, reusing its result.".green()
             ));
-            if let (Some(client), Some(config)) = (notion_client, notion_config) {
-                match client
-                    .create_notion_page(
-                        file_name.to_string_lossy().into_owned().as_str(),
-                        &config.title_property_name,
-                        &config.properties,
-                        &markdown,
-                    )
-                    .await
-                {
-                    Ok(page) => {
+            markdown.clone()
+        }
+        None => {
+            let mut retries = 0;
+            let markdown = loop {
+                let markdown =
+                    request_markdown(&data, &mime_type, client, progress_bar, options).await?;
+                if !markdown.trim().is_empty() {
+                    break markdown;
+                }
+                match options.on_empty {
+                    OnEmpty::Retry if retries < EMPTY_RESPONSE_RETRIES => {
+                        retries += 1;
+                        if let Some(stats) = options.stats {
+                            stats.record_retry();
+                        }
                         progress_bar.println(format!(
                             "{} {}",
-                            "✔".green(),
-                            format!("Notion page created at '{}'", page.url.cyan()).green()
+                            "➜".yellow(),
+                            format!(
+                                "Empty response, retrying ({}/{})...",
+                                retries, EMPTY_RESPONSE_RETRIES
+                            )
+                            .yellow()
                         ));
                     }
-                    Err(e) => {
-                        return Err(e);
+                    OnEmpty::Skip => {
+                        progress_bar.println(format!(
+                            "{} {}",
+                            "✖".red(),
+                            "Empty response, no markdown written for this file.".red()
+                        ));
+                        return Ok(None);
                     }
+                    _ => return Err(NotedError::EmptyResponse(file_path.to_string())),
                 }
             };
-            Ok(())
+            progress_bar.println(format!("{} {}", "✔".green(), "Received response.".green()));
+            if let Some(hash) = content_hash {
+                converted_files.insert(hash, markdown.clone());
+            }
+            markdown
+        }
+    };
+    let mut markdown = normalize_markdown(markdown, options);
+
+    if options.review {
+        loop {
+            match ask_review(file_name, &markdown, progress_bar)? {
+                Review::Accept => break,
+                Review::Retry => {
+                    markdown = normalize_markdown(
+                        request_markdown(&data, &mime_type, client, progress_bar, options).await?,
+                        options,
+                    );
+                }
+                Review::Edit => {
+                    markdown = progress_bar.suspend(|| edit_markdown(&markdown))?;
+                }
+                Review::Skip => {
+                    progress_bar.println(format!(
+                        "{} {}",
+                        "➜".yellow(),
+                        "Skipped after review, nothing saved for this file.".yellow()
+                    ));
+                    return Ok(None);
+                }
+            }
+        }
+    }
+
+    if options.use_outline && mime_type == "application/pdf" {
+        match pdf_utils::outline(&data) {
+            Ok(outline) if !outline.is_empty() => {
+                markdown = format!("{}\n{}", outline_section(&outline), markdown);
+            }
+            Ok(_) => progress_bar.println(format!(
+                "{} {}",
+                "➜".yellow(),
+                "The PDF has no outline, no contents list added.".yellow()
+            )),
+            Err(e) => progress_bar.println(format!(
+                "{} {}",
+                "➜".yellow(),
+                format!("Could not read the PDF outline: {}", e.to_string().trim()).yellow()
+            )),
         }
-        Err(e) => {
+    }
+
+    // Before the sections appended below, which aren't part of the text.
+    let language = options
+        .detect_language
+        .then(|| markdown_utils::detect_language(&markdown));
+
+    let images = if options.extract_images && mime_type == "application/pdf" {
+        extract_pdf_images(&data, &options.image_encoding, progress_bar)?
+    } else {
+        Vec::new()
+    };
+
+    if options.embed_source_thumbnail {
+        if mime_type.starts_with("image/") {
+            markdown.push_str(&format!(
+                "\n<!-- source: {} -->\n",
+                image_utils::thumbnail_data_uri(&data, &options.image_encoding)?
+            ));
+        } else {
             progress_bar.println(format!(
                 "{} {}",
-                "✖".red(),
-                format!("Failed to save file to '{}'. Error: {}", &output_path, e).red()
+                "➜".yellow(),
+                "No source thumbnail embedded, PDF pages can't be rendered to images.".yellow()
             ));
-            Err(e.into())
         }
     }
+
+    let stats = ContentStats {
+        usage: take_usage(client, options),
+        ..ContentStats::of(&markdown)
+    };
+    let converted = ConvertedFile {
+        source: path,
+        data: &data,
+        mime_type: &mime_type,
+        markdown: &markdown,
+        stats: &stats,
+        provider: client.name(),
+        model: client.model(),
+        language,
+        images: &images,
+    };
+    for sink in options.sinks {
+        sink.write(&converted, progress_bar).await?;
+    }
+    Ok(Some(stats))
+}
+
+/// Converts one file with two providers, saves each result as
+/// `<stem>.<provider>.md` and prints a unified diff between them.
+async fn compare_providers(
+    input_path: &Path,
+    first: &dyn AiProvider,
+    second: &dyn AiProvider,
+    options: &ConvertOptions<'_>,
+) -> Result<(), NotedError> {
+    let path_str = input_path
+        .to_str()
+        .ok_or_else(|| NotedError::FileNameError(input_path.to_string_lossy().to_string()))?;
+    let data = std::fs::read(input_path)?;
+    let mime_type = match options.mime_type {
+        Some(mime_type) => mime_type.to_string(),
+        None => file_utils::get_file_mime_type(path_str)?,
+    };
+
+    let spinner = ProgressBar::new_spinner();
+    spinner.set_style(
+        ProgressStyle::default_spinner()
+            .template("{spinner:.cyan} {msg}")
+            .unwrap(),
+    );
+    spinner.enable_steady_tick(std::time::Duration::from_millis(100));
+
+    let mut results = Vec::with_capacity(2);
+    for client in [first, second] {
+        let markdown = match request_markdown(&data, &mime_type, client, &spinner, options).await {
+            Ok(markdown) => normalize_markdown(markdown, options),
+            Err(e) => {
+                spinner.finish_and_clear();
+                return Err(e);
+            }
+        };
+        let extension = format!("{}.md", client.name());
+        let sink = FileSink {
+            output_dir: options.output_dir,
+            date_subdirs: options.date_subdirs,
+            extension: &extension,
+            options: options.output,
+        };
+        let stats = ContentStats {
+            usage: take_usage(client, options),
+            ..ContentStats::of(&markdown)
+        };
+        let converted = ConvertedFile {
+            source: input_path,
+            data: &data,
+            mime_type: &mime_type,
+            markdown: &markdown,
+            stats: &stats,
+            provider: client.name(),
+            model: client.model(),
+            language: options
+                .detect_language
+                .then(|| markdown_utils::detect_language(&markdown)),
+            images: &[],
+        };
+        if let Err(e) = sink.write(&converted, &spinner).await {
+            spinner.finish_and_clear();
+            return Err(e);
+        }
+        results.push((sink.path(input_path), markdown));
+    }
+    spinner.finish_and_clear();
+
+    let (first_path, first_markdown) = &results[0];
+    let (second_path, second_markdown) = &results[1];
+    if first_markdown == second_markdown {
+        println!("{}", "Both providers produced the same markdown.".green());
+        return Ok(());
+    }
+    let diff = TextDiff::from_lines(first_markdown.as_str(), second_markdown.as_str());
+    let unified = diff
+        .unified_diff()
+        // Results either end in one newline or none, so the hint would only
+        // be noise.
+        .missing_newline_hint(false)
+        .header(
+            &first_path.to_string_lossy(),
+            &second_path.to_string_lossy(),
+        )
+        .to_string();
+    for line in unified.lines() {
+        if line.starts_with("@@") {
+            println!("{}", line.cyan());
+        } else if line.starts_with('+') {
+            println!("{}", line.green());
+        } else if line.starts_with('-') {
+            println!("{}", line.red());
+        } else {
+            println!("{}", line);
+        }
+    }
+    Ok(())
+}
+
+/// Moves a source file to `--move-done` or `--move-failed` depending on
+/// whether it converted. Skipped files stay where they are.
+fn move_source(
+    file_path: &Path,
+    result: &Result<Option<ContentStats>, NotedError>,
+    options: &ConvertOptions<'_>,
+    progress_bar: &ProgressBar,
+) {
+    let target_dir = match result {
+        Ok(Some(_)) => options.move_done,
+        Ok(None) => None,
+        Err(_) => options.move_failed,
+    };
+    let Some(target_dir) = target_dir else {
+        return;
+    };
+    match file_utils::move_into_dir(file_path, Path::new(target_dir)) {
+        Ok(destination) => progress_bar.println(format!(
+            "{} {}",
+            "✔".green(),
+            format!("Moved source to '{}'", destination.to_string_lossy().cyan()).green()
+        )),
+        Err(e) => progress_bar.println(format!(
+            "{} {}",
+            "✖".red(),
+            format!("Failed to move {:?} to '{}': {}", file_path, target_dir, e).red()
+        )),
+    }
+}
+
+/// Converts the first page of `input_path` (or of its first file) and prints
+/// the markdown instead of saving it.
+async fn sample_file(
+    input_path: &Path,
+    client: &dyn AiProvider,
+    options: &ConvertOptions<'_>,
+) -> Result<(), NotedError> {
+    let sample_path = if input_path.is_dir() {
+        match file_utils::collect_supported_files(input_path)?
+            .into_iter()
+            .next()
+        {
+            Some(path) => path,
+            None => {
+                print_no_supported_files(&file_utils::unsupported_extensions(input_path)?);
+                return Ok(());
+            }
+        }
+    } else {
+        input_path.to_path_buf()
+    };
+    let path_str = sample_path
+        .to_str()
+        .ok_or_else(|| NotedError::FileNameError(sample_path.to_string_lossy().to_string()))?;
+
+    eprintln!(
+        "{}",
+        format!("Sampling the first page of {:?}", sample_path).bold()
+    );
+    let (data, mime_type) = file_utils::read_file_sample(path_str, options.mime_type)?;
+
+    let spinner = ProgressBar::new_spinner();
+    spinner.set_style(
+        ProgressStyle::default_spinner()
+            .template("{spinner:.cyan} {msg}")
+            .unwrap(),
+    );
+    spinner.enable_steady_tick(std::time::Duration::from_millis(100));
+    // The same steps as a conversion, so the sample shows what convert would
+    // write.
+    let result = request_markdown(&data, &mime_type, client, &spinner, options).await;
+    spinner.finish_and_clear();
+
+    println!("{}", normalize_markdown(result?, options));
+    Ok(())
 }
 
 async fn run() -> Result<(), NotedError> {
@@ -133,34 +1138,81 @@ async fn run() -> Result<(), NotedError> {
             set_provider,
             show_path,
             show,
+            reveal,
             edit,
+            force,
+            init,
+            provider,
+            api_key,
+            model,
+            url,
+            migrate_openai_url,
+            edit_prompt,
         } => {
-            if show_path {
-                if let Some(config_path) = config::get_config_path() {
-                    if config_path.exists() {
-                        println!("Config saved in {:?}", config_path);
-                    } else {
-                        return Err(NotedError::ConfigNotFound);
-                    }
+            if show_path && let Some(config_path) = config::get_config_path() {
+                if config_path.exists() {
+                    println!("Config saved in {:?}", config_path);
+                } else {
+                    return Err(NotedError::ConfigNotFound);
                 }
             }
 
-            if show {
-                if let Some(config_path) = config::get_config_path() {
-                    if config_path.exists() {
-                        let config = Config::load()?;
-                        print_clean_config(config);
-                    } else {
-                        return Err(NotedError::ConfigNotFound);
-                    }
+            if show && let Some(config_path) = config::get_config_path() {
+                if config_path.exists() {
+                    let config = Config::load()?;
+                    // Ask before printing secrets on screen; scripts passing
+                    // --reveal get them without a prompt.
+                    let reveal = reveal
+                        && (!std::io::stdin().is_terminal()
+                            || Confirm::with_theme(&ColorfulTheme::default())
+                                .with_prompt("Print your API keys in plain text?")
+                                .default(false)
+                                .interact()?);
+                    print_clean_config(config, reveal);
+                } else {
+                    return Err(NotedError::ConfigNotFound);
+                }
+            }
+
+            if migrate_openai_url {
+                let mut config = Config::load()?;
+                let openai_config = config
+                    .openai
+                    .as_mut()
+                    .ok_or(NotedError::OpenAINotConfigured)?;
+                let normalized = config::normalize_openai_url(&openai_config.url);
+                if normalized == openai_config.url {
+                    println!("OpenAI url '{}' is already correct.", openai_config.url);
+                } else {
+                    println!(
+                        "Updating OpenAI url: '{}' -> '{}'",
+                        openai_config.url.red(),
+                        normalized.green()
+                    );
+                    openai_config.url = normalized;
+                    config.save()?;
+                    println!("Config saved successfully.");
+                }
+            }
+
+            if let Some(provider) = &edit_prompt {
+                let prompts_dir = config::get_prompts_dir().ok_or(NotedError::ConfigNotFound)?;
+                std::fs::create_dir_all(&prompts_dir)?;
+                let prompt_path = prompts_dir.join(format!("{}.txt", provider));
+                if !prompt_path.exists() {
+                    std::fs::write(&prompt_path, "")?;
                 }
+                open_in_editor(&prompt_path)?;
+                println!("Prompt saved in {:?}", prompt_path);
             }
 
             if let Some(ref key) = set_api_key {
                 let mut config = Config::load()?;
                 config.active_provider = Some("gemini".to_string());
+                let previous = config.gemini.take().unwrap_or_default();
                 config.gemini = Some(config::GeminiConfig {
                     api_key: key.to_string(),
+                    ..previous
                 });
 
                 config.save()?;
@@ -175,15 +1227,22 @@ async fn run() -> Result<(), NotedError> {
                     .default("claude-3-opus-20240229".to_string())
                     .interact_text()?;
 
+                let base_url = config.claude.take().and_then(|claude| claude.base_url);
                 config.claude = Some(config::ClaudeConfig {
                     api_key: key.to_string(),
                     model,
+                    base_url,
                 });
 
                 config.save()?;
                 println!("Config saved successfully.");
             }
 
+            if init {
+                let provider = provider.expect("clap requires --provider with --init");
+                init_config(&provider, api_key, model, url)?;
+            }
+
             if edit {
                 ascii_art();
                 println!(
@@ -210,9 +1269,14 @@ async fn run() -> Result<(), NotedError> {
                             .with_prompt("Enter your Gemini API key: ")
                             .interact()?;
                         config.active_provider = Some("gemini".to_string());
-                        config.gemini = Some(GeminiConfig { api_key });
-                        config.save()?;
-                        println!("{}", "Config saved successfully.".green());
+                        let previous = config.gemini.take().unwrap_or_default();
+                        config.gemini = Some(GeminiConfig {
+                            api_key,
+                            ..previous
+                        });
+                        if save_wizard_config(&config, force)? {
+                            println!("{}", "Config saved successfully.".green());
+                        }
                     }
                     1 => {
                         let mut config = Config::load()?;
@@ -242,36 +1306,48 @@ async fn run() -> Result<(), NotedError> {
                             anthropic_models[selected_model].trim().to_string()
                         };
 
-                        config.claude = Some(ClaudeConfig { api_key, model });
-                        config.save()?;
-                        println!("{}", "Config saved successfully.".green());
+                        let previous = config.claude.take().unwrap_or_default();
+                        config.claude = Some(ClaudeConfig {
+                            api_key,
+                            model,
+                            ..previous
+                        });
+                        if save_wizard_config(&config, force)? {
+                            println!("{}", "Config saved successfully.".green());
+                        }
                     }
                     2 => {
                         let url = Input::with_theme(&ColorfulTheme::default())
                             .with_prompt("Ollama server url")
-                            .default("http://localhost:11434".to_string())
+                            .default(config::DEFAULT_OLLAMA_URL.to_string())
                             .interact_text()?;
 
                         let model = Input::with_theme(&ColorfulTheme::default())
                             .with_prompt("Ollama model")
-                            .default("gemma3:27b".to_string())
+                            .default(config::DEFAULT_LOCAL_MODEL.to_string())
                             .interact_text()?;
 
                         let mut config = Config::load()?;
                         config.active_provider = Some("ollama".to_string());
-                        config.ollama = Some(OllamaConfig { url, model });
-                        config.save()?;
-                        println!("{}", "Config saved successfully.".green());
+                        let previous = config.ollama.take().unwrap_or_default();
+                        config.ollama = Some(OllamaConfig {
+                            url,
+                            model,
+                            ..previous
+                        });
+                        if save_wizard_config(&config, force)? {
+                            println!("{}", "Config saved successfully.".green());
+                        }
                     }
                     3 => {
                         let url = Input::with_theme(&ColorfulTheme::default())
                             .with_prompt("Server url")
-                            .default("http://localhost:1234".to_string())
+                            .default(config::DEFAULT_OPENAI_URL.to_string())
                             .interact_text()?;
 
                         let model = Input::with_theme(&ColorfulTheme::default())
                             .with_prompt("Model")
-                            .default("gemma3:27b".to_string())
+                            .default(config::DEFAULT_LOCAL_MODEL.to_string())
                             .interact_text()?;
 
                         let api_key_str = Password::with_theme(&ColorfulTheme::default())
@@ -287,13 +1363,16 @@ async fn run() -> Result<(), NotedError> {
 
                         let mut config = Config::load()?;
                         config.active_provider = Some("openai".to_string());
+                        let previous = config.openai.take().unwrap_or_default();
                         config.openai = Some(OpenAIConfig {
-                            url,
+                            url: config::normalize_openai_url(&url),
                             model,
                             api_key,
+                            ..previous
                         });
-                        config.save()?;
-                        println!("{}", "Config saved successfully.".green());
+                        if save_wizard_config(&config, force)? {
+                            println!("{}", "Config saved successfully.".green());
+                        }
                     }
                     _ => unreachable!(),
                 }
@@ -310,6 +1389,9 @@ async fn run() -> Result<(), NotedError> {
                     let database_id = Password::with_theme(&ColorfulTheme::default())
                         .with_prompt("Enter your Notion Database ID: ")
                         .interact()?;
+                    // A configured proxy is kept, so the schema comes through it too.
+                    let previous_base_url =
+                        Config::load()?.notion.and_then(|notion| notion.base_url);
 
                     let spinner = ProgressBar::new_spinner();
                     spinner.set_style(
@@ -320,7 +1402,12 @@ async fn run() -> Result<(), NotedError> {
                     spinner.set_message("Fetching Notion database schema...");
                     spinner.enable_steady_tick(std::time::Duration::from_millis(100));
 
-                    let client = NotionClient::new(api_key.clone(), database_id.clone());
+                    let client = NotionClient::new(
+                        api_key.clone(),
+                        database_id.clone(),
+                        DEFAULT_NOTION_API_VERSION.to_string(),
+                        previous_base_url.clone(),
+                    );
                     let schema_result = client.get_database_schema().await;
                     spinner.finish_and_clear();
                     match schema_result {
@@ -342,15 +1429,16 @@ async fn run() -> Result<(), NotedError> {
                             let properties: Vec<_> = schema
                                 .properties
                                 .into_iter()
-                                .filter(|(_name, property)| match &property.type_specific_config {
-                                    PropertyType::Select { .. }
-                                    | PropertyType::MultiSelect { .. }
-                                    | PropertyType::RichText(_)
-                                    | PropertyType::Number(_)
-                                    | PropertyType::Date(_)
-                                    | PropertyType::Checkbox(_) => true,
-
-                                    _ => false,
+                                .filter(|(_name, property)| {
+                                    matches!(
+                                        &property.type_specific_config,
+                                        PropertyType::Select { .. }
+                                            | PropertyType::MultiSelect { .. }
+                                            | PropertyType::RichText(_)
+                                            | PropertyType::Number(_)
+                                            | PropertyType::Date(_)
+                                            | PropertyType::Checkbox(_)
+                                    )
                                 })
                                 .collect();
 
@@ -481,13 +1569,25 @@ async fn run() -> Result<(), NotedError> {
                             }
 
                             let mut config = Config::load()?;
+                            let previous = config.notion.take();
                             config.notion = Some(NotionConfig {
                                 api_key,
                                 database_id,
                                 title_property_name,
                                 properties: default_properties,
+                                source_property_name: previous
+                                    .as_ref()
+                                    .and_then(|notion| notion.source_property_name.clone()),
+                                language_property_name: previous
+                                    .as_ref()
+                                    .and_then(|notion| notion.language_property_name.clone()),
+                                api_version: previous
+                                    .as_ref()
+                                    .map(|notion| notion.api_version.clone())
+                                    .unwrap_or_else(|| DEFAULT_NOTION_API_VERSION.to_string()),
+                                base_url: previous.and_then(|notion| notion.base_url),
                             });
-                            config.save()?;
+                            save_wizard_config(&config, force)?;
                         }
                         Err(e) => eprintln!("{}", e),
                     }
@@ -498,127 +1598,210 @@ async fn run() -> Result<(), NotedError> {
                 );
             }
 
-            if let Some(ref new_provider) = set_provider {
-                if let Some(config_path) = get_config_path() {
-                    if !config_path.exists() {
-                        return Err(NotedError::ConfigNotFound);
-                    }
-
-                    let mut config = Config::load()?;
-                    let new_provider_str = new_provider.as_str();
-                    let is_configured = match new_provider_str {
-                        "gemini" => config.gemini.is_some(),
-                        "claude" => config.claude.is_some(),
-                        "ollama" => config.ollama.is_some(),
-                        "openai" => config.openai.is_some(),
-                        _ => {
-                            eprintln!(
-                                "Invalid provider '{}'. Please choose from 'gemini', 'claude', or 'ollama'.",
-                                new_provider
-                            );
-                            return Ok(());
-                        }
-                    };
+            if let Some(ref new_provider) = set_provider
+                && let Some(config_path) = get_config_path()
+            {
+                if !config_path.exists() {
+                    return Err(NotedError::ConfigNotFound);
+                }
 
-                    if is_configured {
-                        config.active_provider = Some(new_provider_str.to_string());
-                        config.save()?;
-                        println!("Active provider set to '{}'.", new_provider_str.cyan());
-                    } else {
+                let mut config = Config::load()?;
+                let new_provider_str = new_provider.as_str();
+                let is_configured = match new_provider_str {
+                    "gemini" => config.gemini.is_some(),
+                    "claude" => config.claude.is_some(),
+                    "ollama" => config.ollama.is_some(),
+                    "openai" => config.openai.is_some(),
+                    _ => {
                         eprintln!(
-                            "{} is not configured. Please run 'notedmd config --edit' to set it up.",
-                            new_provider_str.yellow()
+                            "Invalid provider '{}'. Please choose from 'gemini', 'claude', or 'ollama'.",
+                            new_provider
                         );
+                        return Ok(());
                     }
+                };
+
+                if is_configured {
+                    config.active_provider = Some(new_provider_str.to_string());
+                    config.save()?;
+                    println!("Active provider set to '{}'.", new_provider_str.cyan());
+                } else {
+                    eprintln!(
+                        "{} is not configured. Please run 'notedmd config --edit' to set it up.",
+                        new_provider_str.yellow()
+                    );
                 }
             }
 
             if !edit
+                && !init
                 && !show
                 && !show_path
                 && set_api_key.is_none()
                 && set_claude_api_key.is_none()
                 && set_provider.is_none()
+                && !migrate_openai_url
+                && edit_prompt.is_none()
+                && let Some(config_path) = get_config_path()
             {
-                if let Some(config_path) = get_config_path() {
-                    if config_path.exists() {
-                        let config = Config::load()?;
-                        print_clean_config(config);
-                    } else {
-                        return Err(NotedError::ConfigNotFound);
-                    }
+                if config_path.exists() {
+                    let config = Config::load()?;
+                    print_clean_config(config, false);
+                } else {
+                    return Err(NotedError::ConfigNotFound);
                 }
             }
         }
         Commands::Convert {
             path,
+            input_list,
+            file_type,
             output,
             api_key,
             prompt,
+            prompt_append,
             notion,
+            sample,
+            pace,
+            rpm,
+            output_encoding,
+            line_endings,
+            fsync,
+            backup,
+            backup_keep,
+            list_supported,
+            dedupe,
+            skip_blank_pages,
+            blank_threshold,
+            skip_non_documents,
+            title,
+            notion_skip_existing,
+            notion_database,
+            tile,
+            tile_size,
+            tile_overlap,
+            jpeg_quality,
+            png_compression,
+            prompt_preset,
+            list_presets,
+            on_empty,
+            no_cleanup,
+            no_math_fix,
+            math_style,
+            inline_math_style,
+            detect_language,
+            max_output_chars,
+            explain_errors,
+            verbose,
+            stats,
+            review,
+            append,
+            append_heading,
+            extract_images,
+            use_outline,
+            embed_source_thumbnail,
+            date_subdirs,
+            no_resume,
+            abort_on_error,
+            temperature,
+            system_prompt,
+            stop_sequences,
+            seed,
+            auto_continue,
+            summary,
+            document_context,
+            image_detail,
+            provider_config,
+            no_project_config,
+            provider,
+            provider_endpoint,
+            compare,
+            move_done,
+            move_failed,
+            output_stdout_json,
         } => {
-            let config = Config::load()?;
-            let client: Box<dyn AiProvider> = match config.active_provider.as_deref() {
-                Some("gemini") => {
-                    let final_api_key = if let Some(key) = api_key {
-                        key
-                    } else if let Some(gemini_config) = &config.gemini {
-                        gemini_config.api_key.clone()
-                    } else {
-                        return Err(NotedError::GeminiNotConfigured);
-                    };
-                    Box::new(GeminiClient::new(final_api_key, prompt))
-                }
-                Some("ollama") => {
-                    let url = if let Some(ollama_config) = &config.ollama {
-                        ollama_config.url.clone()
-                    } else {
-                        return Err(NotedError::OllamaNotConfigured);
-                    };
-                    let model = if let Some(ollama_config) = &config.ollama {
-                        ollama_config.model.clone()
-                    } else {
-                        return Err(NotedError::OllamaNotConfigured);
-                    };
-                    Box::new(OllamaClient::new(url, model, prompt))
-                }
-                Some("claude") => {
-                    let api_key = if let Some(key) = api_key {
-                        key
-                    } else if let Some(claude_config) = &config.claude {
-                        claude_config.api_key.clone()
-                    } else {
-                        return Err(NotedError::ClaudeNotConfigured);
-                    };
-
-                    let model = if let Some(claude_config) = &config.claude {
-                        claude_config.model.clone()
-                    } else {
-                        return Err(NotedError::ClaudeNotConfigured);
-                    };
+            if list_supported {
+                print_supported_file_types();
+                return Ok(());
+            }
+            if list_presets {
+                print_prompt_presets();
+                return Ok(());
+            }
+            // With --input-list, the list file stands in for <PATH>, e.g. to
+            // find the progress of an interrupted run.
+            let path = path
+                .or_else(|| input_list.clone())
+                .expect("clap requires <PATH> or --input-list unless a --list-* flag is set");
 
-                    Box::new(ClaudeClient::new(api_key, model, prompt))
-                }
-                Some("openai") => {
-                    let url = if let Some(openai_config) = &config.openai {
-                        openai_config.url.clone()
-                    } else {
-                        return Err(NotedError::OpenAINotConfigured);
-                    };
-                    let model = if let Some(openai_config) = &config.openai {
-                        openai_config.model.clone()
-                    } else {
-                        return Err(NotedError::OpenAINotConfigured);
-                    };
-                    let api_key = if let Some(openai_config) = &config.openai {
-                        openai_config.api_key.clone()
-                    } else {
-                        return Err(NotedError::OpenAINotConfigured);
-                    };
-                    Box::new(OpenAIClient::new(url, model, api_key, prompt))
-                }
-                _ => return Err(NotedError::NoActiveProvider),
+            let project_config = if no_project_config {
+                None
+            } else {
+                std::env::current_dir()
+                    .ok()
+                    .and_then(|dir| config::find_project_config(&dir))
+            };
+            if let Some(project_config) = &project_config {
+                eprintln!(
+                    "{}",
+                    format!("Using project config {:?}", project_config).dimmed()
+                );
+            }
+            let mut config =
+                Config::load_with_overrides(project_config.as_deref(), provider_config.as_slice())?;
+            if let Some(endpoint) = &provider_endpoint
+                && let Some(provider) = provider.clone().or(config.active_provider.clone())
+            {
+                config.override_base_url(&provider, endpoint);
+            }
+            let images_config = config.images.unwrap_or_default();
+            let image_encoding = ImageEncoding {
+                jpeg_quality: jpeg_quality.or(images_config.jpeg_quality),
+                png_compression: png_compression.or(images_config.png_compression),
             };
+            image_encoding.check()?;
+            let stream_progress: Arc<OnceLock<ProgressBar>> = Arc::default();
+            let client_options = ClientOptions {
+                prompt: if summary {
+                    Some(prompt_presets::SUMMARY_PROMPT.to_string())
+                } else {
+                    prompt.or_else(|| prompt_preset.map(|preset| preset.prompt().to_string()))
+                },
+                prompt_append,
+                document_context,
+                temperature,
+                seed,
+                auto_continue,
+                image_encoding,
+                system_prompt,
+                stop_sequences,
+                // One pacer for the whole run, so routed clients and both
+                // --compare providers share the same budget.
+                pacer: rpm.map(|rpm| Arc::new(RequestPacer::per_minute(rpm))),
+                on_delta: Some(stream_display(stream_progress.clone())),
+            };
+            let run_stats = stats.then(RunStats::start);
+            let extension = if summary {
+                output::SUMMARY_EXTENSION
+            } else {
+                output::MARKDOWN_EXTENSION
+            };
+            let output_options = OutputOptions {
+                encoding: output_encoding,
+                line_endings,
+                fsync,
+                backups: backup.then_some(backup_keep),
+            };
+            if provider.is_none() {
+                config.check_routes()?;
+            }
+            let client = build_client(
+                provider.as_deref().or(config.active_provider.as_deref()),
+                &config,
+                api_key.clone(),
+                image_detail,
+                client_options.clone(),
+            )?;
 
             let input_path = Path::new(&path);
             if !input_path.exists() {
@@ -627,39 +1810,241 @@ async fn run() -> Result<(), NotedError> {
                     format!("Input path not found: {}", path),
                 )));
             }
-            let (notion_client, notion_config) = if notion {
-                if let Some(config) = &config.notion {
-                    let client =
-                        NotionClient::new(config.api_key.clone(), config.database_id.clone());
-                    (Some(client), Some(config))
-                } else {
-                    return Err(NotedError::NotionNotConfigured);
+            let mime_type = match &file_type {
+                Some(_) if input_path.is_dir() => {
+                    return Err(NotedError::InvalidArgument(
+                        "--as works on a single file, not a directory".to_string(),
+                    ));
                 }
+                Some(file_type) => Some(file_utils::parse_file_type(file_type)?),
+                None => None,
+            };
+            let mut sinks: Vec<Box<dyn OutputSink>> = Vec::new();
+            if output_stdout_json {
+                sinks.push(Box::new(StdoutSink));
+            } else if let Some(append) = &append {
+                sinks.push(Box::new(AppendSink {
+                    path: Path::new(append),
+                    heading: append_heading.as_deref(),
+                    options: output_options,
+                }));
             } else {
-                (None, None)
+                sinks.push(Box::new(FileSink {
+                    output_dir: output.as_deref(),
+                    date_subdirs,
+                    extension,
+                    options: output_options,
+                }));
+            }
+            if notion {
+                let notion_config = config
+                    .notion
+                    .as_ref()
+                    .ok_or(NotedError::NotionNotConfigured)?;
+                let database_id = match &notion_database {
+                    Some(id) => notion_client::validate_database_id(id)?,
+                    None => notion_config.database_id.clone(),
+                };
+                sinks.push(Box::new(NotionSink {
+                    client: NotionClient::new(
+                        notion_config.api_key.clone(),
+                        database_id,
+                        notion_config.api_version.clone(),
+                        notion_config.base_url.clone(),
+                    ),
+                    config: notion_config,
+                    title: title.as_deref(),
+                    skip_existing: notion_skip_existing,
+                }));
+            }
+
+            if !compare.is_empty() && (compare.len() != 2 || compare[0] == compare[1]) {
+                return Err(NotedError::InvalidArgument(
+                    "--compare needs two different providers, e.g. --compare gemini,claude"
+                        .to_string(),
+                ));
+            }
+
+            if tile && tile_overlap >= tile_size {
+                return Err(NotedError::InvalidArgument(
+                    "--tile-overlap must be smaller than --tile-size".to_string(),
+                ));
+            }
+
+            let review_enabled =
+                review && std::io::stdin().is_terminal() && std::io::stdout().is_terminal();
+            if review && !review_enabled {
+                eprintln!(
+                    "{}",
+                    "--review needs an interactive terminal, saving results without review."
+                        .yellow()
+                );
+            }
+
+            let convert_options = ConvertOptions {
+                output_dir: output.as_deref(),
+                date_subdirs,
+                mime_type: mime_type.as_deref(),
+                sinks: &sinks,
+                output: output_options,
+                extension,
+                dedupe,
+                blank_threshold: skip_blank_pages.then_some(blank_threshold),
+                skip_non_documents,
+                tile: tile.then_some(TileOptions {
+                    size: tile_size,
+                    overlap: tile_overlap,
+                }),
+                image_encoding,
+                on_empty,
+                cleanup: !no_cleanup,
+                math: (!no_math_fix).then_some(MathOptions {
+                    display: math_style,
+                    inline: inline_math_style,
+                }),
+                detect_language,
+                max_output_chars,
+                explain_errors,
+                verbose,
+                stats: run_stats.as_ref(),
+                review: review_enabled,
+                extract_images,
+                use_outline,
+                embed_source_thumbnail,
+                move_done: move_done.as_deref(),
+                move_failed: move_failed.as_deref(),
             };
+            if sample {
+                return sample_file(input_path, client.as_ref(), &convert_options).await;
+            }
+            let mut converted_files = HashMap::new();
 
-            if input_path.is_dir() {
-                let files_to_convert: Vec<_> = std::fs::read_dir(input_path)?
-                    .filter_map(Result::ok)
-                    .filter_map(|entry| {
-                        let path = entry.path();
-                        if path.is_file() {
-                            if let Some(path_str) = path.to_str() {
-                                if file_utils::get_file_mime_type(path_str).is_ok() {
-                                    return Some(path);
-                                }
-                            }
-                        }
-                        None
-                    })
-                    .collect();
+            if let [first, second] = compare.as_slice() {
+                if input_path.is_dir() {
+                    return Err(NotedError::InvalidArgument(
+                        "--compare works on a single file, not a directory".to_string(),
+                    ));
+                }
+                let first_client = build_client(
+                    Some(first),
+                    &config,
+                    None,
+                    image_detail,
+                    client_options.clone(),
+                )?;
+                let second_client =
+                    build_client(Some(second), &config, None, image_detail, client_options)?;
+                compare_providers(
+                    input_path,
+                    first_client.as_ref(),
+                    second_client.as_ref(),
+                    &convert_options,
+                )
+                .await?;
+                if let Some(run_stats) = &run_stats {
+                    let (totals, elapsed) = run_stats.finish();
+                    print_run_stats(&totals, elapsed);
+                }
+                return Ok(());
+            }
+
+            let mut router = ClientRouter {
+                config: &config,
+                default_client: client,
+                routed_clients: HashMap::new(),
+                sidecar_client: None,
+                use_routes: provider.is_none(),
+                api_key,
+                image_detail,
+                client_options: &client_options,
+            };
+
+            if input_list.is_some() || input_path.is_dir() {
+                let files_to_convert = if input_list.is_some() {
+                    let (files, missing) = file_utils::read_input_list(input_path)?;
+                    for path in missing {
+                        eprintln!(
+                            "{}",
+                            format!("Skipping {:?}: file not found.", path).yellow()
+                        );
+                    }
+                    files
+                } else {
+                    file_utils::collect_supported_files(input_path)?
+                };
 
                 if files_to_convert.is_empty() {
-                    println!("No supported files found in the directory.");
+                    if input_list.is_some() {
+                        println!("{}", "No files to convert in the input list.".yellow());
+                    } else {
+                        print_no_supported_files(&file_utils::unsupported_extensions(input_path)?);
+                    }
                     return Ok(());
                 }
 
+                let collisions = output::output_collisions(
+                    &files_to_convert,
+                    convert_options.output_dir,
+                    convert_options.date_subdirs,
+                    convert_options.extension,
+                );
+                if !output_stdout_json && append.is_none() && !collisions.is_empty() {
+                    let listing = collisions
+                        .iter()
+                        .map(|(output_path, sources)| {
+                            let sources = sources
+                                .iter()
+                                .map(|source| source.to_string_lossy())
+                                .collect::<Vec<_>>()
+                                .join(", ");
+                            format!("  {} <- {}", output_path.to_string_lossy(), sources)
+                        })
+                        .collect::<Vec<_>>()
+                        .join("\n");
+                    return Err(NotedError::OutputCollision(listing));
+                }
+
+                // Everything that changes what a run writes, so a run with other
+                // settings doesn't skip files converted for this one.
+                let run_settings = format!(
+                    "{:?}",
+                    (
+                        router.default_client.name(),
+                        router.default_client.model(),
+                        &config.routes,
+                        (&output, date_subdirs),
+                        // The pacer only changes when requests go out.
+                        &ClientOptions {
+                            pacer: None,
+                            ..client_options.clone()
+                        },
+                        notion,
+                        &title,
+                        convert_options.cleanup,
+                        convert_options.math,
+                        convert_options.detect_language,
+                        convert_options.embed_source_thumbnail,
+                        &append,
+                    )
+                );
+                let mut manifest = if no_resume || output_stdout_json {
+                    None
+                } else {
+                    manifest::RunManifest::open(input_path, &run_settings)?
+                };
+                if let Some(manifest) = &manifest
+                    && manifest.completed_count() > 0
+                {
+                    eprintln!(
+                        "{}",
+                        format!(
+                            "Resuming an interrupted run: skipping {} file(s) already converted. Use --no-resume to start over.",
+                            manifest.completed_count()
+                        )
+                        .yellow()
+                    );
+                }
+
                 let progress_bar = ProgressBar::new(files_to_convert.len() as u64);
                 progress_bar.set_style(
                     ProgressStyle::default_bar()
@@ -667,32 +2052,107 @@ async fn run() -> Result<(), NotedError> {
                         .unwrap(),
                 );
                 progress_bar.set_message("Processing files...");
+                let _ = stream_progress.set(progress_bar.clone());
 
+                let mut summary = Vec::new();
+                let mut failed = 0;
                 for file_path_buf in files_to_convert {
+                    if manifest
+                        .as_ref()
+                        .is_some_and(|manifest| manifest.is_done(&file_path_buf))
+                    {
+                        progress_bar.inc(1);
+                        continue;
+                    }
+                    let client = match router
+                        .client_for(&file_path_buf, None)
+                        .map_err(|e| e.in_file(&file_path_buf))
+                    {
+                        Ok(client) => client,
+                        Err(e) if abort_on_error => return Err(abort_run(e, &progress_bar)),
+                        Err(e) => {
+                            progress_bar.println(format!("{}", e.to_string().red()));
+                            failed += 1;
+                            progress_bar.inc(1);
+                            continue;
+                        }
+                    };
+                    if pace && let Some(delay) = client.pacing_delay() {
+                        progress_bar.set_message(format!(
+                            "{}",
+                            format!(
+                                "Rate limit reached, waiting {}s before the next request...",
+                                delay.as_secs()
+                            )
+                            .yellow()
+                        ));
+                        tokio::time::sleep(delay).await;
+                        progress_bar.set_message("Processing files...");
+                    }
                     if let Some(file_path_str) = file_path_buf.to_str() {
-                        if let Err(e) = process_and_save_file(
+                        let result = process_and_save_file(
                             file_path_str,
-                            client.as_ref(),
-                            output.as_deref(),
+                            client,
                             &progress_bar,
-                            notion_client.as_ref(),
-                            notion_config,
+                            &convert_options,
+                            &mut converted_files,
                         )
                         .await
+                        .map_err(|e| e.in_file(&file_path_buf));
+                        move_source(&file_path_buf, &result, &convert_options, &progress_bar);
+                        if result.is_ok()
+                            && let Some(manifest) = manifest.as_mut()
+                            && let Err(e) = manifest.mark_done(&file_path_buf)
                         {
-                            progress_bar.println(format!("{}", e.to_string().red()));
+                            progress_bar.println(format!(
+                                "{}",
+                                format!("Failed to record progress for resuming: {}", e).yellow()
+                            ));
+                        }
+                        match result {
+                            Ok(Some(stats)) => summary.push((file_path_buf.clone(), stats)),
+                            Ok(None) => {}
+                            Err(e) if abort_on_error => return Err(abort_run(e, &progress_bar)),
+                            Err(e) => {
+                                progress_bar.println(format!("{}", e.to_string().red()));
+                                failed += 1;
+                            }
                         }
                     }
                     progress_bar.inc(1);
                 }
 
+                // Kept after failures, so that running again only retries those.
+                if let Some(manifest) = manifest {
+                    if failed == 0 {
+                        manifest.remove()?;
+                    } else {
+                        progress_bar.println(format!(
+                            "{}",
+                            format!(
+                                "{} file(s) failed. Run the same command again to retry only those.",
+                                failed
+                            )
+                            .yellow()
+                        ));
+                    }
+                }
                 progress_bar
                     .finish_with_message(format!("{}", "Completed processing all files".green()));
+                if !output_stdout_json {
+                    print_content_summary(&summary);
+                }
+                if let Some(run_stats) = &run_stats {
+                    let (totals, elapsed) = run_stats.finish();
+                    print_run_stats(&totals, elapsed);
+                }
             } else {
                 let path_str = input_path.to_str().ok_or_else(|| {
                     NotedError::FileNameError(input_path.to_string_lossy().to_string())
                 })?;
-                file_utils::get_file_mime_type(path_str)?;
+                if mime_type.is_none() {
+                    file_utils::get_file_mime_type(path_str)?;
+                }
                 let progress_bar = ProgressBar::new(1);
                 progress_bar.set_style(
                     ProgressStyle::default_bar()
@@ -700,23 +2160,171 @@ async fn run() -> Result<(), NotedError> {
                         .unwrap(),
                 );
                 progress_bar.set_message("Processing file...");
-                if let Err(e) = process_and_save_file(
+                let _ = stream_progress.set(progress_bar.clone());
+                let client = router.client_for(input_path, mime_type.as_deref())?;
+                let result = process_and_save_file(
                     path_str,
-                    client.as_ref(),
-                    output.as_deref(),
+                    client,
                     &progress_bar,
-                    notion_client.as_ref(),
-                    notion_config,
+                    &convert_options,
+                    &mut converted_files,
                 )
                 .await
-                {
-                    progress_bar.println(format!("{}", e.to_string().red()));
+                .map_err(|e| e.in_file(input_path));
+                move_source(input_path, &result, &convert_options, &progress_bar);
+                match result {
+                    Err(e) if abort_on_error => return Err(abort_run(e, &progress_bar)),
+                    Err(e) => progress_bar.println(format!("{}", e.to_string().red())),
+                    Ok(_) => {}
                 }
                 progress_bar.inc(1);
                 progress_bar
                     .finish_with_message(format!("{}", "Completed processing file".green()));
+                if let Some(run_stats) = &run_stats {
+                    let (totals, elapsed) = run_stats.finish();
+                    print_run_stats(&totals, elapsed);
+                }
             }
         }
+        Commands::Notion {
+            command:
+                NotionCommands::Upload {
+                    path,
+                    title,
+                    skip_existing,
+                },
+        } => {
+            let config = Config::load()?;
+            let notion_config = config
+                .notion
+                .as_ref()
+                .ok_or(NotedError::NotionNotConfigured)?;
+            let notion_client = NotionClient::new(
+                notion_config.api_key.clone(),
+                notion_config.database_id.clone(),
+                notion_config.api_version.clone(),
+                notion_config.base_url.clone(),
+            );
+            let source_path = Path::new(&path);
+            let markdown = std::fs::read_to_string(source_path)?;
+            let title = output::resolve_title(title.as_deref(), source_path);
+
+            let spinner = ProgressBar::new_spinner();
+            spinner.set_style(
+                ProgressStyle::default_spinner()
+                    .template("{spinner:.cyan} {msg}")
+                    .unwrap(),
+            );
+            spinner.set_message(format!("{}", "Uploading to Notion...".yellow()));
+            spinner.enable_steady_tick(std::time::Duration::from_millis(100));
+            let upload = upload_to_notion(
+                &notion_client,
+                notion_config,
+                source_path,
+                &title,
+                &markdown,
+                None,
+                skip_existing,
+            )
+            .await;
+            spinner.finish_and_clear();
+
+            println!("{}", upload?.message());
+        }
+        Commands::Serve {
+            host,
+            port,
+            provider,
+            api_key,
+            prompt,
+            prompt_preset,
+            temperature,
+            token,
+            max_upload_mb,
+        } => {
+            let config = Config::load()?;
+            let images_config = config.images.unwrap_or_default();
+            let image_encoding = ImageEncoding {
+                jpeg_quality: images_config.jpeg_quality,
+                png_compression: images_config.png_compression,
+            };
+            image_encoding.check()?;
+            let client_options = ClientOptions {
+                prompt: prompt.or_else(|| prompt_preset.map(|preset| preset.prompt().to_string())),
+                temperature,
+                image_encoding,
+                ..ClientOptions::default()
+            };
+            let client = build_client(
+                provider.as_deref().or(config.active_provider.as_deref()),
+                &config,
+                api_key,
+                ImageDetail::default(),
+                client_options,
+            )?;
+            let token = match token {
+                Some(token) => token,
+                None => {
+                    let token = server::generate_token()?;
+                    println!("Token: {}", token.cyan());
+                    token
+                }
+            };
+            let address = std::net::SocketAddr::new(host, port);
+            println!(
+                "{} {}",
+                "✔".green(),
+                format!(
+                    "Converting with {} on http://{}/convert (Ctrl+C to stop)",
+                    client.name(),
+                    address
+                )
+                .green()
+            );
+            server::serve(
+                client,
+                server::ServeOptions {
+                    address,
+                    token,
+                    max_body_bytes: max_upload_mb as usize * 1024 * 1024,
+                },
+            )
+            .await?;
+        }
+        Commands::Clean { manifests: _, all } => {
+            // Clap requires --manifests or --all, and --all includes the rest.
+            let (dir, what) = if all {
+                (config::get_cache_dir(), "all cached data")
+            } else {
+                (manifest::manifests_dir(), "progress of interrupted runs")
+            };
+            let Some(dir) = dir else {
+                println!("No cache directory found, nothing to clean.");
+                return Ok(());
+            };
+            let freed = file_utils::clear_dir(&dir)?;
+            println!(
+                "{} {}",
+                "✔".green(),
+                format!("Deleted {} ({} freed).", what, format_bytes(freed)).green()
+            );
+        }
+        Commands::DebugAst { path } => {
+            let markdown = std::fs::read_to_string(&path)?;
+            let arena = Arena::new();
+            let ast = Converter::ast_json(&markdown, &arena);
+            let blocks = Converter::run(&markdown, &arena)
+                .map_err(|e| NotedError::ApiError(e.to_string()))?;
+            let debug_output = serde_json::json!({
+                "ast": ast,
+                "blocks": blocks,
+            });
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&debug_output)
+                    .map_err(|e| NotedError::ResponseDecodeError(e.to_string()))?
+            );
+        }
     }
     Ok(())
 }
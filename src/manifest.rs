@@ -0,0 +1,136 @@
+use crate::{config, error::NotedError};
+use serde::Deserialize;
+use std::{
+    collections::BTreeSet,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Files a directory run has finished, saved after each one so that running
+/// the same directory with the same settings again skips them. The manifest is
+/// removed once a run converts every file, so only interrupted runs and runs
+/// with failed files resume.
+#[derive(Debug)]
+pub struct RunManifest {
+    path: PathBuf,
+    completed: BTreeSet<String>,
+}
+
+#[derive(Deserialize)]
+struct ManifestFile {
+    completed: BTreeSet<String>,
+}
+
+/// Directory holding the manifests of interrupted runs.
+pub fn manifests_dir() -> Option<PathBuf> {
+    config::get_cache_dir().map(|cache_dir| cache_dir.join("manifests"))
+}
+
+impl RunManifest {
+    /// Opens the manifest of the run over `dir` with `settings`, which should
+    /// cover everything that changes the output. Returns `None` when there is
+    /// no cache directory to keep it in.
+    pub fn open(dir: &Path, settings: &str) -> Result<Option<Self>, NotedError> {
+        match manifests_dir() {
+            Some(manifests_dir) => Self::open_in(&manifests_dir, dir, settings).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn open_in(manifests_dir: &Path, dir: &Path, settings: &str) -> Result<Self, NotedError> {
+        // The name has to stay the same across builds for a run to resume, so
+        // it can't come from std's hasher, whose algorithm may change.
+        let key = [
+            fs::canonicalize(dir)?.to_string_lossy().as_bytes(),
+            b"\0",
+            settings.as_bytes(),
+        ]
+        .concat();
+        let path = manifests_dir.join(format!("{:016x}.json", fnv1a(&key)));
+
+        let completed = match fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str::<ManifestFile>(&content)
+                .map(|file| file.completed)
+                .unwrap_or_default(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeSet::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self { path, completed })
+    }
+
+    /// Number of files already finished by an earlier, interrupted run.
+    pub fn completed_count(&self) -> usize {
+        self.completed.len()
+    }
+
+    pub fn is_done(&self, file: &Path) -> bool {
+        self.completed
+            .contains(&file.to_string_lossy().into_owned())
+    }
+
+    pub fn mark_done(&mut self, file: &Path) -> io::Result<()> {
+        self.completed.insert(file.to_string_lossy().into_owned());
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(
+            &self.path,
+            serde_json::to_string(&serde_json::json!({ "completed": &self.completed }))?,
+        )
+    }
+
+    /// Deletes the manifest once the run has converted every file.
+    pub fn remove(self) -> io::Result<()> {
+        match fs::remove_file(&self.path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// 64-bit FNV-1a hash.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TempDir;
+
+    #[test]
+    fn fnv1a_matches_the_reference_values() {
+        assert_eq!(fnv1a(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a(b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(fnv1a(b"foobar"), 0x8594_4171_f739_67e8);
+    }
+
+    #[test]
+    fn an_interrupted_run_resumes_where_it_stopped() {
+        let root = TempDir::new("manifest-resume");
+        let manifests = root.path().join("manifests");
+        let scans = root.path().join("scans");
+        fs::create_dir_all(&scans).unwrap();
+        let (first, second) = (scans.join("a.png"), scans.join("b.png"));
+
+        let mut manifest = RunManifest::open_in(&manifests, &scans, "gemini").unwrap();
+        manifest.mark_done(&first).unwrap();
+        // The run is interrupted here, before it gets to the second file.
+        drop(manifest);
+
+        let manifest = RunManifest::open_in(&manifests, &scans, "gemini").unwrap();
+        assert_eq!(manifest.completed_count(), 1);
+        assert!(manifest.is_done(&first));
+        assert!(!manifest.is_done(&second));
+
+        // A run with other settings starts over.
+        let other = RunManifest::open_in(&manifests, &scans, "claude").unwrap();
+        assert_eq!(other.completed_count(), 0);
+
+        manifest.remove().unwrap();
+        let manifest = RunManifest::open_in(&manifests, &scans, "gemini").unwrap();
+        assert_eq!(manifest.completed_count(), 0);
+    }
+}
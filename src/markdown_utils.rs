@@ -0,0 +1,633 @@
+use crate::ai_provider::TokenUsage;
+use crate::cli::{LineEnding, MathStyle};
+
+/// Size of a converted file's markdown, used to spot under-transcribed pages.
+#[derive(Debug, Clone, Copy)]
+pub struct ContentStats {
+    pub characters: usize,
+    /// Rough estimate at four characters per token; providers count differently.
+    pub tokens: usize,
+    /// Tokens the provider reported for the requests behind this result.
+    pub usage: Option<TokenUsage>,
+}
+
+impl ContentStats {
+    pub fn of(markdown: &str) -> Self {
+        let characters = markdown.chars().count();
+        Self {
+            characters,
+            tokens: characters.div_ceil(4),
+            usage: None,
+        }
+    }
+}
+
+/// Strips the wrapping some models put around their answer: a single outer
+/// code fence of any language tag (possibly nested more than once) and a JSON
+/// object carrying the markdown in a `content` field.
+pub fn clean_markdown(text: &str) -> String {
+    let mut current = text.trim().to_string();
+    loop {
+        if let Some(inner) = unwrap_outer_fence(&current) {
+            current = inner;
+        } else if let Some(inner) = unwrap_json_content(&current) {
+            current = inner;
+        } else {
+            return current;
+        }
+    }
+}
+
+fn is_fence(line: &str) -> bool {
+    line.trim_start().starts_with("```")
+}
+
+fn fence_info(line: &str) -> &str {
+    line.trim().trim_start_matches('`').trim()
+}
+
+/// Returns the body of `text` if the whole of it is one fenced block.
+fn unwrap_outer_fence(text: &str) -> Option<String> {
+    let lines: Vec<&str> = text.lines().collect();
+    if lines.len() < 2 || !is_fence(lines[0]) {
+        return None;
+    }
+    let last = lines.len() - 1;
+    if !is_fence(lines[last]) || !fence_info(lines[last]).is_empty() {
+        return None;
+    }
+
+    // Make sure the final fence closes the opening one and not an inner block,
+    // otherwise a response that merely starts and ends with code would be
+    // stripped.
+    let mut depth = 0;
+    for line in &lines[1..last] {
+        if !is_fence(line) {
+            continue;
+        }
+        if !fence_info(line).is_empty() {
+            depth += 1;
+        } else if depth > 0 {
+            depth -= 1;
+        } else {
+            return None;
+        }
+    }
+
+    Some(lines[1..last].join("\n").trim().to_string())
+}
+
+/// Returns the `content` string if `text` is a JSON object holding one.
+fn unwrap_json_content(text: &str) -> Option<String> {
+    if !text.starts_with('{') {
+        return None;
+    }
+    let value: serde_json::Value = serde_json::from_str(text).ok()?;
+    value
+        .get("content")
+        .and_then(|content| content.as_str())
+        .map(|content| content.trim().to_string())
+}
+
+/// Converts every line ending in `text` (`\r\n`, `\n` or a lone `\r`) to
+/// `line_ending`.
+pub fn normalize_line_endings(text: &str, line_ending: LineEnding) -> String {
+    let normalized = text.replace("\r\n", "\n").replace('\r', "\n");
+    match line_ending {
+        LineEnding::Lf => normalized,
+        LineEnding::Crlf => normalized.replace('\n', "\r\n"),
+    }
+}
+
+/// Cuts `text` down to `max_chars` characters and marks the cut with an HTML
+/// comment. Returns `None` when the text already fits.
+pub fn truncate_runaway(text: &str, max_chars: usize) -> Option<String> {
+    let (end, _) = text.char_indices().nth(max_chars)?;
+    Some(format!(
+        "{}\n\n<!-- notedmd: output cut off after {} characters, the response was longer than --max-output-chars -->",
+        &text[..end],
+        max_chars
+    ))
+}
+
+/// Trims trailing whitespace from every line and ends the text with exactly
+/// one newline, as markdown linters expect. Two or more trailing spaces mark
+/// a hard line break and are kept as two, and lines inside code blocks are
+/// left alone. Line endings come out as `\n`; blank text comes out empty.
+pub fn tidy_whitespace(text: &str) -> String {
+    let mut lines = Vec::new();
+    let mut in_fence = false;
+    for line in text.lines() {
+        if in_fence && !is_fence(line) {
+            lines.push(line.to_string());
+            continue;
+        }
+        if is_fence(line) {
+            in_fence = !in_fence;
+            lines.push(line.trim_end().to_string());
+            continue;
+        }
+        let trimmed = line.trim_end();
+        let trailing = &line[trimmed.len()..];
+        if !trimmed.is_empty() && trailing.len() >= 2 && trailing.bytes().all(|byte| byte == b' ') {
+            lines.push(format!("{}  ", trimmed));
+        } else {
+            lines.push(trimmed.to_string());
+        }
+    }
+    let mut tidied = lines.join("\n").trim_end().to_string();
+    if !tidied.is_empty() {
+        tidied.push('\n');
+    }
+    tidied
+}
+
+/// How `fix_math_delimiters` writes display and inline math.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MathOptions {
+    pub display: MathStyle,
+    pub inline: MathStyle,
+}
+
+/// Puts display math (`$$...$$`) on lines of its own and joins inline math
+/// (`$...$`) that was broken over several lines, neither of which Obsidian
+/// renders. With the GitHub styles, display math becomes a ```` ```math ````
+/// block and inline math `` $`...`$ ``. Code blocks and code spans are left
+/// alone.
+pub fn fix_math_delimiters(text: &str, options: MathOptions) -> String {
+    let mut blocks: Vec<String> = Vec::new();
+    let mut prose: Vec<&str> = Vec::new();
+    let mut in_fence = false;
+    for line in text.split('\n') {
+        if in_fence {
+            blocks.push(line.to_string());
+            in_fence = !is_fence(line);
+        } else if is_fence(line) {
+            if !prose.is_empty() {
+                blocks.push(fix_math_in_prose(&prose.join("\n"), options));
+                prose.clear();
+            }
+            blocks.push(line.to_string());
+            in_fence = true;
+        } else {
+            prose.push(line);
+        }
+    }
+    if !prose.is_empty() {
+        blocks.push(fix_math_in_prose(&prose.join("\n"), options));
+    }
+    blocks.join("\n")
+}
+
+fn fix_math_in_prose(text: &str, options: MathOptions) -> String {
+    let bytes = text.as_bytes();
+    let mut fixed = String::with_capacity(text.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => {
+                let end = text[i + 1..]
+                    .chars()
+                    .next()
+                    .map_or(i + 1, |c| i + 1 + c.len_utf8());
+                fixed.push_str(&text[i..end]);
+                i = end;
+            }
+            b'`' => {
+                let end = code_span_end(text, i);
+                fixed.push_str(&text[i..end]);
+                i = end;
+            }
+            b'$' if bytes.get(i + 1) == Some(&b'$') => match find_unescaped(text, i + 2, "$$") {
+                Some(close) => i = push_display_math(&mut fixed, text, i, close, options.display),
+                None => {
+                    fixed.push_str("$$");
+                    i += 2;
+                }
+            },
+            b'$' => match inline_math_end(text, i) {
+                Some(close) => {
+                    let body = &text[i + 1..close];
+                    let body = if body.contains('\n') {
+                        body.split('\n')
+                            .map(str::trim)
+                            .collect::<Vec<_>>()
+                            .join(" ")
+                    } else {
+                        body.to_string()
+                    };
+                    match options.inline {
+                        MathStyle::Dollars => fixed.push_str(&format!("${}$", body)),
+                        MathStyle::Github => {
+                            // Already `$`...`$` when the body is wrapped in backticks.
+                            let body = body
+                                .strip_prefix('`')
+                                .and_then(|body| body.strip_suffix('`'))
+                                .unwrap_or(&body);
+                            fixed.push_str(&format!("$`{}`$", body));
+                        }
+                    }
+                    i = close + 1;
+                }
+                None => {
+                    fixed.push('$');
+                    i += 1;
+                }
+            },
+            _ => {
+                let end = text[i..]
+                    .find(['\\', '`', '$'])
+                    .map_or(text.len(), |offset| i + offset);
+                fixed.push_str(&text[i..end]);
+                i = end;
+            }
+        }
+    }
+    fixed
+}
+
+/// Returns the end of the code span opened by the backticks at `start`, or
+/// the end of those backticks when nothing closes them.
+fn code_span_end(text: &str, start: usize) -> usize {
+    let run = text[start..].len() - text[start..].trim_start_matches('`').len();
+    let ticks = &text[start..start + run];
+    let mut search = start + run;
+    while let Some(offset) = text[search..].find(ticks) {
+        let found = search + offset;
+        let found_run = text[found..].len() - text[found..].trim_start_matches('`').len();
+        if found_run == run {
+            return found + run;
+        }
+        search = found + found_run;
+    }
+    start + run
+}
+
+/// Returns the position of the first `pattern` at or after `from` that is
+/// not escaped with a backslash.
+fn find_unescaped(text: &str, from: usize, pattern: &str) -> Option<usize> {
+    let bytes = text.as_bytes();
+    let mut j = from;
+    while j < bytes.len() {
+        if bytes[j] == b'\\' {
+            j += 2;
+        } else if bytes[j..].starts_with(pattern.as_bytes()) {
+            return Some(j);
+        } else {
+            j += 1;
+        }
+    }
+    None
+}
+
+/// Writes the display math between the `$$` at `open` and the one at `close`
+/// on lines of its own, leaving `$$` blocks as they are when they already
+/// are. Returns where to continue reading.
+fn push_display_math(
+    fixed: &mut String,
+    text: &str,
+    open: usize,
+    close: usize,
+    style: MathStyle,
+) -> usize {
+    let body = &text[open + 2..close];
+    let after = close + 2;
+    let rest_of_line = text[after..].split('\n').next().unwrap_or("");
+    let current_line = &fixed[fixed.rfind('\n').map_or(0, |n| n + 1)..];
+    let own_lines = style == MathStyle::Dollars
+        && current_line.trim().is_empty()
+        && rest_of_line.trim().is_empty()
+        && body
+            .split_once('\n')
+            .is_some_and(|(first, _)| first.trim().is_empty())
+        && body
+            .rsplit_once('\n')
+            .is_some_and(|(_, last)| last.trim().is_empty());
+    if own_lines {
+        fixed.push_str(&text[open..after]);
+        return after;
+    }
+
+    if !current_line.trim().is_empty() {
+        fixed.truncate(fixed.trim_end_matches([' ', '\t']).len());
+        fixed.push('\n');
+    }
+    let (opening, closing) = match style {
+        MathStyle::Dollars => ("$$", "$$"),
+        MathStyle::Github => ("```math", "```"),
+    };
+    fixed.push_str(&format!("{}\n{}\n{}", opening, body.trim(), closing));
+    if rest_of_line.trim().is_empty() {
+        return after;
+    }
+    fixed.push('\n');
+    after + (rest_of_line.len() - rest_of_line.trim_start().len())
+}
+
+/// Returns the position of the `$` closing the inline math opened at `open`.
+/// Follows pandoc's rules so that prices like "$5 and $10" aren't taken for
+/// math: the opening `$` is followed by a non-space, the closing one is
+/// preceded by a non-space and not followed by a digit, and math doesn't run
+/// past a blank line.
+fn inline_math_end(text: &str, open: usize) -> Option<usize> {
+    let bytes = text.as_bytes();
+    if bytes.get(open + 1).is_none_or(u8::is_ascii_whitespace) {
+        return None;
+    }
+    let mut j = open + 1;
+    while j < bytes.len() {
+        match bytes[j] {
+            b'\\' => j += 2,
+            b'\n' => {
+                if text[j + 1..]
+                    .split('\n')
+                    .next()
+                    .is_none_or(|line| line.trim().is_empty())
+                {
+                    return None;
+                }
+                j += 1;
+            }
+            b'$' if bytes.get(j + 1) == Some(&b'$') => return None,
+            b'$' if !bytes[j - 1].is_ascii_whitespace()
+                && !bytes.get(j + 1).is_some_and(u8::is_ascii_digit) =>
+            {
+                return Some(j);
+            }
+            _ => j += 1,
+        }
+    }
+    None
+}
+
+/// Returns the ISO 639-3 code of the language `markdown` is written in, or
+/// `"unknown"` when the text is too short or mixed to tell reliably.
+pub fn detect_language(markdown: &str) -> &'static str {
+    match whatlang::detect(markdown) {
+        Some(info) if info.is_reliable() => info.lang().code(),
+        _ => "unknown",
+    }
+}
+
+/// Records `language` in the front matter of `markdown`: merged into the
+/// front matter it starts with, replacing any `language` already there, or in
+/// a new block at the top.
+pub fn with_language(markdown: &str, language: Option<&str>) -> String {
+    let Some(language) = language else {
+        return markdown.to_string();
+    };
+    let entry = format!("language: {}", language);
+    if let Some(rest) = markdown.strip_prefix("---\n")
+        && let Some(end) = rest
+            .find("\n---\n")
+            .or_else(|| rest.ends_with("\n---").then(|| rest.len() - "\n---".len()))
+    {
+        let mut lines: Vec<&str> = rest[..end]
+            .lines()
+            .filter(|line| !line.starts_with("language:"))
+            .collect();
+        lines.push(&entry);
+        return format!("---\n{}{}", lines.join("\n"), &rest[end..]);
+    }
+    format!("---\n{}\n---\n\n{}", entry, markdown)
+}
+
+/// Joins the markdown of consecutive tiles, dropping the lines at the start of
+/// a tile that repeat the end of the previous one because the tiles overlap.
+/// Whitespace-only tiles are left out.
+pub fn stitch_markdown(parts: &[String]) -> String {
+    let mut lines: Vec<&str> = Vec::new();
+    for part in parts.iter().filter(|part| !part.trim().is_empty()) {
+        let part_lines: Vec<&str> = part.trim().lines().collect();
+        let repeated = (1..=part_lines.len().min(lines.len()))
+            .rev()
+            .find(|&n| {
+                lines[lines.len() - n..]
+                    .iter()
+                    .zip(&part_lines[..n])
+                    .all(|(a, b)| a.trim() == b.trim())
+            })
+            .unwrap_or(0);
+        if !lines.is_empty() && repeated == 0 {
+            lines.push("");
+        }
+        lines.extend(&part_lines[repeated..]);
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clean_markdown_unwraps_an_outer_fence_with_any_tag() {
+        assert_eq!(clean_markdown("```markdown\n# Notes\n```"), "# Notes");
+        assert_eq!(clean_markdown("```md\n# Notes\n```"), "# Notes");
+        assert_eq!(clean_markdown("```\n# Notes\n```"), "# Notes");
+    }
+
+    #[test]
+    fn clean_markdown_unwraps_json_and_text_fences_around_markdown() {
+        assert_eq!(
+            clean_markdown("```json\n# Notes\n\n- a\n```"),
+            "# Notes\n\n- a"
+        );
+        assert_eq!(clean_markdown("```text\n# Notes\n```"), "# Notes");
+    }
+
+    #[test]
+    fn clean_markdown_unwraps_a_double_fence() {
+        assert_eq!(
+            clean_markdown("```markdown\n```markdown\n# Notes\n```\n```"),
+            "# Notes"
+        );
+    }
+
+    #[test]
+    fn clean_markdown_unwraps_nested_fences_and_json() {
+        assert_eq!(
+            clean_markdown("```json\n{\"content\": \"```markdown\\n# Notes\\n```\"}\n```"),
+            "# Notes"
+        );
+    }
+
+    #[test]
+    fn clean_markdown_keeps_answers_that_only_start_and_end_with_code() {
+        let text = "```rust\nfn a() {}\n```\n\nSome text\n\n```\nplain\n```";
+        assert_eq!(clean_markdown(text), text);
+    }
+
+    #[test]
+    fn clean_markdown_keeps_json_without_content() {
+        assert_eq!(clean_markdown("{\"title\": \"x\"}"), "{\"title\": \"x\"}");
+    }
+
+    #[test]
+    fn normalize_line_endings_converts_every_kind() {
+        let mixed = "a\r\nb\rc\nd";
+        assert_eq!(normalize_line_endings(mixed, LineEnding::Lf), "a\nb\nc\nd");
+        assert_eq!(
+            normalize_line_endings(mixed, LineEnding::Crlf),
+            "a\r\nb\r\nc\r\nd"
+        );
+        assert_eq!(normalize_line_endings("a\r\n", LineEnding::Crlf), "a\r\n");
+    }
+
+    #[test]
+    fn truncate_runaway_cuts_at_a_char_boundary() {
+        assert_eq!(truncate_runaway("Grüße", 5), None);
+        assert_eq!(truncate_runaway("", 0), None);
+
+        let cut = truncate_runaway("Grüße, Welt", 4).unwrap();
+        assert!(cut.starts_with("Grüß\n\n<!-- notedmd: output cut off after 4 characters"));
+        let cut = truncate_runaway("∑∑∑", 1).unwrap();
+        assert!(cut.starts_with("∑\n\n<!--"));
+    }
+
+    #[test]
+    fn tidy_whitespace_trims_lines_and_ends_with_one_newline() {
+        assert_eq!(
+            tidy_whitespace("# Title \t\n\nText \n\n\n"),
+            "# Title\n\nText\n"
+        );
+        assert_eq!(tidy_whitespace("a  \r\nb\t\r\n"), "a  \nb\n");
+        assert_eq!(tidy_whitespace(""), "");
+        assert_eq!(tidy_whitespace(" \n\n"), "");
+    }
+
+    #[test]
+    fn tidy_whitespace_keeps_hard_line_breaks() {
+        assert_eq!(
+            tidy_whitespace("Roses are red,  \nviolets are blue,    \nthe end.\n"),
+            "Roses are red,  \nviolets are blue,  \nthe end.\n"
+        );
+        // A single space, a tab or a blank line is no hard break.
+        assert_eq!(tidy_whitespace("a \nb \t\n  \nc"), "a\nb\n\nc\n");
+    }
+
+    #[test]
+    fn tidy_whitespace_leaves_code_blocks_alone() {
+        let text = "Code: \n```python \nif x:   \n    \n    pass \n```  \nAfter \n";
+        assert_eq!(
+            tidy_whitespace(text),
+            "Code:\n```python\nif x:   \n    \n    pass \n```\nAfter\n"
+        );
+    }
+
+    fn fix_math(text: &str) -> String {
+        fix_math_delimiters(text, MathOptions::default())
+    }
+
+    #[test]
+    fn fix_math_moves_display_math_onto_its_own_lines() {
+        assert_eq!(
+            fix_math("So $$E = mc^2$$ holds."),
+            "So\n$$\nE = mc^2\n$$\nholds."
+        );
+        let own_lines = "Before\n\n$$\nx^2\n$$\n\nAfter";
+        assert_eq!(fix_math(own_lines), own_lines);
+    }
+
+    #[test]
+    fn fix_math_joins_broken_inline_math() {
+        assert_eq!(
+            fix_math("where $a +\n  b$ is small"),
+            "where $a + b$ is small"
+        );
+    }
+
+    #[test]
+    fn fix_math_leaves_prices_alone() {
+        let text = "It costs $5 and $10,\nor $ 20 in total.";
+        assert_eq!(fix_math(text), text);
+    }
+
+    #[test]
+    fn fix_math_leaves_code_alone() {
+        let text = "Run `echo $$x$$` first.\n\n```sh\necho $a\nb$ $$y$$\n```";
+        assert_eq!(fix_math(text), text);
+    }
+
+    #[test]
+    fn fix_math_writes_github_styles() {
+        let github = MathOptions {
+            display: MathStyle::Github,
+            inline: MathStyle::Github,
+        };
+        assert_eq!(
+            fix_math_delimiters("Area $\\pi r^2$:\n$$\n\\int f\n$$", github),
+            "Area $`\\pi r^2`$:\n```math\n\\int f\n```"
+        );
+        // Inline math already in the GitHub style isn't wrapped twice.
+        assert_eq!(fix_math_delimiters("$`x`$", github), "$`x`$");
+    }
+
+    #[test]
+    fn stitch_markdown_drops_lines_repeated_by_the_overlap() {
+        let parts = vec![
+            "# Notes\n\nFirst line\nSecond line".to_string(),
+            "Second line\nThird line".to_string(),
+        ];
+        assert_eq!(
+            stitch_markdown(&parts),
+            "# Notes\n\nFirst line\nSecond line\nThird line"
+        );
+    }
+
+    #[test]
+    fn stitch_markdown_separates_tiles_without_overlap() {
+        let parts = vec!["One".to_string(), "  ".to_string(), "Two".to_string()];
+        assert_eq!(stitch_markdown(&parts), "One\n\nTwo");
+    }
+
+    #[test]
+    fn detect_language_names_the_language_or_unknown() {
+        assert_eq!(
+            detect_language(
+                "# Waves\n\nA wave carries energy from one place to another without carrying matter along with it."
+            ),
+            "eng"
+        );
+        assert_eq!(
+            detect_language(
+                "# Wellen\n\nEine Welle transportiert Energie von einem Ort zum anderen, ohne dabei Materie mitzunehmen."
+            ),
+            "deu"
+        );
+        assert_eq!(detect_language("ok"), "unknown");
+        assert_eq!(detect_language(""), "unknown");
+    }
+
+    #[test]
+    fn with_language_merges_into_existing_front_matter() {
+        assert_eq!(with_language("# Notes\n", None), "# Notes\n");
+        assert_eq!(
+            with_language("# Notes\n", Some("eng")),
+            "---\nlanguage: eng\n---\n\n# Notes\n"
+        );
+        assert_eq!(
+            with_language(
+                "---\ntitle: Waves\ntags: [physics]\n---\n\n# Notes\n",
+                Some("eng")
+            ),
+            "---\ntitle: Waves\ntags: [physics]\nlanguage: eng\n---\n\n# Notes\n"
+        );
+        assert_eq!(
+            with_language(
+                "---\nlanguage: unknown\ntitle: Waves\n---\n# Notes",
+                Some("eng")
+            ),
+            "---\ntitle: Waves\nlanguage: eng\n---\n# Notes"
+        );
+        assert_eq!(
+            with_language("---\ntitle: Waves\n---", Some("eng")),
+            "---\ntitle: Waves\nlanguage: eng\n---"
+        );
+        // A thematic break that never closes is not front matter.
+        assert_eq!(
+            with_language("---\n# Notes\n", Some("eng")),
+            "---\nlanguage: eng\n---\n\n---\n# Notes\n"
+        );
+    }
+}
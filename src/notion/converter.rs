@@ -12,20 +12,60 @@ use notion_client::objects::{
     rich_text::{self, RichText},
 };
 
+/// Deepest level of list items that can still hold children. Notion accepts
+/// two levels of nested children in one request, so blocks nested deeper are
+/// placed after their list item instead of inside it.
+const MAX_NESTING_DEPTH: usize = 2;
+
+/// A rendered list item: its text, the blocks nested inside it, and the blocks
+/// to place after it because they would be nested too deep.
+struct ListItemContent {
+    rich_text: Vec<RichText>,
+    children: Option<Vec<Block>>,
+    following: Vec<Block>,
+}
+
 pub struct Converter<'a> {
     _arena: &'a Arena<AstNode<'a>>,
+    /// How many list items enclose the blocks being rendered.
+    depth: usize,
 }
 
 impl<'a> Converter<'a> {
-    pub fn run(markdown: &str, arena: &'a Arena<AstNode<'a>>) -> Result<Vec<Block>, anyhow::Error> {
+    fn parse_options<'c>() -> ComrakOptions<'c> {
         let mut options = ComrakOptions::default();
         options.extension.math_dollars = true;
-        let root = parse_document(arena, markdown, &options);
-        let mut converter = Self { _arena: arena };
+        options
+    }
+
+    pub fn run(markdown: &str, arena: &'a Arena<AstNode<'a>>) -> Result<Vec<Block>, anyhow::Error> {
+        let root = parse_document(arena, markdown, &Self::parse_options());
+        let mut converter = Self {
+            _arena: arena,
+            depth: 0,
+        };
         let blocks = converter.render_nodes(root.children())?;
 
         Ok(blocks)
     }
+
+    /// Parses `markdown` the same way `run` does and returns the node tree as
+    /// JSON, to see why a block did not convert as expected.
+    pub fn ast_json(markdown: &str, arena: &'a Arena<AstNode<'a>>) -> serde_json::Value {
+        let root = parse_document(arena, markdown, &Self::parse_options());
+        Self::node_json(root)
+    }
+
+    fn node_json(node: &'a AstNode<'a>) -> serde_json::Value {
+        let data = node.data.borrow();
+        let children: Vec<_> = node.children().map(Self::node_json).collect();
+        serde_json::json!({
+            "node": format!("{:?}", data.value),
+            "sourcepos": data.sourcepos.to_string(),
+            "children": children,
+        })
+    }
+
     fn render_nodes(
         &mut self,
         nodes: impl Iterator<Item = &'a AstNode<'a>>,
@@ -42,10 +82,10 @@ impl<'a> Converter<'a> {
             NodeValue::Heading(heading) => Ok(vec![self.render_heading(node, heading)?]),
             NodeValue::Paragraph => {
                 let mut children = node.children();
-                if let (Some(child), None) = (children.next(), children.next()) {
-                    if let NodeValue::Math(_) = &child.data.borrow().value {
-                        return Ok(vec![self.render_math(child)?]);
-                    }
+                if let (Some(child), None) = (children.next(), children.next())
+                    && let NodeValue::Math(_) = &child.data.borrow().value
+                {
+                    return Ok(vec![self.render_math(child)?]);
                 }
                 Ok(vec![self.render_paragraph(node)?])
             }
@@ -60,8 +100,9 @@ impl<'a> Converter<'a> {
     fn render_bullet_list(&mut self, node: &'a AstNode<'a>) -> Result<Vec<Block>> {
         let mut items = Vec::new();
         for child in node.children() {
-            let block = self.render_bulleted_list_item(child)?;
+            let (block, following) = self.render_bulleted_list_item(child)?;
             items.push(block);
+            items.extend(following);
         }
         Ok(items)
     }
@@ -69,60 +110,99 @@ impl<'a> Converter<'a> {
     fn render_numbered_list(&mut self, node: &'a AstNode<'a>) -> Result<Vec<Block>> {
         let mut items = Vec::new();
         for child in node.children() {
-            let block = self.render_numbered_list_item(child)?;
+            let (block, following) = self.render_numbered_list_item(child)?;
             items.push(block);
+            items.extend(following);
         }
         Ok(items)
     }
 
-    fn render_numbered_list_item(&mut self, node: &'a AstNode<'a>) -> Result<Block> {
-        let mut rich_text = Vec::new();
-
-        if let Some(paragraph) = node
-            .children()
-            .find(|child| matches!(child.data.borrow().value, NodeValue::Paragraph))
-        {
-            rich_text = self.render_rich_text(paragraph)?;
-        }
+    fn render_numbered_list_item(&mut self, node: &'a AstNode<'a>) -> Result<(Block, Vec<Block>)> {
+        let ListItemContent {
+            rich_text,
+            children,
+            following,
+        } = self.render_list_item_content(node)?;
 
         let value = NumberedListItemValue {
             rich_text,
             color: notion_client::objects::block::TextColor::Default,
-            children: None,
+            children,
         };
 
-        Ok(Block {
+        let block = Block {
             block_type: BlockType::NumberedListItem {
                 numbered_list_item: value,
             },
             ..Default::default()
-        })
+        };
+        Ok((block, following))
     }
 
-    fn render_bulleted_list_item(&mut self, node: &'a AstNode<'a>) -> Result<Block> {
-        let mut rich_text = Vec::new();
-
-        if let Some(paragraph) = node
-            .children()
-            .find(|child| matches!(child.data.borrow().value, NodeValue::Paragraph))
-        {
-            rich_text = self.render_rich_text(paragraph)?;
-        }
+    fn render_bulleted_list_item(&mut self, node: &'a AstNode<'a>) -> Result<(Block, Vec<Block>)> {
+        let ListItemContent {
+            rich_text,
+            children,
+            following,
+        } = self.render_list_item_content(node)?;
 
         let value = BulletedListItemValue {
             rich_text,
             color: notion_client::objects::block::TextColor::Default,
-            children: None,
+            children,
         };
 
-        Ok(Block {
+        let block = Block {
             block_type: BlockType::BulletedListItem {
                 bulleted_list_item: value,
             },
             ..Default::default()
+        };
+        Ok((block, following))
+    }
+
+    /// Splits a list item into its text, taken from its first block when that
+    /// is a paragraph, and the blocks after it (further paragraphs, nested
+    /// lists). Those become the item's children, or, past
+    /// [`MAX_NESTING_DEPTH`], blocks to place after the item.
+    fn render_list_item_content(&mut self, node: &'a AstNode<'a>) -> Result<ListItemContent> {
+        if self.depth >= MAX_NESTING_DEPTH {
+            let (rich_text, following) = self.render_list_item_blocks(node)?;
+            return Ok(ListItemContent {
+                rich_text,
+                children: None,
+                following,
+            });
+        }
+
+        self.depth += 1;
+        let rendered = self.render_list_item_blocks(node);
+        self.depth -= 1;
+        let (rich_text, blocks) = rendered?;
+        Ok(ListItemContent {
+            rich_text,
+            children: (!blocks.is_empty()).then_some(blocks),
+            following: Vec::new(),
         })
     }
 
+    fn render_list_item_blocks(
+        &mut self,
+        node: &'a AstNode<'a>,
+    ) -> Result<(Vec<RichText>, Vec<Block>)> {
+        let mut rich_text = Vec::new();
+        let mut blocks = Vec::new();
+        for (index, child) in node.children().enumerate() {
+            let is_paragraph = matches!(child.data.borrow().value, NodeValue::Paragraph);
+            if index == 0 && is_paragraph {
+                rich_text = self.render_rich_text(child)?;
+            } else {
+                blocks.extend(self.render_node(child)?);
+            }
+        }
+        Ok((rich_text, blocks))
+    }
+
     fn render_math(&mut self, node: &'a AstNode<'a>) -> Result<Block> {
         if let NodeValue::Math(math) = &node.data.borrow().value {
             let expression = math.literal.clone();
@@ -210,3 +290,96 @@ impl<'a> Converter<'a> {
         Ok(rich_text_nodes)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(rich_text: &[RichText]) -> String {
+        rich_text
+            .iter()
+            .map(|part| match part {
+                RichText::Text { text, .. } => text.content.clone(),
+                RichText::Equation { equation, .. } => equation.expression.clone(),
+                _ => String::new(),
+            })
+            .collect()
+    }
+
+    /// Lists the blocks one per line, indented by how deep Notion nests them.
+    fn outline(blocks: &[Block], depth: usize, lines: &mut Vec<String>) {
+        for block in blocks {
+            let (line, children) = match &block.block_type {
+                BlockType::Paragraph { paragraph } => {
+                    (text(&paragraph.rich_text), &paragraph.children)
+                }
+                BlockType::BulletedListItem { bulleted_list_item } => (
+                    format!("- {}", text(&bulleted_list_item.rich_text)),
+                    &bulleted_list_item.children,
+                ),
+                BlockType::NumberedListItem { numbered_list_item } => (
+                    format!("1. {}", text(&numbered_list_item.rich_text)),
+                    &numbered_list_item.children,
+                ),
+                other => (format!("{:?}", other), &None),
+            };
+            lines.push(format!("{}{}", "  ".repeat(depth), line));
+            if let Some(children) = children {
+                outline(children, depth + 1, lines);
+            }
+        }
+    }
+
+    fn convert(markdown: &str) -> Vec<String> {
+        let arena = Arena::new();
+        let mut lines = Vec::new();
+        outline(&Converter::run(markdown, &arena).unwrap(), 0, &mut lines);
+        lines
+    }
+
+    #[test]
+    fn keeps_a_continued_paragraph_inside_its_list_item() {
+        assert_eq!(
+            convert("- first\n\n  continued\n- second"),
+            ["- first", "  continued", "- second"]
+        );
+    }
+
+    #[test]
+    fn nests_a_nested_list() {
+        assert_eq!(
+            convert("1. one\n   - a\n     - b\n2. two"),
+            ["1. one", "  - a", "    - b", "1. two"]
+        );
+    }
+
+    #[test]
+    fn places_lists_deeper_than_two_levels_after_their_item() {
+        assert_eq!(
+            convert("- a\n  - b\n    - c\n      - d\n        - e\n- f"),
+            ["- a", "  - b", "    - c", "    - d", "    - e", "- f"]
+        );
+    }
+
+    #[test]
+    fn takes_the_item_text_only_from_a_leading_paragraph() {
+        let lines = convert("- ## Title\n\n  after");
+        assert_eq!(lines[0], "- ");
+        assert!(lines[1].starts_with("  Heading2"));
+        assert_eq!(lines[2], "  after");
+    }
+
+    #[test]
+    fn ast_json_shows_the_node_tree() {
+        let arena = Arena::new();
+        let ast = Converter::ast_json("# Title\n\nSome *text*", &arena);
+        assert_eq!(ast["node"], "Document");
+        let children = ast["children"].as_array().unwrap();
+        assert_eq!(children.len(), 2);
+        assert!(children[0]["node"].as_str().unwrap().starts_with("Heading"));
+        assert_eq!(children[0]["sourcepos"], "1:1-1:7");
+        assert_eq!(children[1]["node"], "Paragraph");
+        let inline = children[1]["children"].as_array().unwrap();
+        assert!(inline[1]["node"].as_str().unwrap().starts_with("Emph"));
+    }
+}
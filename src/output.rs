@@ -0,0 +1,501 @@
+use std::{
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Local};
+use serde::Serialize;
+
+use crate::ai_provider::TokenUsage;
+use crate::cli::{DateSubdirs, LineEnding, OutputEncoding};
+use crate::markdown_utils::{normalize_line_endings, with_language};
+
+/// One converted file as printed by `--output-stdout-json`.
+#[derive(Serialize, Debug)]
+pub struct JsonResult<'a> {
+    pub source: &'a str,
+    pub provider: &'a str,
+    pub model: &'a str,
+    /// `null` for a PDF the provider read but lopdf can't parse.
+    pub pages: Option<usize>,
+    pub markdown: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usage: Option<TokenUsage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<&'a str>,
+}
+
+/// Settings that control how converted markdown is written to disk.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct OutputOptions {
+    pub encoding: OutputEncoding,
+    pub line_endings: Option<LineEnding>,
+    pub fsync: bool,
+    /// How many backups of an overwritten file to keep, if any.
+    pub backups: Option<u32>,
+}
+
+/// Serializes the final markdown document into the bytes written to disk.
+pub fn encode_markdown(markdown: &str, options: &OutputOptions) -> Vec<u8> {
+    let markdown = match options.line_endings {
+        Some(line_ending) => normalize_line_endings(markdown, line_ending),
+        None => markdown.to_string(),
+    };
+
+    let mut bytes = Vec::with_capacity(markdown.len() + 3);
+    if options.encoding == OutputEncoding::Utf8Bom {
+        bytes.extend_from_slice("\u{feff}".as_bytes());
+    }
+    bytes.extend_from_slice(markdown.as_bytes());
+    bytes
+}
+
+/// Extension of the markdown written for a transcription.
+pub const MARKDOWN_EXTENSION: &str = "md";
+
+/// Extension of the markdown written by `--summary`.
+pub const SUMMARY_EXTENSION: &str = "summary.md";
+
+/// Path of the markdown written for `source`: next to it, or in `output_dir`
+/// when one is given, with the extension replaced by `extension`. With
+/// `date_subdirs` the file goes into `YYYY/MM` subdirectories of that
+/// directory.
+pub fn output_path(
+    source: &Path,
+    output_dir: Option<&str>,
+    date_subdirs: Option<DateSubdirs>,
+    extension: &str,
+) -> PathBuf {
+    let path = match (output_dir, source.file_name()) {
+        (Some(dir), Some(file_name)) => Path::new(dir).join(file_name).with_extension(extension),
+        _ => source.with_extension(extension),
+    };
+    match (date_subdirs, path.parent(), path.file_name()) {
+        (Some(date_subdirs), Some(dir), Some(file_name)) => {
+            let date = match date_subdirs {
+                DateSubdirs::Modified => fs::metadata(source)
+                    .and_then(|metadata| metadata.modified())
+                    .map(DateTime::<Local>::from)
+                    .unwrap_or_else(|_| Local::now()),
+                DateSubdirs::Now => Local::now(),
+            };
+            dir.join(date.format("%Y").to_string())
+                .join(date.format("%m").to_string())
+                .join(file_name)
+        }
+        _ => path,
+    }
+}
+
+/// Groups `sources` whose markdown would be written to the same path, e.g.
+/// `notes.png` and `notes.jpg`. Returns each shared output path with the
+/// sources that map to it, in input order.
+pub fn output_collisions(
+    sources: &[PathBuf],
+    output_dir: Option<&str>,
+    date_subdirs: Option<DateSubdirs>,
+    extension: &str,
+) -> Vec<(PathBuf, Vec<PathBuf>)> {
+    let mut by_output: Vec<(PathBuf, Vec<PathBuf>)> = Vec::new();
+    for source in sources {
+        let output = output_path(source, output_dir, date_subdirs, extension);
+        match by_output.iter_mut().find(|(path, _)| *path == output) {
+            Some((_, group)) => group.push(source.clone()),
+            None => by_output.push((output, vec![source.clone()])),
+        }
+    }
+    by_output.retain(|(_, group)| group.len() > 1);
+    by_output
+}
+
+/// Writes the markdown to `path` atomically: the content goes to a temporary
+/// file next to it that is renamed over `path` only once fully written, so a
+/// failure part-way through leaves any previous output intact. With `fsync`
+/// set the data is also synced to disk before the rename.
+pub fn write_markdown(path: &Path, markdown: &str, options: &OutputOptions) -> std::io::Result<()> {
+    let temp_path = temporary_path(path);
+    let result = (|| {
+        let mut file = File::create(&temp_path)?;
+        file.write_all(&encode_markdown(markdown, options))?;
+        if options.fsync {
+            file.sync_all()?;
+        }
+        if let Some(keep) = options.backups {
+            back_up(path, keep)?;
+        }
+        fs::rename(&temp_path, path)
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+/// Path of the `n`th most recent backup of `path`: `notes.md.bak`, then
+/// `notes.md.bak.2` and so on.
+fn backup_path(path: &Path, n: u32) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(".bak");
+    if n > 1 {
+        name.push(format!(".{}", n));
+    }
+    PathBuf::from(name)
+}
+
+/// Moves an existing file at `path` to its first backup, shifting older
+/// backups along so that at most `keep` remain.
+fn back_up(path: &Path, keep: u32) -> std::io::Result<()> {
+    if !path.exists() {
+        return Ok(());
+    }
+    for n in (1..keep).rev() {
+        let backup = backup_path(path, n);
+        if backup.exists() {
+            fs::rename(&backup, backup_path(path, n + 1))?;
+        }
+    }
+    fs::rename(path, backup_path(path, 1))
+}
+
+/// Appends the markdown to the document at `path`, creating it if needed.
+/// When the document already has content, a `---` separator goes between it
+/// and the new part, unless the document already ends with one; `heading` is
+/// added as a `##` heading above the new part. Whitespace-only markdown is not
+/// appended, so it can't leave stacked separators behind. A byte order mark and
+/// the front matter recording `language` are only written when the file is
+/// created, so the document keeps the language of its first part.
+pub fn append_markdown(
+    path: &Path,
+    markdown: &str,
+    heading: Option<&str>,
+    language: Option<&str>,
+    options: &OutputOptions,
+) -> std::io::Result<()> {
+    let existing = match fs::read(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e),
+    };
+    if markdown.trim().is_empty() {
+        return Ok(());
+    }
+
+    let existing_text = String::from_utf8_lossy(&existing);
+    let existing_text = existing_text.trim_start_matches('\u{feff}').trim_end();
+    let mut part = String::new();
+    if !existing_text.is_empty() {
+        if !existing.ends_with(b"\n") {
+            part.push('\n');
+        }
+        if existing_text.lines().last() == Some("---") {
+            part.push('\n');
+        } else {
+            part.push_str("\n---\n\n");
+        }
+    }
+    if let Some(heading) = heading {
+        part.push_str(&format!("## {}\n\n", heading));
+    }
+    part.push_str(markdown);
+    if existing_text.is_empty() {
+        part = with_language(&part, language);
+    }
+
+    let options = OutputOptions {
+        encoding: if existing.is_empty() {
+            options.encoding
+        } else {
+            OutputEncoding::Utf8
+        },
+        ..*options
+    };
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    file.write_all(&encode_markdown(&part, &options))?;
+    if options.fsync {
+        file.sync_all()?;
+    }
+    Ok(())
+}
+
+fn temporary_path(path: &Path) -> PathBuf {
+    let file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{}.tmp", file_name))
+}
+
+/// Resolves the title for a converted file. A `--title` template wins over the
+/// default (the input's file name) and may use `{stem}` for the file name
+/// without its extension and `{name}` for the full file name.
+pub fn resolve_title(template: Option<&str>, path: &Path) -> String {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    match template {
+        Some(template) => {
+            let stem = path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            template.replace("{stem}", &stem).replace("{name}", &name)
+        }
+        None => name,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TempDir;
+
+    fn result<'a>(pages: Option<usize>, usage: Option<TokenUsage>) -> JsonResult<'a> {
+        JsonResult {
+            source: "scans/page.pdf",
+            provider: "gemini",
+            model: "gemma-3-27b-it",
+            pages,
+            markdown: "# Notes\n",
+            usage,
+            language: None,
+        }
+    }
+
+    #[test]
+    fn json_result_schema() {
+        let value = serde_json::to_value(result(
+            Some(3),
+            Some(TokenUsage {
+                input_tokens: 1200,
+                output_tokens: 300,
+            }),
+        ))
+        .unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({
+                "source": "scans/page.pdf",
+                "provider": "gemini",
+                "model": "gemma-3-27b-it",
+                "pages": 3,
+                "markdown": "# Notes\n",
+                "usage": {"input_tokens": 1200, "output_tokens": 300},
+            })
+        );
+    }
+
+    #[test]
+    fn json_result_keeps_unknown_page_counts_as_null() {
+        let value = serde_json::to_value(result(None, None)).unwrap();
+        assert_eq!(value["pages"], serde_json::Value::Null);
+        assert!(value.get("usage").is_none());
+        assert!(value.get("language").is_none());
+    }
+
+    #[test]
+    fn encode_markdown_adds_a_bom_only_when_asked() {
+        let bom = OutputOptions {
+            encoding: OutputEncoding::Utf8Bom,
+            ..Default::default()
+        };
+        assert_eq!(encode_markdown("# Ä", &bom), "\u{feff}# Ä".as_bytes());
+        assert_eq!(
+            encode_markdown("# Ä", &OutputOptions::default()),
+            "# Ä".as_bytes()
+        );
+    }
+
+    #[test]
+    fn encode_markdown_normalizes_line_endings() {
+        let crlf = OutputOptions {
+            line_endings: Some(LineEnding::Crlf),
+            encoding: OutputEncoding::Utf8Bom,
+            ..Default::default()
+        };
+        assert_eq!(
+            encode_markdown("a\nb\r\nc", &crlf),
+            "\u{feff}a\r\nb\r\nc".as_bytes()
+        );
+        // Left as the provider sent them without --line-endings.
+        assert_eq!(
+            encode_markdown("a\r\nb\n", &OutputOptions::default()),
+            b"a\r\nb\n"
+        );
+    }
+
+    #[test]
+    fn output_path_replaces_the_extension_next_to_the_source() {
+        assert_eq!(
+            output_path(Path::new("scans/page.png"), None, None, "md"),
+            PathBuf::from("scans/page.md")
+        );
+        assert_eq!(
+            output_path(Path::new("scans/page.pdf"), None, None, SUMMARY_EXTENSION),
+            PathBuf::from("scans/page.summary.md")
+        );
+    }
+
+    #[test]
+    fn output_path_uses_the_output_dir() {
+        assert_eq!(
+            output_path(Path::new("scans/page.png"), Some("notes"), None, "md"),
+            PathBuf::from("notes/page.md")
+        );
+    }
+
+    #[test]
+    fn output_path_adds_date_subdirectories() {
+        let dir = TempDir::new("output-date-subdirs");
+        let source = dir.write("scans/page.png", "png");
+        // Mid-month, so the month is the same in every time zone.
+        let modified = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_710_504_000);
+        File::options()
+            .write(true)
+            .open(&source)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+
+        assert_eq!(
+            output_path(&source, Some("notes"), Some(DateSubdirs::Modified), "md"),
+            PathBuf::from("notes/2024/03/page.md")
+        );
+        assert_eq!(
+            output_path(&source, None, Some(DateSubdirs::Modified), "md"),
+            dir.path().join("scans/2024/03/page.md")
+        );
+        let now = Local::now();
+        assert_eq!(
+            output_path(&source, Some("notes"), Some(DateSubdirs::Now), "md"),
+            Path::new("notes")
+                .join(now.format("%Y").to_string())
+                .join(now.format("%m").to_string())
+                .join("page.md")
+        );
+    }
+
+    #[test]
+    fn output_collisions_groups_sources_with_the_same_stem() {
+        let sources = [
+            PathBuf::from("scans/notes.png"),
+            PathBuf::from("scans/notes.jpg"),
+            PathBuf::from("scans/other.png"),
+            PathBuf::from("more/notes.pdf"),
+        ];
+        assert_eq!(
+            output_collisions(&sources, Some("out"), None, "md"),
+            vec![(
+                PathBuf::from("out/notes.md"),
+                vec![
+                    PathBuf::from("scans/notes.png"),
+                    PathBuf::from("scans/notes.jpg"),
+                    PathBuf::from("more/notes.pdf"),
+                ]
+            )]
+        );
+        // Next to their sources, only files in the same directory collide.
+        assert_eq!(output_collisions(&sources, None, None, "md").len(), 1);
+    }
+
+    #[test]
+    fn write_markdown_replaces_the_file_without_leaving_a_temp_file() {
+        let dir = TempDir::new("output-atomic");
+        let path = dir.write("notes.md", "old");
+        write_markdown(&path, "new", &OutputOptions::default()).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        assert!(!temporary_path(&path).exists());
+    }
+
+    #[test]
+    fn write_markdown_cleans_up_when_the_rename_fails() {
+        let dir = TempDir::new("output-failed");
+        // A directory in the way makes the final rename fail.
+        let path = dir.path().join("notes.md");
+        fs::create_dir(&path).unwrap();
+        fs::write(path.join("kept.txt"), "previous").unwrap();
+        assert!(write_markdown(&path, "new", &OutputOptions::default()).is_err());
+        assert_eq!(
+            fs::read_to_string(path.join("kept.txt")).unwrap(),
+            "previous"
+        );
+        assert!(!temporary_path(&path).exists());
+    }
+
+    #[test]
+    fn write_markdown_backs_up_the_overwritten_file() {
+        let dir = TempDir::new("output-backup");
+        let path = dir.path().join("notes.md");
+        let backups = |keep| OutputOptions {
+            backups: Some(keep),
+            ..Default::default()
+        };
+
+        // Nothing to back up the first time.
+        write_markdown(&path, "v1", &backups(1)).unwrap();
+        assert!(!backup_path(&path, 1).exists());
+
+        write_markdown(&path, "v2", &backups(1)).unwrap();
+        write_markdown(&path, "v3", &backups(1)).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "v3");
+        assert_eq!(
+            fs::read_to_string(dir.path().join("notes.md.bak")).unwrap(),
+            "v2"
+        );
+        assert!(!backup_path(&path, 2).exists());
+
+        write_markdown(&path, "v4", &backups(2)).unwrap();
+        write_markdown(&path, "v5", &backups(2)).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "v5");
+        assert_eq!(fs::read_to_string(backup_path(&path, 1)).unwrap(), "v4");
+        assert_eq!(
+            fs::read_to_string(dir.path().join("notes.md.bak.2")).unwrap(),
+            "v3"
+        );
+        assert!(!backup_path(&path, 3).exists());
+    }
+
+    #[test]
+    fn append_markdown_separates_the_parts() {
+        let dir = TempDir::new("output-append");
+        let path = dir.path().join("course.md");
+        let bom = OutputOptions {
+            encoding: OutputEncoding::Utf8Bom,
+            ..Default::default()
+        };
+
+        append_markdown(&path, "Waves\n", None, None, &bom).unwrap();
+        append_markdown(&path, "Wellen\n", Some("week2"), None, &bom).unwrap();
+        // Whitespace-only parts are left out.
+        append_markdown(&path, " \n\n", None, None, &bom).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "\u{feff}Waves\n\n---\n\n## week2\n\nWellen\n"
+        );
+
+        // A document already ending with a separator doesn't get a second one.
+        let path = dir.write("notes.md", "Intro\n\n---");
+        append_markdown(&path, "Part\n", None, Some("eng"), &bom).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "Intro\n\n---\n\nPart\n");
+    }
+
+    #[test]
+    fn resolve_title_fills_the_template() {
+        let path = Path::new("scans/week 3.pdf");
+        assert_eq!(resolve_title(None, path), "week 3.pdf");
+        assert_eq!(
+            resolve_title(Some("Physics: {stem}"), path),
+            "Physics: week 3"
+        );
+        assert_eq!(
+            resolve_title(Some("{name} ({stem})"), path),
+            "week 3.pdf (week 3)"
+        );
+        assert_eq!(resolve_title(Some("Fixed title"), path), "Fixed title");
+    }
+}
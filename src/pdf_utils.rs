@@ -0,0 +1,301 @@
+use crate::error::NotedError;
+use crate::image_utils::ImageEncoding;
+use image::{DynamicImage, GrayImage, ImageFormat, RgbImage};
+use lopdf::Document;
+use lopdf::xobject::PdfImage;
+
+pub fn page_count(data: &[u8]) -> Result<usize, NotedError> {
+    let document = Document::load_mem(data).map_err(|e| NotedError::PdfError(e.to_string()))?;
+    Ok(document.get_pages().len())
+}
+
+/// Rejects a PDF without pages before it is sent anywhere. Only what lopdf can
+/// read is rejected; providers may still cope with a PDF it fails to parse.
+pub fn ensure_has_pages(data: &[u8]) -> Result<(), NotedError> {
+    match page_count(data) {
+        Ok(0) => Err(NotedError::PdfError(
+            "The PDF has no pages, nothing to convert".to_string(),
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Returns a copy of the PDF that contains only its first page.
+pub fn extract_first_page(data: &[u8]) -> Result<Vec<u8>, NotedError> {
+    let mut document = Document::load_mem(data).map_err(|e| NotedError::PdfError(e.to_string()))?;
+
+    let page_count = document.get_pages().len() as u32;
+    if page_count == 0 {
+        return Err(NotedError::PdfError("The PDF has no pages".to_string()));
+    }
+
+    let remaining_pages: Vec<u32> = (2..=page_count).collect();
+    document.delete_pages(&remaining_pages);
+    document.prune_objects();
+
+    let mut buffer = Vec::new();
+    document
+        .save_to(&mut buffer)
+        .map_err(|e| NotedError::PdfError(e.to_string()))?;
+    Ok(buffer)
+}
+
+/// One bookmark of a PDF's outline.
+pub struct OutlineEntry {
+    /// 1 for top-level bookmarks, 2 for the ones nested below them, ...
+    pub level: usize,
+    pub title: String,
+    /// 1-based page number the bookmark points to.
+    pub page: usize,
+}
+
+/// Reads the outline (bookmarks) of the PDF in document order. A PDF without
+/// an outline gives an empty list.
+pub fn outline(data: &[u8]) -> Result<Vec<OutlineEntry>, NotedError> {
+    let document = Document::load_mem(data).map_err(|e| NotedError::PdfError(e.to_string()))?;
+    let has_outline = document
+        .catalog()
+        .is_ok_and(|catalog| catalog.has(b"Outlines"));
+    if !has_outline {
+        return Ok(Vec::new());
+    }
+    let toc = match document.get_toc() {
+        Ok(toc) => toc,
+        Err(lopdf::Error::NoOutline) => return Ok(Vec::new()),
+        Err(e) => return Err(NotedError::PdfError(e.to_string())),
+    };
+    Ok(toc
+        .toc
+        .into_iter()
+        .map(|entry| OutlineEntry {
+            level: entry.level,
+            title: entry.title.trim().to_string(),
+            page: entry.page,
+        })
+        .collect())
+}
+
+/// An image embedded in a PDF page, re-encoded as PNG.
+pub struct PageImage {
+    /// 1-based page number.
+    pub page: u32,
+    pub png: Vec<u8>,
+}
+
+/// Pulls the images embedded in each page of the PDF, in page order. Only
+/// JPEG images and 8-bit RGB or grayscale bitmaps can be decoded; other
+/// images (masks, CMYK, JPEG 2000, ...) are left out. Returns the extracted
+/// images and how many were left out.
+pub fn extract_images(
+    data: &[u8],
+    encoding: &ImageEncoding,
+) -> Result<(Vec<PageImage>, usize), NotedError> {
+    let document = Document::load_mem(data).map_err(|e| NotedError::PdfError(e.to_string()))?;
+
+    let mut images = Vec::new();
+    let mut skipped = 0;
+    for (page, page_id) in document.get_pages() {
+        let page_images = match document.get_page_images(page_id) {
+            Ok(page_images) => page_images,
+            // lopdf reports a page without resources as a missing key.
+            Err(lopdf::Error::DictKey(_)) => continue,
+            Err(e) => return Err(NotedError::PdfError(e.to_string())),
+        };
+        for page_image in page_images {
+            let Some(decoded) = decode_image(&document, &page_image) else {
+                skipped += 1;
+                continue;
+            };
+            let png = encoding.encode_png(&decoded)?;
+            images.push(PageImage { page, png });
+        }
+    }
+    Ok((images, skipped))
+}
+
+fn decode_image(document: &Document, image: &PdfImage) -> Option<DynamicImage> {
+    let filters = image.filters.as_deref().unwrap_or_default();
+    if let [filter] = filters
+        && filter == "DCTDecode"
+    {
+        return image::load_from_memory_with_format(image.content, ImageFormat::Jpeg).ok();
+    }
+    if filters.iter().any(|filter| filter != "FlateDecode") || image.bits_per_component != Some(8) {
+        return None;
+    }
+
+    let pixels = document
+        .get_object(image.id)
+        .and_then(|object| object.as_stream())
+        .ok()?
+        .decompressed_content()
+        .unwrap_or_else(|_| image.content.to_vec());
+    let width = u32::try_from(image.width).ok()?;
+    let height = u32::try_from(image.height).ok()?;
+    match image.color_space.as_deref() {
+        Some("DeviceRGB") => RgbImage::from_raw(width, height, pixels).map(DynamicImage::ImageRgb8),
+        Some("DeviceGray") => {
+            GrayImage::from_raw(width, height, pixels).map(DynamicImage::ImageLuma8)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lopdf::{Object, ObjectId, Stream, dictionary};
+
+    /// Builds a PDF with `pages` empty pages. `edit` gets the document, its
+    /// catalog and its pages to add more before it is saved.
+    fn pdf(pages: usize, edit: impl FnOnce(&mut Document, ObjectId, &[ObjectId])) -> Vec<u8> {
+        let mut document = Document::with_version("1.5");
+        let pages_id = document.new_object_id();
+        let page_ids: Vec<ObjectId> = (0..pages)
+            .map(|_| {
+                document.add_object(dictionary! {
+                    "Type" => "Page",
+                    "Parent" => pages_id,
+                    "MediaBox" => vec![0.into(), 0.into(), 100.into(), 100.into()],
+                })
+            })
+            .collect();
+        document.objects.insert(
+            pages_id,
+            Object::Dictionary(dictionary! {
+                "Type" => "Pages",
+                "Kids" => page_ids.iter().map(|&id| Object::Reference(id)).collect::<Vec<_>>(),
+                "Count" => pages as i64,
+            }),
+        );
+        let catalog_id = document.add_object(dictionary! {
+            "Type" => "Catalog",
+            "Pages" => pages_id,
+        });
+        document.trailer.set("Root", catalog_id);
+        edit(&mut document, catalog_id, &page_ids);
+
+        let mut buffer = Vec::new();
+        document.save_to(&mut buffer).unwrap();
+        buffer
+    }
+
+    #[test]
+    fn rejects_a_pdf_without_pages() {
+        let empty = pdf(0, |_, _, _| {});
+        assert_eq!(page_count(&empty).unwrap(), 0);
+        assert!(matches!(
+            ensure_has_pages(&empty),
+            Err(NotedError::PdfError(message)) if message.contains("no pages")
+        ));
+        assert!(extract_first_page(&empty).is_err());
+
+        let lecture = pdf(3, |_, _, _| {});
+        assert_eq!(page_count(&lecture).unwrap(), 3);
+        assert!(ensure_has_pages(&lecture).is_ok());
+        assert_eq!(
+            page_count(&extract_first_page(&lecture).unwrap()).unwrap(),
+            1
+        );
+
+        // Left for the provider to make sense of.
+        assert!(page_count(b"not a pdf").is_err());
+        assert!(ensure_has_pages(b"not a pdf").is_ok());
+    }
+
+    #[test]
+    fn outline_lists_nested_bookmarks_in_order() {
+        let bookmarked = pdf(3, |document, catalog, pages| {
+            let dest = |page: ObjectId| vec![page.into(), "Fit".into()];
+            let section = document.add_object(dictionary! {
+                "Title" => Object::string_literal("Sound"),
+                "Dest" => dest(pages[1]),
+            });
+            let second = document.add_object(dictionary! {
+                "Title" => Object::string_literal("Light"),
+                "Dest" => dest(pages[2]),
+            });
+            let first = document.add_object(dictionary! {
+                "Title" => Object::string_literal(" Waves "),
+                "Dest" => dest(pages[0]),
+                "First" => section,
+                "Last" => section,
+                "Next" => second,
+            });
+            let outlines = document.add_object(dictionary! {
+                "Type" => "Outlines",
+                "First" => first,
+                "Last" => second,
+            });
+            document
+                .get_object_mut(catalog)
+                .and_then(Object::as_dict_mut)
+                .unwrap()
+                .set("Outlines", outlines);
+        });
+
+        let entries: Vec<_> = outline(&bookmarked)
+            .unwrap()
+            .into_iter()
+            .map(|entry| (entry.level, entry.title, entry.page))
+            .collect();
+        assert_eq!(
+            entries,
+            [
+                (1, "Waves".to_string(), 1),
+                (2, "Sound".to_string(), 2),
+                (1, "Light".to_string(), 3),
+            ]
+        );
+
+        assert!(outline(&pdf(2, |_, _, _| {})).unwrap().is_empty());
+    }
+
+    #[test]
+    fn extract_images_decodes_what_it_can() {
+        let encoding = ImageEncoding::default();
+        let jpeg = encoding.encode_jpeg(&RgbImage::new(3, 2)).unwrap();
+        let with_images = pdf(2, |document, _, pages| {
+            let image = |color_space: &str, filter: Option<&str>, content: Vec<u8>| {
+                let mut dict = dictionary! {
+                    "Type" => "XObject",
+                    "Subtype" => "Image",
+                    "Width" => 3,
+                    "Height" => 2,
+                    "ColorSpace" => color_space,
+                    "BitsPerComponent" => 8,
+                };
+                if let Some(filter) = filter {
+                    dict.set("Filter", Object::Name(filter.as_bytes().to_vec()));
+                }
+                Stream::new(dict, content)
+            };
+            let rgb = document.add_object(image("DeviceRGB", None, vec![255; 18]));
+            let jpeg = document.add_object(image("DeviceRGB", Some("DCTDecode"), jpeg));
+            let cmyk = document.add_object(image("DeviceCMYK", None, vec![0; 24]));
+            let page = document
+                .get_object_mut(pages[1])
+                .and_then(Object::as_dict_mut)
+                .unwrap();
+            page.set(
+                "Resources",
+                dictionary! {
+                    "XObject" => dictionary! { "Im1" => rgb, "Im2" => jpeg, "Im3" => cmyk },
+                },
+            );
+        });
+
+        // The first page has no resources at all.
+        let (images, skipped) = extract_images(&with_images, &encoding).unwrap();
+        assert_eq!(skipped, 1);
+        assert_eq!(images.len(), 2);
+        for image in &images {
+            assert_eq!(image.page, 2);
+            assert_eq!(image::guess_format(&image.png).unwrap(), ImageFormat::Png);
+            let decoded = image::load_from_memory(&image.png).unwrap();
+            assert_eq!((decoded.width(), decoded.height()), (3, 2));
+        }
+
+        assert!(extract_images(b"not a pdf", &encoding).is_err());
+    }
+}
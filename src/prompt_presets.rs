@@ -0,0 +1,41 @@
+use clap::ValueEnum;
+
+/// Prompt used by `--summary` to ask for a summary instead of a transcription.
+pub const SUMMARY_PROMPT: &str = "The user has provided handwritten notes. Instead of transcribing them, write a concise summary in Markdown. Start with a one-paragraph overview, then list the main topics, key points, definitions and conclusions as headings and bullet points, in the order they appear. Keep important formulas in LaTeX using the $$ syntax. If the input has several pages, summarize the document as a whole rather than page by page. The output should only be the markdown content.";
+
+/// Built-in prompts tuned for particular kinds of notes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum PromptPreset {
+    Math,
+    Chemistry,
+    Code,
+    Tables,
+}
+
+impl PromptPreset {
+    pub fn description(self) -> &'static str {
+        match self {
+            PromptPreset::Math => "Equations, proofs and derivations written in LaTeX",
+            PromptPreset::Chemistry => "Formulas, reactions and structures with mhchem notation",
+            PromptPreset::Code => "Handwritten code kept in fenced blocks with a language tag",
+            PromptPreset::Tables => "Tabular notes turned into Markdown tables",
+        }
+    }
+
+    pub fn prompt(self) -> &'static str {
+        match self {
+            PromptPreset::Math => {
+                "The user has provided an image of handwritten mathematics notes. Transcribe them into a well-structured Markdown file. Preserve headings, lists and the order of the steps. Write every mathematical expression in LaTeX: use $...$ for inline math and $$...$$ on their own lines for displayed equations, and keep multi-line derivations aligned with an aligned environment. Copy symbols, subscripts and superscripts exactly and do not simplify or correct the math. The output should only be the markdown content."
+            }
+            PromptPreset::Chemistry => {
+                "The user has provided an image of handwritten chemistry notes. Transcribe them into a well-structured Markdown file. Preserve headings and lists. Write chemical formulas and reactions with the mhchem syntax inside LaTeX math, for example $\\ce{2H2 + O2 -> 2H2O}$, keeping charges, states, stoichiometric coefficients and reaction conditions. Describe drawn structures briefly in text when they cannot be written as formulas. The output should only be the markdown content."
+            }
+            PromptPreset::Code => {
+                "The user has provided an image of handwritten notes that contain source code. Transcribe them into a well-structured Markdown file. Put every piece of code in a fenced code block tagged with its language, preserving indentation, identifiers and punctuation exactly. Keep the surrounding prose, headings and lists as Markdown. The output should only be the markdown content."
+            }
+            PromptPreset::Tables => {
+                "The user has provided an image of handwritten notes that contain tables. Transcribe them into a well-structured Markdown file. Convert every table into a Markdown table with a header row, keeping the rows and columns in their original order and leaving cells empty where the notes do. Keep the other content as headings, lists and paragraphs. The output should only be the markdown content."
+            }
+        }
+    }
+}
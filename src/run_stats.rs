@@ -0,0 +1,59 @@
+use crate::ai_provider::TokenUsage;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Measurements collected over a `convert` run for `--stats`.
+pub struct RunStats {
+    started: Instant,
+    totals: Mutex<RunTotals>,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RunTotals {
+    /// Requests sent to the provider, tiles and retries included.
+    pub requests: u32,
+    pub failed_requests: u32,
+    /// Files sent again after an empty or undecodable response.
+    pub retries: u32,
+    /// Base64 payload sent in requests.
+    pub bytes_uploaded: u64,
+    /// Time spent waiting for the provider to answer.
+    pub network_time: Duration,
+    pub usage: Option<TokenUsage>,
+}
+
+impl RunStats {
+    pub fn start() -> Self {
+        Self {
+            started: Instant::now(),
+            totals: Mutex::new(RunTotals::default()),
+        }
+    }
+
+    pub fn record_request(&self, bytes: usize, elapsed: Duration, succeeded: bool) {
+        let mut totals = self.totals.lock().unwrap();
+        totals.requests += 1;
+        if !succeeded {
+            totals.failed_requests += 1;
+        }
+        totals.bytes_uploaded += bytes as u64;
+        totals.network_time += elapsed;
+    }
+
+    pub fn record_retry(&self) {
+        self.totals.lock().unwrap().retries += 1;
+    }
+
+    pub fn record_usage(&self, usage: TokenUsage) {
+        let mut totals = self.totals.lock().unwrap();
+        totals.usage = Some(match totals.usage {
+            Some(total) => total + usage,
+            None => usage,
+        });
+    }
+
+    /// Returns the totals so far and the time since the run started.
+    pub fn finish(&self) -> (RunTotals, Duration) {
+        (*self.totals.lock().unwrap(), self.started.elapsed())
+    }
+}
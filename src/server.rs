@@ -0,0 +1,117 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use axum::Router;
+use axum::body::Bytes;
+use axum::extract::{DefaultBodyLimit, State};
+use axum::http::{HeaderMap, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use axum::routing::post;
+use subtle::ConstantTimeEq;
+
+use crate::ai_provider::AiProvider;
+use crate::error::NotedError;
+use crate::file_utils::{FileData, SUPPORTED_FILE_TYPES};
+use crate::markdown_utils::{self, MathOptions};
+
+/// Settings for `notedmd serve`.
+pub struct ServeOptions {
+    pub address: SocketAddr,
+    /// Bearer token every request has to carry.
+    pub token: String,
+    /// Larger uploads are rejected with 413 before reaching the provider.
+    pub max_body_bytes: usize,
+}
+
+struct ServerState {
+    client: Box<dyn AiProvider>,
+    token: String,
+}
+
+/// Makes a token for a server started without `--token`: 32 bytes from the
+/// operating system's random number generator, hex encoded.
+pub fn generate_token() -> Result<String, NotedError> {
+    let mut bytes = [0u8; 32];
+    getrandom::fill(&mut bytes).map_err(|e| {
+        NotedError::IoError(std::io::Error::other(format!(
+            "could not generate a token: {}",
+            e
+        )))
+    })?;
+    Ok(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+/// Serves `POST /convert` until the process is stopped. The request body is
+/// the file, its `Content-Type` one of the supported mime types, and the
+/// response the markdown `client` returns for it.
+pub async fn serve(client: Box<dyn AiProvider>, options: ServeOptions) -> Result<(), NotedError> {
+    let state = Arc::new(ServerState {
+        client,
+        token: options.token,
+    });
+    let app = Router::new()
+        .route("/convert", post(convert))
+        .layer(DefaultBodyLimit::max(options.max_body_bytes))
+        .with_state(state);
+    let listener = tokio::net::TcpListener::bind(options.address).await?;
+    axum::serve(listener, app).await?;
+    Ok(())
+}
+
+async fn convert(
+    State(state): State<Arc<ServerState>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let authorized = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        // Compared in constant time so response timing doesn't reveal how
+        // much of a guess was right.
+        .is_some_and(|token| bool::from(token.as_bytes().ct_eq(state.token.as_bytes())));
+    if !authorized {
+        return (StatusCode::UNAUTHORIZED, "Missing or wrong bearer token\n").into_response();
+    }
+
+    let mime_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .map(|value| value.trim().to_ascii_lowercase())
+        .filter(|mime_type| {
+            SUPPORTED_FILE_TYPES
+                .iter()
+                .any(|(_, supported)| supported == mime_type)
+        });
+    let Some(mime_type) = mime_type else {
+        return (
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "Content-Type must be image/png, image/jpeg or application/pdf\n",
+        )
+            .into_response();
+    };
+    if mime_type == "application/pdf" && !state.client.supports_pdf() {
+        let error = NotedError::PdfNotSupported(state.client.name().to_string());
+        return (StatusCode::UNPROCESSABLE_ENTITY, format!("{}\n", error)).into_response();
+    }
+    if body.is_empty() {
+        return (StatusCode::BAD_REQUEST, "The request body is empty\n").into_response();
+    }
+
+    match state
+        .client
+        .send_request(FileData::new(&body, mime_type))
+        .await
+    {
+        Ok(markdown) => {
+            let markdown = markdown_utils::fix_math_delimiters(&markdown, MathOptions::default());
+            (
+                [(header::CONTENT_TYPE, "text/markdown; charset=utf-8")],
+                markdown_utils::tidy_whitespace(&markdown),
+            )
+                .into_response()
+        }
+        Err(e) => (StatusCode::BAD_GATEWAY, format!("{}\n", e)).into_response(),
+    }
+}
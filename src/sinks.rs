@@ -0,0 +1,484 @@
+use std::path::{Path, PathBuf};
+
+use async_trait::async_trait;
+use colored::Colorize;
+use indicatif::ProgressBar;
+
+use crate::cli::DateSubdirs;
+use crate::clients::notion_client::{NotionClient, SourceMarker};
+use crate::config::{NotionConfig, NotionPropertyConfig};
+use crate::error::NotedError;
+use crate::markdown_utils::{ContentStats, with_language};
+use crate::output::{self, OutputOptions};
+use crate::pdf_utils::{self, PageImage};
+
+/// A converted file as handed to each output sink.
+pub struct ConvertedFile<'a> {
+    pub source: &'a Path,
+    /// Content and type of the source file.
+    pub data: &'a [u8],
+    pub mime_type: &'a str,
+    pub markdown: &'a str,
+    pub stats: &'a ContentStats,
+    pub provider: &'a str,
+    pub model: &'a str,
+    /// Detected language code, with `--detect-language`.
+    pub language: Option<&'a str>,
+    /// Images embedded in the source PDF, with `--extract-images`. Sinks that
+    /// write markdown files save them next to it.
+    pub images: &'a [PageImage],
+}
+
+/// A destination for converted markdown. `convert` picks its sinks from the
+/// flags and writes every converted file to each of them in turn, stopping
+/// at the first that fails. Not `Send`, as the Notion upload isn't.
+#[async_trait(?Send)]
+pub trait OutputSink {
+    async fn write(
+        &self,
+        file: &ConvertedFile<'_>,
+        progress_bar: &ProgressBar,
+    ) -> Result<(), NotedError>;
+}
+
+/// Writes `<stem>.md` next to the source, or in `output_dir`.
+pub struct FileSink<'a> {
+    pub output_dir: Option<&'a str>,
+    pub date_subdirs: Option<DateSubdirs>,
+    /// Extension of the written markdown, `summary.md` with `--summary` and
+    /// `<provider>.md` with `--compare`.
+    pub extension: &'a str,
+    pub options: OutputOptions,
+}
+
+impl FileSink<'_> {
+    /// Where the markdown for `source` is written.
+    pub fn path(&self, source: &Path) -> PathBuf {
+        output::output_path(source, self.output_dir, self.date_subdirs, self.extension)
+    }
+}
+
+#[async_trait(?Send)]
+impl OutputSink for FileSink<'_> {
+    async fn write(
+        &self,
+        file: &ConvertedFile<'_>,
+        progress_bar: &ProgressBar,
+    ) -> Result<(), NotedError> {
+        let output_path = self.path(file.source);
+        let dir = output_path.parent().unwrap_or(Path::new(""));
+        if !dir.as_os_str().is_empty() && !dir.exists() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let markdown = with_language(
+            &format!("{}{}", file.markdown, save_images(file, dir, progress_bar)?),
+            file.language,
+        );
+        let written = output::write_markdown(&output_path, &markdown, &self.options);
+        report_written(written, &output_path, "saved to", file.stats, progress_bar)
+    }
+}
+
+/// Appends to a single document (`--append`), optionally under a heading.
+pub struct AppendSink<'a> {
+    pub path: &'a Path,
+    /// `--append-heading` template, see [`output::resolve_title`].
+    pub heading: Option<&'a str>,
+    pub options: OutputOptions,
+}
+
+#[async_trait(?Send)]
+impl OutputSink for AppendSink<'_> {
+    async fn write(
+        &self,
+        file: &ConvertedFile<'_>,
+        progress_bar: &ProgressBar,
+    ) -> Result<(), NotedError> {
+        let heading = self
+            .heading
+            .map(|template| output::resolve_title(Some(template), file.source));
+        let images = save_images(
+            file,
+            self.path.parent().unwrap_or(Path::new("")),
+            progress_bar,
+        )?;
+        let written = output::append_markdown(
+            self.path,
+            &format!("{}{}", file.markdown, images),
+            heading.as_deref(),
+            file.language,
+            &self.options,
+        );
+        report_written(written, self.path, "appended to", file.stats, progress_bar)
+    }
+}
+
+/// Saves the images of `file` as PNG files in a `<stem>_assets` folder inside
+/// `markdown_dir`, and returns a markdown section linking to them, or an empty
+/// string when there are none.
+fn save_images(
+    file: &ConvertedFile<'_>,
+    markdown_dir: &Path,
+    progress_bar: &ProgressBar,
+) -> Result<String, NotedError> {
+    if file.images.is_empty() {
+        return Ok(String::new());
+    }
+
+    let stem = file
+        .source
+        .file_stem()
+        .ok_or_else(|| NotedError::FileNameError(file.source.to_string_lossy().to_string()))?
+        .to_string_lossy();
+    let assets_name = format!("{}_assets", stem);
+    let assets_dir = markdown_dir.join(&assets_name);
+    std::fs::create_dir_all(&assets_dir)?;
+
+    let mut section = String::from("\n## Extracted images\n\n");
+    let (mut page, mut index) = (0, 0);
+    for image in file.images {
+        if image.page != page {
+            (page, index) = (image.page, 0);
+        }
+        index += 1;
+        let file_name = format!("page-{}-{}.png", image.page, index);
+        std::fs::write(assets_dir.join(&file_name), &image.png)?;
+        section.push_str(&format!(
+            "![Page {}, image {}]({}/{})\n\n",
+            image.page,
+            index,
+            assets_name.replace(' ', "%20"),
+            file_name
+        ));
+    }
+    // Keep a single newline at the end of the document.
+    section.pop();
+    progress_bar.println(format!(
+        "{} {}",
+        "✔".green(),
+        format!(
+            "Extracted {} image(s) to '{}'",
+            file.images.len(),
+            assets_dir.to_string_lossy().cyan()
+        )
+        .green()
+    ));
+    Ok(section)
+}
+
+/// Prints the outcome of writing the markdown to `path`.
+fn report_written(
+    written: std::io::Result<()>,
+    path: &Path,
+    verb: &str,
+    stats: &ContentStats,
+    progress_bar: &ProgressBar,
+) -> Result<(), NotedError> {
+    let path = path.to_string_lossy();
+    match written {
+        Ok(()) => {
+            progress_bar.println(format!(
+                "{} {}",
+                "✔".green(),
+                format!(
+                    "Markdown {} '{}' ({} characters, ~{} tokens)",
+                    verb,
+                    path.cyan(),
+                    stats.characters,
+                    stats.tokens
+                )
+                .green()
+            ));
+            Ok(())
+        }
+        Err(e) => {
+            progress_bar.println(format!(
+                "{} {}",
+                "✖".red(),
+                format!("Failed to save file to '{}'. Error: {}", path, e).red()
+            ));
+            Err(e.into())
+        }
+    }
+}
+
+/// Prints one JSON object per file to stdout (`--output-stdout-json`).
+pub struct StdoutSink;
+
+#[async_trait(?Send)]
+impl OutputSink for StdoutSink {
+    async fn write(
+        &self,
+        file: &ConvertedFile<'_>,
+        _progress_bar: &ProgressBar,
+    ) -> Result<(), NotedError> {
+        println!("{}", json_line(file)?);
+        Ok(())
+    }
+}
+
+/// The JSON object [`StdoutSink`] prints for `file`.
+fn json_line(file: &ConvertedFile<'_>) -> Result<String, NotedError> {
+    // Counted after the provider has answered, so a PDF lopdf can't parse
+    // mustn't cost the result.
+    let pages = if file.mime_type == "application/pdf" {
+        pdf_utils::page_count(file.data).ok()
+    } else {
+        Some(1)
+    };
+    let result = output::JsonResult {
+        source: &file.source.to_string_lossy(),
+        provider: file.provider,
+        model: file.model,
+        pages,
+        markdown: file.markdown,
+        usage: file.stats.usage,
+        language: file.language,
+    };
+    serde_json::to_string(&result).map_err(|e| NotedError::ResponseDecodeError(e.to_string()))
+}
+
+/// Creates a Notion page per file (`--notion`).
+pub struct NotionSink<'a> {
+    pub client: NotionClient,
+    pub config: &'a NotionConfig,
+    /// `--title` template, see [`output::resolve_title`].
+    pub title: Option<&'a str>,
+    pub skip_existing: bool,
+}
+
+#[async_trait(?Send)]
+impl OutputSink for NotionSink<'_> {
+    async fn write(
+        &self,
+        file: &ConvertedFile<'_>,
+        progress_bar: &ProgressBar,
+    ) -> Result<(), NotedError> {
+        let title = output::resolve_title(self.title, file.source);
+        let upload = upload_to_notion(
+            &self.client,
+            self.config,
+            file.source,
+            &title,
+            file.markdown,
+            file.language,
+            self.skip_existing,
+        )
+        .await?;
+        progress_bar.println(upload.message());
+        Ok(())
+    }
+}
+
+/// Outcome of sending a converted file to Notion.
+pub enum NotionUpload {
+    Created(String),
+    AlreadyExists(String),
+}
+
+impl NotionUpload {
+    pub fn message(&self) -> String {
+        match self {
+            NotionUpload::Created(url) => format!(
+                "{} {}",
+                "✔".green(),
+                format!("Notion page created at '{}'", url.cyan()).green()
+            ),
+            NotionUpload::AlreadyExists(url) => format!(
+                "{} {}",
+                "➜".yellow(),
+                format!(
+                    "Notion page already exists at '{}', skipping upload.",
+                    url.cyan()
+                )
+                .yellow()
+            ),
+        }
+    }
+}
+
+/// Creates a Notion page for `markdown`, which came from `source_path`. With
+/// `skip_existing`, a page already created from the same source is kept as is.
+/// `language` fills the configured language property, if any.
+pub async fn upload_to_notion(
+    client: &NotionClient,
+    config: &NotionConfig,
+    source_path: &Path,
+    title: &str,
+    markdown: &str,
+    language: Option<&str>,
+    skip_existing: bool,
+) -> Result<NotionUpload, NotedError> {
+    let source_id = std::fs::canonicalize(source_path)
+        .unwrap_or_else(|_| source_path.to_path_buf())
+        .to_string_lossy()
+        .into_owned();
+    let source = config
+        .source_property_name
+        .as_deref()
+        .map(|property_name| SourceMarker {
+            property_name,
+            value: &source_id,
+        });
+
+    if skip_existing
+        && let Some(page) = client
+            .find_existing_page(title, &config.title_property_name, source.as_ref())
+            .await?
+    {
+        return Ok(NotionUpload::AlreadyExists(page.url));
+    }
+
+    let mut properties = config.properties.clone();
+    if let (Some(property_name), Some(language)) = (&config.language_property_name, language) {
+        properties.push(NotionPropertyConfig {
+            name: property_name.clone(),
+            property_type: "select".to_string(),
+            default_value: serde_json::Value::String(language.to_string()),
+        });
+    }
+
+    let page = client
+        .create_notion_page(
+            title,
+            &config.title_property_name,
+            &properties,
+            source.as_ref(),
+            markdown,
+        )
+        .await?;
+    Ok(NotionUpload::Created(page.url))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai_provider::TokenUsage;
+    use crate::test_utils::TempDir;
+
+    const STATS: ContentStats = ContentStats {
+        characters: 8,
+        tokens: 2,
+        usage: None,
+    };
+
+    fn converted<'a>(
+        source: &'a Path,
+        markdown: &'a str,
+        language: Option<&'a str>,
+        images: &'a [PageImage],
+    ) -> ConvertedFile<'a> {
+        ConvertedFile {
+            source,
+            data: b"png",
+            mime_type: "image/png",
+            markdown,
+            stats: &STATS,
+            provider: "ollama",
+            model: "llava",
+            language,
+            images,
+        }
+    }
+
+    fn image(page: u32) -> PageImage {
+        PageImage {
+            page,
+            png: vec![page as u8],
+        }
+    }
+
+    #[tokio::test]
+    async fn file_sink_writes_front_matter_and_extracted_images() {
+        let tree = TempDir::new("sinks-file");
+        let source = tree.write("scans/week 3.pdf", "pdf");
+        let out = tree.path().join("out");
+        let sink = FileSink {
+            output_dir: out.to_str(),
+            date_subdirs: None,
+            extension: "md",
+            options: OutputOptions::default(),
+        };
+        let images = [image(1), image(1), image(2)];
+        sink.write(
+            &converted(&source, "# Notes\n", Some("eng"), &images),
+            &ProgressBar::hidden(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(out.join("week 3.md")).unwrap(),
+            "---\nlanguage: eng\n---\n\n# Notes\n\n## Extracted images\n\n\
+             ![Page 1, image 1](week%203_assets/page-1-1.png)\n\n\
+             ![Page 1, image 2](week%203_assets/page-1-2.png)\n\n\
+             ![Page 2, image 1](week%203_assets/page-2-1.png)\n"
+        );
+        assert_eq!(
+            std::fs::read(out.join("week 3_assets/page-2-1.png")).unwrap(),
+            [2]
+        );
+    }
+
+    #[tokio::test]
+    async fn append_sink_adds_parts_under_headings() {
+        let tree = TempDir::new("sinks-append");
+        let document = tree.path().join("course.md");
+        let sink = AppendSink {
+            path: &document,
+            heading: Some("{stem}"),
+            options: OutputOptions::default(),
+        };
+        let progress_bar = ProgressBar::hidden();
+        let first = tree.write("week1.png", "png");
+        let second = tree.write("week2.pdf", "pdf");
+        sink.write(
+            &converted(&first, "Waves\n", Some("eng"), &[]),
+            &progress_bar,
+        )
+        .await
+        .unwrap();
+        sink.write(
+            &converted(&second, "Wellen\n", Some("deu"), &[image(1)]),
+            &progress_bar,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(&document).unwrap(),
+            "---\nlanguage: eng\n---\n\n## week1\n\nWaves\n\n---\n\n## week2\n\nWellen\n\n\
+             ## Extracted images\n\n![Page 1, image 1](week2_assets/page-1-1.png)\n"
+        );
+        assert!(tree.path().join("week2_assets/page-1-1.png").exists());
+    }
+
+    #[test]
+    fn stdout_sink_prints_a_json_line() {
+        let stats = ContentStats {
+            usage: Some(TokenUsage {
+                input_tokens: 10,
+                output_tokens: 4,
+            }),
+            ..STATS
+        };
+        let file = ConvertedFile {
+            stats: &stats,
+            ..converted(Path::new("scans/page.png"), "# Notes\n", Some("eng"), &[])
+        };
+        let line = json_line(&file).unwrap();
+        assert!(!line.contains('\n'));
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&line).unwrap(),
+            serde_json::json!({
+                "source": "scans/page.png",
+                "provider": "ollama",
+                "model": "llava",
+                "pages": 1,
+                "markdown": "# Notes\n",
+                "usage": {"input_tokens": 10, "output_tokens": 4},
+                "language": "eng",
+            })
+        );
+    }
+}
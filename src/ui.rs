@@ -1,5 +1,14 @@
 use crate::Config;
+use crate::ai_provider::TokenUsage;
+use crate::config::{ConfigChange, OllamaApi, OpenAIApi};
+use crate::file_utils::{CONVERTIBLE_FILE_TYPES, SUPPORTED_FILE_TYPES};
+use crate::markdown_utils::ContentStats;
+use crate::prompt_presets::PromptPreset;
+use crate::run_stats::RunTotals;
+use clap::ValueEnum;
 use colored::Colorize;
+use std::path::PathBuf;
+use std::time::Duration;
 
 pub fn ascii_art() {
     println!(
@@ -21,7 +30,45 @@ pub fn ascii_art() {
     );
 }
 
-pub fn print_clean_config(config: Config) {
+/// Shows an API key in full when `reveal` is set, otherwise only its first
+/// three characters.
+fn display_key(api_key: &str, reveal: bool) -> String {
+    if reveal {
+        api_key.to_string()
+    } else {
+        format!("{:.3}***************** (hidden for security)", api_key)
+    }
+}
+
+/// Prints what saving would add (+), change (~) or remove (-), with API keys
+/// hidden.
+pub fn print_config_changes(changes: &[ConfigChange]) {
+    for change in changes {
+        let show = |value: &toml::Value| match value {
+            toml::Value::String(key) if change.is_secret() => display_key(key, false),
+            toml::Value::Array(keys) if change.is_secret() => {
+                format!("{} keys (hidden for security)", keys.len())
+            }
+            value => value.to_string(),
+        };
+        match (&change.old, &change.new) {
+            (Some(old), Some(new)) => println!(
+                "  {} {}: {} -> {}",
+                "~".yellow(),
+                change.key,
+                show(old).red(),
+                show(new).green()
+            ),
+            (Some(old), None) => println!("  {} {}: {}", "-".red(), change.key, show(old).red()),
+            (None, Some(new)) => {
+                println!("  {} {}: {}", "+".green(), change.key, show(new).green())
+            }
+            (None, None) => {}
+        }
+    }
+}
+
+pub fn print_clean_config(config: Config, reveal: bool) {
     println!("{}", "noted.md Configuration".bold());
     println!("-------------------------");
 
@@ -33,23 +80,26 @@ pub fn print_clean_config(config: Config) {
 
     println!("{}", "Gemini".bold());
     if let Some(gemini_config) = config.gemini {
-        let api_key = format!(
-            "{:.3}***************** (hidden for security)",
-            gemini_config.api_key
-        );
+        let api_key = display_key(&gemini_config.api_key, reveal);
         println!("  API Key: {}", api_key);
+        if !gemini_config.api_keys.is_empty() {
+            println!("  Extra API Keys: {}", gemini_config.api_keys.len());
+        }
+        if let Some(base_url) = &gemini_config.base_url {
+            println!("  URL:     {}", base_url);
+        }
     } else {
         println!("  (Not Configured)");
     }
 
     println!("{}", "Claude".bold());
     if let Some(claude_config) = config.claude {
-        let api_key = format!(
-            "{:.3}***************** (hidden for security)",
-            claude_config.api_key
-        );
+        let api_key = display_key(&claude_config.api_key, reveal);
         println!("  API Key: {}", api_key);
         println!("  Model:   {}", claude_config.model);
+        if let Some(base_url) = &claude_config.base_url {
+            println!("  URL:     {}", base_url);
+        }
     } else {
         println!("  (Not Configured)");
     }
@@ -58,6 +108,9 @@ pub fn print_clean_config(config: Config) {
     if let Some(ollama_config) = config.ollama {
         println!("  URL:     {}", ollama_config.url);
         println!("  Model:   {}", ollama_config.model);
+        if ollama_config.api == OllamaApi::Chat {
+            println!("  API:     chat");
+        }
     } else {
         println!("  (Not Configured)");
     }
@@ -66,13 +119,13 @@ pub fn print_clean_config(config: Config) {
     if let Some(openai_config) = config.openai {
         println!("  URL:     {}", openai_config.url);
         println!("  Model:   {}", openai_config.model);
-        let api_key = if openai_config.api_key.is_none() {
-            "API key empty.".to_string()
+        if openai_config.api == OpenAIApi::Responses {
+            println!("  API:     responses");
+        }
+        let api_key = if let Some(api_key) = openai_config.api_key {
+            display_key(&api_key, reveal)
         } else {
-            format!(
-                "{:.3}***************** (hidden for security)",
-                openai_config.api_key.unwrap()
-            )
+            "API key empty.".to_string()
         };
 
         println!("  API Key: {}", api_key);
@@ -82,12 +135,13 @@ pub fn print_clean_config(config: Config) {
 
     println!("{}", "Notion".bold());
     if let Some(notion_config) = config.notion {
-        let api_key = format!(
-            "{:.3}***************** (hidden for security)",
-            notion_config.api_key
-        );
+        let api_key = display_key(&notion_config.api_key, reveal);
         println!("  API Key: {}", api_key);
         println!("  Database ID: {}", notion_config.database_id);
+        println!("  API Version: {}", notion_config.api_version);
+        if let Some(base_url) = &notion_config.base_url {
+            println!("  URL:     {}", base_url);
+        }
         println!(
             "  Title Property Name: {}",
             notion_config.title_property_name
@@ -97,3 +151,151 @@ pub fn print_clean_config(config: Config) {
         println!("  (Not Configured)");
     }
 }
+
+pub fn print_supported_file_types() {
+    println!("{}", "Supported file types".bold());
+    println!("-------------------------");
+    for (extension, mime_type) in SUPPORTED_FILE_TYPES {
+        println!("  .{:<6} {}", extension, mime_type.dimmed());
+    }
+}
+
+pub fn print_prompt_presets() {
+    println!("{}", "Prompt presets".bold());
+    println!("-------------------------");
+    for preset in PromptPreset::value_variants() {
+        if let Some(value) = preset.to_possible_value() {
+            println!(
+                "  {:<10} {}",
+                value.get_name(),
+                preset.description().dimmed()
+            );
+        }
+    }
+}
+
+pub fn print_content_summary(summary: &[(PathBuf, ContentStats)]) {
+    if summary.is_empty() {
+        return;
+    }
+    println!("\n{}", "Content summary".bold());
+    println!("-------------------------");
+    for (path, stats) in summary {
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy())
+            .unwrap_or_else(|| path.to_string_lossy());
+        println!(
+            "  {:<32} {:>8} chars {:>8} tokens{}",
+            name,
+            stats.characters,
+            format!("~{}", stats.tokens),
+            format_usage(stats.usage)
+        );
+    }
+    let characters: usize = summary.iter().map(|(_, stats)| stats.characters).sum();
+    let tokens: usize = summary.iter().map(|(_, stats)| stats.tokens).sum();
+    let usage = summary
+        .iter()
+        .filter_map(|(_, stats)| stats.usage)
+        .reduce(|total, usage| total + usage);
+    println!(
+        "  {:<32} {:>8} chars {:>8} tokens{}",
+        "Total".bold(),
+        characters,
+        format!("~{}", tokens),
+        format_usage(usage)
+    );
+}
+
+/// The token usage reported by the provider, as a column of the summary.
+fn format_usage(usage: Option<TokenUsage>) -> String {
+    match usage {
+        Some(usage) => format!("   {} in / {} out", usage.input_tokens, usage.output_tokens),
+        None => String::new(),
+    }
+}
+
+/// Prints the `--stats` summary of a run that took `elapsed`.
+pub fn print_run_stats(totals: &RunTotals, elapsed: Duration) {
+    let local_time = elapsed.saturating_sub(totals.network_time);
+    println!("\n{}", "Run statistics".bold());
+    println!("-------------------------");
+    println!("  Total time:        {:.1}s", elapsed.as_secs_f64());
+    println!(
+        "  Waiting for API:   {:.1}s",
+        totals.network_time.as_secs_f64()
+    );
+    println!("  Local processing:  {:.1}s", local_time.as_secs_f64());
+    if totals.requests > 0 {
+        println!(
+            "  Average latency:   {:.2}s per request",
+            totals.network_time.as_secs_f64() / totals.requests as f64
+        );
+    }
+    println!(
+        "  Requests:          {} ({} failed)",
+        totals.requests, totals.failed_requests
+    );
+    println!("  Retries:           {}", totals.retries);
+    println!(
+        "  Uploaded:          {}",
+        format_bytes(totals.bytes_uploaded)
+    );
+    match totals.usage {
+        Some(usage) => println!(
+            "  Tokens:            {} in / {} out",
+            usage.input_tokens, usage.output_tokens
+        ),
+        None => println!("  Tokens:            not reported by the provider"),
+    }
+}
+
+/// Explains why a directory had nothing to convert, given the extensions of
+/// the files that were skipped.
+pub fn print_no_supported_files(unsupported: &[String]) {
+    println!("{}", "No supported files found in the directory.".yellow());
+    if unsupported.is_empty() {
+        return;
+    }
+
+    let listed: Vec<String> = unsupported.iter().map(|ext| format!(".{}", ext)).collect();
+    println!("Skipped unsupported file types: {}", listed.join(", "));
+    for ext in unsupported {
+        let lowercase = ext.to_lowercase();
+        if lowercase != *ext
+            && SUPPORTED_FILE_TYPES
+                .iter()
+                .any(|(extension, _)| *extension == lowercase)
+        {
+            println!(
+                "  {} .{} files are supported when the extension is lowercase; rename them to .{}",
+                "➜".cyan(),
+                ext,
+                lowercase
+            );
+        } else if CONVERTIBLE_FILE_TYPES.contains(&lowercase.as_str()) {
+            println!(
+                "  {} .{} images can be converted to PNG or JPEG first",
+                "➜".cyan(),
+                ext
+            );
+        }
+    }
+    println!("Run 'notedmd convert --list-supported' to see the supported file types.");
+}
+
+/// Formats a byte count with the largest unit that keeps it at or above one.
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
@@ -0,0 +1,104 @@
+//! Runs `notedmd config --init` and checks the config it writes.
+
+use std::path::{Path, PathBuf};
+use std::process::Output;
+
+use tokio::process::Command;
+
+/// A fresh `XDG_CONFIG_HOME` for one test. Removed when the test ends.
+struct ConfigHome(PathBuf);
+
+impl ConfigHome {
+    fn new(name: &str) -> Self {
+        let root = std::env::temp_dir().join(format!(
+            "notedmd-config-test-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        Self(root)
+    }
+
+    fn config_file(&self) -> PathBuf {
+        self.0.join("notedmd").join("config.toml")
+    }
+
+    fn config(&self) -> toml::Table {
+        toml::from_str(&std::fs::read_to_string(self.config_file()).unwrap()).unwrap()
+    }
+
+    async fn run(&self, args: &[&str]) -> Output {
+        Command::new(env!("CARGO_BIN_EXE_notedmd"))
+            .arg("config")
+            .args(args)
+            .env("XDG_CONFIG_HOME", self.path())
+            .output()
+            .await
+            .unwrap()
+    }
+
+    fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for ConfigHome {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+#[tokio::test]
+async fn init_writes_a_config_that_loads() {
+    let home = ConfigHome::new("init");
+    let output = home
+        .run(&["--init", "--provider", "gemini", "--api-key", "gemini-key"])
+        .await;
+    assert!(output.status.success(), "{:?}", output);
+
+    let config = home.config();
+    assert_eq!(config["active_provider"].as_str(), Some("gemini"));
+    assert_eq!(config["gemini"]["api_key"].as_str(), Some("gemini-key"));
+
+    // A second provider is added next to the first and becomes active.
+    let output = home
+        .run(&[
+            "--init",
+            "--provider",
+            "ollama",
+            "--url",
+            "http://gpu-box:11434/",
+            "--model",
+            "llava",
+        ])
+        .await;
+    assert!(output.status.success(), "{:?}", output);
+    let config = home.config();
+    assert_eq!(config["active_provider"].as_str(), Some("ollama"));
+    assert_eq!(
+        config["ollama"]["url"].as_str(),
+        Some("http://gpu-box:11434")
+    );
+    assert_eq!(config["ollama"]["model"].as_str(), Some("llava"));
+    assert_eq!(config["gemini"]["api_key"].as_str(), Some("gemini-key"));
+
+    let output = home.run(&["--show"]).await;
+    assert!(output.status.success(), "{:?}", output);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("llava"));
+}
+
+#[tokio::test]
+async fn init_refuses_incomplete_flags() {
+    let home = ConfigHome::new("init-incomplete");
+    let output = home.run(&["--init", "--provider", "claude"]).await;
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--api-key"));
+    assert!(!home.config_file().exists());
+
+    let output = home
+        .run(&["--init", "--provider", "ollama", "--api-key", "key"])
+        .await;
+    assert!(!output.status.success());
+    assert!(!home.config_file().exists());
+}
@@ -0,0 +1,346 @@
+//! Runs `notedmd convert` against a stand-in Ollama server.
+
+use std::io::Cursor;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::process::Output;
+use std::sync::{Arc, Mutex};
+
+use axum::Router;
+use axum::extract::State;
+use axum::routing::post;
+use tokio::process::Command;
+
+/// What the fake server answers with, in order, and the request bodies it
+/// received.
+#[derive(Default)]
+struct Exchange {
+    answers: Vec<String>,
+    requests: Vec<String>,
+}
+
+type Shared = Arc<Mutex<Exchange>>;
+
+/// Starts a fake Ollama `/api/generate` that sends back `answers` one per
+/// request, repeating the last one once they run out.
+async fn fake_ollama(answers: &[&str]) -> (SocketAddr, Shared) {
+    let bodies: Vec<String> = answers
+        .iter()
+        .map(|answer| serde_json::json!({ "response": answer, "done": true }).to_string())
+        .collect();
+    fake_ollama_bodies(bodies).await
+}
+
+/// Like [`fake_ollama`], with the response bodies given as they are sent.
+async fn fake_ollama_bodies(bodies: Vec<String>) -> (SocketAddr, Shared) {
+    let exchange = Arc::new(Mutex::new(Exchange {
+        answers: bodies,
+        requests: Vec::new(),
+    }));
+    let app = Router::new()
+        .route(
+            "/api/generate",
+            post(|State(exchange): State<Shared>, body: String| async move {
+                let mut exchange = exchange.lock().unwrap();
+                exchange.requests.push(body);
+                if exchange.answers.len() > 1 {
+                    exchange.answers.remove(0)
+                } else {
+                    exchange.answers[0].clone()
+                }
+            }),
+        )
+        .with_state(exchange.clone());
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    (address, exchange)
+}
+
+/// A fresh directory for one test, holding a config that uses the fake
+/// Ollama. Removed when the test ends.
+struct Workspace(PathBuf);
+
+impl Workspace {
+    fn new(name: &str, ollama: SocketAddr) -> Self {
+        let root = std::env::temp_dir().join(format!(
+            "notedmd-convert-test-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&root);
+        let config_dir = root.join("config").join("notedmd");
+        std::fs::create_dir_all(&config_dir).unwrap();
+        std::fs::write(
+            config_dir.join("config.toml"),
+            format!(
+                "active_provider = \"ollama\"\n\n[ollama]\nurl = \"http://{}\"\nmodel = \"test\"\n",
+                ollama
+            ),
+        )
+        .unwrap();
+        Self(root)
+    }
+
+    fn path(&self) -> &Path {
+        &self.0
+    }
+
+    /// Writes a small PNG page and returns its path.
+    fn page(&self, name: &str) -> PathBuf {
+        let mut bytes = Vec::new();
+        image::RgbImage::new(4, 4)
+            .write_to(&mut Cursor::new(&mut bytes), image::ImageFormat::Png)
+            .unwrap();
+        let path = self.0.join(name);
+        std::fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    async fn convert(&self, input: &Path, args: &[&str]) -> Output {
+        Command::new(env!("CARGO_BIN_EXE_notedmd"))
+            .arg("convert")
+            .arg(input)
+            .arg("--output")
+            .arg(self.0.join("out"))
+            .args(args)
+            .env("XDG_CONFIG_HOME", self.0.join("config"))
+            .env("XDG_CACHE_HOME", self.0.join("cache"))
+            .output()
+            .await
+            .unwrap()
+    }
+}
+
+impl Drop for Workspace {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+fn request_count(exchange: &Shared) -> usize {
+    exchange.lock().unwrap().requests.len()
+}
+
+#[tokio::test]
+async fn on_empty_fail_reports_the_file() {
+    let (ollama, exchange) = fake_ollama(&["  \n"]).await;
+    let workspace = Workspace::new("on-empty-fail", ollama);
+    let page = workspace.page("page.png");
+
+    let output = workspace
+        .convert(&page, &["--on-empty", "fail", "--abort-on-error"])
+        .await;
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("empty"));
+    assert_eq!(request_count(&exchange), 1);
+    assert!(!workspace.path().join("out/page.md").exists());
+}
+
+#[tokio::test]
+async fn on_empty_skip_moves_on() {
+    let (ollama, exchange) = fake_ollama(&[""]).await;
+    let workspace = Workspace::new("on-empty-skip", ollama);
+    let page = workspace.page("page.png");
+
+    let output = workspace
+        .convert(&page, &["--on-empty", "skip", "--abort-on-error"])
+        .await;
+    assert!(output.status.success());
+    assert_eq!(request_count(&exchange), 1);
+    assert!(!workspace.path().join("out/page.md").exists());
+}
+
+#[tokio::test]
+async fn on_empty_retry_sends_the_file_again() {
+    let (ollama, exchange) = fake_ollama(&["", "", "# Page"]).await;
+    let workspace = Workspace::new("on-empty-retry", ollama);
+    let page = workspace.page("page.png");
+
+    let output = workspace.convert(&page, &["--on-empty", "retry"]).await;
+    assert!(output.status.success());
+    assert_eq!(request_count(&exchange), 3);
+    let markdown = std::fs::read_to_string(workspace.path().join("out/page.md")).unwrap();
+    assert!(markdown.contains("# Page"));
+
+    // After two retries it gives up like `fail`.
+    let (ollama, exchange) = fake_ollama(&[""]).await;
+    let workspace = Workspace::new("on-empty-retry-give-up", ollama);
+    let page = workspace.page("page.png");
+    let output = workspace
+        .convert(&page, &["--on-empty", "retry", "--abort-on-error"])
+        .await;
+    assert!(!output.status.success());
+    assert_eq!(request_count(&exchange), 3);
+}
+
+#[tokio::test]
+async fn moves_sources_by_outcome() {
+    let (ollama, _) = fake_ollama(&["# Page", ""]).await;
+    let workspace = Workspace::new("move-source", ollama);
+    let done = workspace.path().join("done");
+    let failed = workspace.path().join("failed");
+    let moves = [
+        "--move-done",
+        done.to_str().unwrap(),
+        "--move-failed",
+        failed.to_str().unwrap(),
+    ];
+
+    let converted = workspace.page("converted.png");
+    let output = workspace.convert(&converted, &moves).await;
+    assert!(output.status.success());
+    assert!(!converted.exists());
+    assert!(done.join("converted.png").exists());
+    assert!(!failed.exists());
+
+    let empty = workspace.page("empty.png");
+    let output = workspace.convert(&empty, &moves).await;
+    assert!(output.status.success());
+    assert!(!empty.exists());
+    assert!(failed.join("empty.png").exists());
+}
+
+#[tokio::test]
+async fn converts_a_file_without_an_extension_as_the_forced_type() {
+    let (ollama, exchange) = fake_ollama(&["# Page"]).await;
+    let workspace = Workspace::new("forced-type", ollama);
+    let scan = workspace.page("scan");
+
+    let output = workspace.convert(&scan, &[]).await;
+    assert!(!output.status.success());
+    assert_eq!(request_count(&exchange), 0);
+
+    let output = workspace.convert(&scan, &["--as", "png"]).await;
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(request_count(&exchange), 1);
+    assert!(workspace.path().join("out/scan.md").exists());
+}
+
+#[tokio::test]
+async fn sends_the_sidecar_prompt_instead_of_the_given_one() {
+    let (ollama, exchange) = fake_ollama(&["# Diagram"]).await;
+    let workspace = Workspace::new("sidecar", ollama);
+    let diagram = workspace.page("diagram.png");
+    std::fs::write(
+        workspace.path().join("diagram.png.prompt"),
+        "Label every arrow.\n",
+    )
+    .unwrap();
+
+    let output = workspace
+        .convert(&diagram, &["--prompt", "Transcribe the page."])
+        .await;
+    assert!(output.status.success(), "{:?}", output);
+    let exchange = exchange.lock().unwrap();
+    let request: serde_json::Value = serde_json::from_str(&exchange.requests[0]).unwrap();
+    assert_eq!(request["prompt"], "Label every arrow.");
+}
+
+#[tokio::test]
+async fn converts_the_files_in_an_input_list() {
+    let (ollama, exchange) = fake_ollama(&["# Page"]).await;
+    let workspace = Workspace::new("input-list", ollama);
+    let first = workspace.page("first.png");
+    let second = workspace.page("second.png");
+    workspace.page("unlisted.png");
+    let list = workspace.path().join("files.txt");
+    std::fs::write(
+        &list,
+        format!(
+            "# Scans\n{}\n\n{}\n{}\n",
+            second.display(),
+            workspace.path().join("missing.png").display(),
+            first.display()
+        ),
+    )
+    .unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_notedmd"))
+        .arg("convert")
+        .arg("--input-list")
+        .arg(&list)
+        .arg("--output")
+        .arg(workspace.path().join("out"))
+        .env("XDG_CONFIG_HOME", workspace.path().join("config"))
+        .env("XDG_CACHE_HOME", workspace.path().join("cache"))
+        .output()
+        .await
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("missing.png"));
+    assert_eq!(request_count(&exchange), 2);
+    assert!(workspace.path().join("out/first.md").exists());
+    assert!(workspace.path().join("out/second.md").exists());
+    assert!(!workspace.path().join("out/unlisted.md").exists());
+}
+
+#[tokio::test]
+async fn abort_on_error_stops_a_directory_run_at_the_first_failure() {
+    let (ollama, exchange) = fake_ollama(&["# A", "", "# C"]).await;
+    let workspace = Workspace::new("abort-on-error", ollama);
+    let scans = workspace.path().join("scans");
+    std::fs::create_dir(&scans).unwrap();
+    for name in ["a.png", "b.png", "c.png"] {
+        workspace.page(&format!("scans/{}", name));
+    }
+
+    let output = workspace
+        .convert(&scans, &["--on-empty", "fail", "--abort-on-error"])
+        .await;
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("b.png"));
+    assert_eq!(request_count(&exchange), 2);
+    assert!(workspace.path().join("out/a.md").exists());
+    assert!(!workspace.path().join("out/c.md").exists());
+
+    // Without it the run goes on, and only the files left are sent.
+    let output = workspace.convert(&scans, &["--on-empty", "fail"]).await;
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(request_count(&exchange), 4);
+    assert!(workspace.path().join("out/b.md").exists());
+    assert!(workspace.path().join("out/c.md").exists());
+}
+
+#[tokio::test]
+async fn retries_an_undecodable_response_once() {
+    let gateway_page = "<html><body>502 Bad Gateway</body></html>".to_string();
+    let answer = serde_json::json!({ "response": "# Page", "done": true }).to_string();
+    let (ollama, exchange) = fake_ollama_bodies(vec![gateway_page.clone(), answer]).await;
+    let workspace = Workspace::new("decode-retry", ollama);
+    let page = workspace.page("page.png");
+
+    let output = workspace.convert(&page, &["--abort-on-error"]).await;
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(request_count(&exchange), 2);
+    assert!(workspace.path().join("out/page.md").exists());
+
+    let (ollama, exchange) = fake_ollama_bodies(vec![gateway_page]).await;
+    let workspace = Workspace::new("decode-retry-give-up", ollama);
+    let page = workspace.page("page.png");
+    let output = workspace.convert(&page, &["--abort-on-error"]).await;
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8_lossy(&output.stderr)
+            .contains("could not be decoded after a retry: the body is an HTML page")
+    );
+    assert_eq!(request_count(&exchange), 2);
+}
+
+#[tokio::test]
+async fn dedupe_sends_identical_files_once() {
+    let (ollama, exchange) = fake_ollama(&["# Page"]).await;
+    let workspace = Workspace::new("dedupe", ollama);
+    let scans = workspace.path().join("scans");
+    std::fs::create_dir(&scans).unwrap();
+    workspace.page("scans/copy.png");
+    workspace.page("scans/page.png");
+
+    let output = workspace.convert(&scans, &["--dedupe"]).await;
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(request_count(&exchange), 1);
+    for name in ["copy.md", "page.md"] {
+        let markdown = std::fs::read_to_string(workspace.path().join("out").join(name)).unwrap();
+        assert!(markdown.contains("# Page"));
+    }
+}
@@ -0,0 +1,164 @@
+//! Runs `notedmd notion upload` against a stand-in Notion API.
+
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::process::Output;
+use std::sync::{Arc, Mutex};
+
+use axum::Router;
+use axum::extract::State;
+use axum::http::{HeaderMap, Uri};
+use axum::routing::post;
+use tokio::process::Command;
+
+const DATABASE_ID: &str = "0123456789abcdef0123456789abcdef";
+
+/// A request the fake Notion received.
+struct Received {
+    path: String,
+    notion_version: String,
+    body: serde_json::Value,
+}
+
+type Requests = Arc<Mutex<Vec<Received>>>;
+
+/// Starts a fake Notion API that finds no existing pages and creates every
+/// page it's sent.
+async fn fake_notion() -> (SocketAddr, Requests) {
+    let requests: Requests = Arc::default();
+    let record = |State(requests): State<Requests>, uri: Uri, headers: HeaderMap, body: String| async move {
+        requests.lock().unwrap().push(Received {
+            path: uri.path().to_string(),
+            notion_version: headers["notion-version"].to_str().unwrap().to_string(),
+            body: serde_json::from_str(&body).unwrap(),
+        });
+        if uri.path().ends_with("/query") {
+            r#"{"results":[]}"#
+        } else {
+            r#"{"id":"page-1","url":"https://notion.so/page-1"}"#
+        }
+    };
+    let app = Router::new()
+        .route("/v1/pages", post(record))
+        .route("/v1/databases/{id}/query", post(record))
+        .with_state(requests.clone());
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    (address, requests)
+}
+
+/// A fresh directory for one test, holding a config that uses the fake
+/// Notion. Removed when the test ends.
+struct Workspace(PathBuf);
+
+impl Workspace {
+    fn new(name: &str, notion: SocketAddr, extra_config: &str) -> Self {
+        let root = std::env::temp_dir().join(format!(
+            "notedmd-notion-test-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&root);
+        let config_dir = root.join("config").join("notedmd");
+        std::fs::create_dir_all(&config_dir).unwrap();
+        std::fs::write(
+            config_dir.join("config.toml"),
+            format!(
+                "[notion]\napi_key = \"secret\"\ndatabase_id = \"{}\"\ntitle_property_name = \"Name\"\nbase_url = \"http://{}\"\n{}",
+                DATABASE_ID, notion, extra_config
+            ),
+        )
+        .unwrap();
+        Self(root)
+    }
+
+    fn write(&self, name: &str, contents: &str) -> PathBuf {
+        let path = self.0.join(name);
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    async fn upload(&self, file: &Path, args: &[&str]) -> Output {
+        Command::new(env!("CARGO_BIN_EXE_notedmd"))
+            .args(["notion", "upload"])
+            .arg(file)
+            .args(args)
+            .env("XDG_CONFIG_HOME", self.0.join("config"))
+            .env("XDG_CACHE_HOME", self.0.join("cache"))
+            .output()
+            .await
+            .unwrap()
+    }
+}
+
+impl Drop for Workspace {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+#[tokio::test]
+async fn uploads_a_markdown_file() {
+    let (notion, requests) = fake_notion().await;
+    let workspace = Workspace::new("upload", notion, "");
+    let notes = workspace.write("lecture.md", "# Waves\n\nA wave carries energy.\n");
+
+    let output = workspace.upload(&notes, &[]).await;
+    assert!(output.status.success(), "{:?}", output);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("https://notion.so/page-1"));
+
+    let requests = requests.lock().unwrap();
+    assert_eq!(requests.len(), 1);
+    let page = &requests[0];
+    assert_eq!(page.path, "/v1/pages");
+    assert_eq!(page.notion_version, "2022-06-28");
+    assert_eq!(page.body["parent"]["database_id"], DATABASE_ID);
+    assert_eq!(
+        page.body["properties"]["Name"]["title"][0]["text"]["content"],
+        "lecture.md"
+    );
+    assert_eq!(page.body["children"][0]["type"], "heading_1");
+    assert_eq!(page.body["children"][1]["type"], "paragraph");
+}
+
+#[tokio::test]
+async fn uploads_with_a_title_template_after_looking_for_the_page() {
+    let (notion, requests) = fake_notion().await;
+    let workspace = Workspace::new("upload-title", notion, "");
+    let notes = workspace.write("lecture.md", "Notes\n");
+
+    let output = workspace
+        .upload(&notes, &["--title", "Physics: {stem}", "--skip-existing"])
+        .await;
+    assert!(output.status.success(), "{:?}", output);
+
+    let requests = requests.lock().unwrap();
+    assert_eq!(requests.len(), 2);
+    assert_eq!(
+        requests[0].path,
+        format!("/v1/databases/{}/query", DATABASE_ID)
+    );
+    assert_eq!(
+        requests[1].body["properties"]["Name"]["title"][0]["text"]["content"],
+        "Physics: lecture"
+    );
+}
+
+#[tokio::test]
+async fn sends_the_configured_api_version() {
+    let (notion, requests) = fake_notion().await;
+    let workspace = Workspace::new("api-version", notion, "api_version = \"2025-09-03\"\n");
+    let notes = workspace.write("lecture.md", "Notes\n");
+
+    let output = workspace.upload(&notes, &["--skip-existing"]).await;
+    assert!(output.status.success(), "{:?}", output);
+
+    let requests = requests.lock().unwrap();
+    assert_eq!(requests.len(), 2);
+    assert!(
+        requests
+            .iter()
+            .all(|request| request.notion_version == "2025-09-03")
+    );
+}
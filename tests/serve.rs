@@ -0,0 +1,160 @@
+//! Runs `notedmd serve` against a stand-in Ollama server and checks the
+//! responses of `POST /convert`.
+
+use std::io::Cursor;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::Router;
+use axum::extract::State;
+use axum::routing::post;
+use reqwest::StatusCode;
+
+const TOKEN: &str = "test-token";
+
+/// Stops the server when the test ends, even when it fails.
+struct Server(Child);
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+/// Starts a fake Ollama `/api/generate` that answers with a fixed markdown
+/// line and keeps the request bodies it receives.
+async fn fake_ollama() -> (SocketAddr, Arc<Mutex<Vec<String>>>) {
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let app = Router::new()
+        .route(
+            "/api/generate",
+            post(
+                |State(requests): State<Arc<Mutex<Vec<String>>>>, body: String| async move {
+                    requests.lock().unwrap().push(body);
+                    r##"{"response":"# Hi\n\nFrom the notes.","done":true}"##
+                },
+            ),
+        )
+        .with_state(requests.clone());
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    (address, requests)
+}
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+/// Writes a config using the fake Ollama and returns the directory to use as
+/// `XDG_CONFIG_HOME`.
+fn config_home(ollama: SocketAddr) -> PathBuf {
+    let home = std::env::temp_dir().join(format!("notedmd-serve-test-{}", std::process::id()));
+    let config_dir = home.join("notedmd");
+    std::fs::create_dir_all(&config_dir).unwrap();
+    std::fs::write(
+        config_dir.join("config.toml"),
+        format!(
+            "active_provider = \"ollama\"\n\n[ollama]\nurl = \"http://{}\"\nmodel = \"test\"\n",
+            ollama
+        ),
+    )
+    .unwrap();
+    home
+}
+
+fn start_server(config_home: &Path, port: u16) -> Server {
+    let server = Server(
+        Command::new(env!("CARGO_BIN_EXE_notedmd"))
+            .args(["serve", "--port", &port.to_string(), "--token", TOKEN])
+            .args(["--prompt", "Transcribe the test page"])
+            .env("XDG_CONFIG_HOME", config_home)
+            .env("XDG_CACHE_HOME", config_home.join("cache"))
+            .stdout(Stdio::null())
+            .spawn()
+            .unwrap(),
+    );
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while TcpStream::connect(("127.0.0.1", port)).is_err() {
+        assert!(Instant::now() < deadline, "the server didn't start");
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    server
+}
+
+fn png() -> Vec<u8> {
+    let mut bytes = Vec::new();
+    image::RgbImage::new(4, 4)
+        .write_to(&mut Cursor::new(&mut bytes), image::ImageFormat::Png)
+        .unwrap();
+    bytes
+}
+
+#[tokio::test]
+async fn converts_posted_files() {
+    let (ollama, requests) = fake_ollama().await;
+    let config_home = config_home(ollama);
+    let port = free_port();
+    let _server = start_server(&config_home, port);
+    let url = format!("http://127.0.0.1:{}/convert", port);
+    let client = reqwest::Client::new();
+
+    let response = client
+        .post(&url)
+        .header("Content-Type", "image/png")
+        .body(png())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let response = client
+        .post(&url)
+        .bearer_auth("wrong-token")
+        .header("Content-Type", "image/png")
+        .body(png())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let response = client
+        .post(&url)
+        .bearer_auth(TOKEN)
+        .header("Content-Type", "text/plain")
+        .body("notes")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    assert!(requests.lock().unwrap().is_empty());
+
+    let response = client
+        .post(&url)
+        .bearer_auth(TOKEN)
+        .header("Content-Type", "image/png")
+        .body(png())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()["content-type"],
+        "text/markdown; charset=utf-8"
+    );
+    assert_eq!(response.text().await.unwrap(), "# Hi\n\nFrom the notes.\n");
+
+    let requests = requests.lock().unwrap();
+    assert_eq!(requests.len(), 1);
+    assert!(requests[0].contains("Transcribe the test page"));
+
+    let _ = std::fs::remove_dir_all(&config_home);
+}